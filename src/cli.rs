@@ -1,8 +1,45 @@
 //! Contains useful functions pertaining to setting-up and maintaining CLI arguments.
 
+/// The names of the initial distributions of entities.
+pub const DISTRIBUTIONS: &[&str] = &[
+    "big-bang",
+    "random"
+];
+
+/// Returns whether the specified delta time is valid, or the reason it is not.
+pub fn validate_delta_time(delta_time: f64) -> Result<(), String> {
+    match delta_time {
+        val if val.is_finite() && val > 0.0 => Ok(()),
+        val if val < 0.0 => Err(String::from("Specified delta time is negative, but time-reversed integration is not supported.")),
+        val if val.is_finite() => Err(String::from("Specified delta time is zero, which would freeze the simulation.")),
+        _ => Err(String::from("Specified delta time is not a finite number."))
+    }
+}
+
 /// Parses the command-line arguments passed to the program, returning a
 /// collection of matches.
 pub fn get_arguments<'a>() -> clap::ArgMatches<'a> {
+    get_arguments_from(std::env::args_os())
+}
+
+/// Parses the specified command-line arguments (the first of which is the name
+/// of the program), returning a collection of matches.
+///
+/// Like `get_arguments`, this exits the program if the arguments are invalid.
+pub fn get_arguments_from<'a, I, T>(arguments: I) -> clap::ArgMatches<'a>
+where I: IntoIterator<Item = T>,
+      T: Into<std::ffi::OsString> + Clone
+{
+    try_get_arguments_from(arguments).unwrap_or_else(|e| e.exit())
+}
+
+/// Parses the specified command-line arguments (the first of which is the name
+/// of the program), returning a collection of matches or the error describing
+/// why they are invalid.
+pub fn try_get_arguments_from<'a, I, T>(arguments: I) -> clap::Result<clap::ArgMatches<'a>>
+where I: IntoIterator<Item = T>,
+      T: Into<std::ffi::OsString> + Clone
+{
     use clap:: {
         crate_authors,
         crate_description,
@@ -15,6 +52,120 @@ pub fn get_arguments<'a>() -> clap::ArgMatches<'a> {
         .help_message("Displays help and usage information.")
         .version(crate_version!())
         .version_message("Displays version information.")
+        .arg(clap::Arg::with_name("background_density")
+             .default_value("0.0")
+             .env("GRAV_BACKGROUND_DENSITY")
+             .help("Specifies the density of the smooth background, enabling a mean-field gravitational force when non-zero.")
+             .long("--background-density")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val >= 0.0 => Ok(()),
+                     _ => Err(String::from("Specified background density is not a non-negative number."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("barnes_hut_theta")
+             .env("GRAV_BARNES_HUT_THETA")
+             .help("Approximates gravity with a Barnes-Hut octree using the specified opening angle.")
+             .long("--barnes-hut-theta")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val > 0.0 => Ok(()),
+                     _ => Err(String::from("Specified Barnes-Hut opening angle is not a positive floating-point value."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("charge_distribution")
+             .default_value("uniform")
+             .env("GRAV_CHARGE_DISTRIBUTION")
+             .help("Specifies how charges are assigned to entities in the initial distribution.")
+             .long("--charge-distribution")
+             .possible_values(&[
+                 "cyclic",
+                 "uniform"
+             ])
+             .value_name("DIST")
+        )
+        .arg(clap::Arg::with_name("collision_substeps")
+             .default_value("1")
+             .env("GRAV_COLLISION_SUBSTEPS")
+             .help("Specifies the number of substeps used to resolve dynamics and collisions within each step.")
+             .long("--collision-substeps")
+             .short("-c")
+             .validator( | val_str | {
+                 match val_str.parse::<u32>() {
+                     Ok(val) if val > 0 => Ok(()),
+                     _ => Err(String::from("Specified substep count is not a positive integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("config")
+             .env("GRAV_CONFIG")
+             .help("Specifies a YAML configuration file describing the simulation. Arguments passed on the command line take precedence over it.")
+             .long("--config")
+             .value_name("FILE")
+        )
+        .arg(clap::Arg::with_name("core_radius")
+             .default_value("50.0")
+             .env("GRAV_CORE_RADIUS")
+             .help("Specifies the radius within which entities are placed by the \"big-bang\" distribution.")
+             .long("--core-radius")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val > 0.0 => Ok(()),
+                     _ => Err(String::from("Specified core radius is not a positive number."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("correlation")
+             .env("GRAV_CORRELATION")
+             .help("Specifies a file to write periodic estimates of the two-point correlation function to.")
+             .long("--correlation")
+             .value_name("FILE")
+        )
+        .arg(clap::Arg::with_name("correlation_bins")
+             .default_value("20")
+             .env("GRAV_CORRELATION_BINS")
+             .help("Specifies the number of separation bins in each two-point correlation estimate.")
+             .long("--correlation-bins")
+             .validator( | val_str | {
+                 match val_str.parse::<usize>() {
+                     Ok(val) if val > 0 => Ok(()),
+                     _ => Err(String::from("Specified two-point correlation bin count is not a positive integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("correlation_interval")
+             .default_value("10")
+             .env("GRAV_CORRELATION_INTERVAL")
+             .help("Specifies the number of steps between two-point correlation entries.")
+             .long("--correlation-interval")
+             .validator( | val_str | {
+                 match val_str.parse::<u128>() {
+                     Ok(val) if val > 0 => Ok(()),
+                     _ => Err(String::from("Specified two-point correlation interval is not a positive integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("correlation_maximum_separation")
+             .default_value("10.0")
+             .env("GRAV_CORRELATION_MAXIMUM_SEPARATION")
+             .help("Specifies the largest pair separation included in the two-point correlation function.")
+             .long("--correlation-maximum-separation")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val.is_finite() && val > 0.0 => Ok(()),
+                     _ => Err(String::from("Specified maximum separation is not a positive floating-point value."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
         .arg(clap::Arg::with_name("data_dir")
              .default_value("data")
              .env("GRAV_DATA_DIR")
@@ -23,6 +174,211 @@ pub fn get_arguments<'a>() -> clap::ArgMatches<'a> {
              .short("-d")
              .value_name("DIR")
         )
+        .arg(clap::Arg::with_name("delta_time")
+             .default_value("0.5")
+             .env("GRAV_DELTA_TIME")
+             .help("Specifies the amount of simulated time between steps.")
+             .long("--delta-time")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) => validate_delta_time(val),
+                     _ => Err(String::from("Specified delta time is not a finite number."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("distribution")
+             .default_value("random")
+             .env("GRAV_DISTRIBUTION")
+             .help("Specifies the initial distribution of entities.")
+             .long("--distribution")
+             .possible_values(DISTRIBUTIONS)
+             .value_name("DIST")
+        )
+        .arg(clap::Arg::with_name("drag_coefficient")
+             .default_value("0.0")
+             .env("GRAV_DRAG_COEFFICIENT")
+             .help("Specifies the drag coefficient applied to the cross-sectional area of each entity (disabled if zero).")
+             .long("--drag-coefficient")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val >= 0.0 && val.is_finite() => Ok(()),
+                     _ => Err(String::from("Specified drag coefficient is not a non-negative number."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("duration")
+             .env("GRAV_DURATION")
+             .help("Specifies the total amount of simulated time to run for.")
+             .long("--duration")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val > 0.0 => Ok(()),
+                     _ => Err(String::from("Specified duration is not a positive number."))
+                 }
+             })
+             .value_name("TIME")
+        )
+        .arg(clap::Arg::with_name("electrostatic")
+             .default_value("0.5")
+             .env("GRAV_ELECTROSTATIC")
+             .help("Specifies the electrostatic constant.")
+             .long("--electrostatic")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val.is_finite() => Ok(()),
+                     _ => Err(String::from("Specified electrostatic constant is not a finite number."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("electrostatic_softening")
+             .default_value("0.0")
+             .env("GRAV_ELECTROSTATIC_SOFTENING")
+             .help("Specifies the softening length applied to electrostatic interactions.")
+             .long("--electrostatic-softening")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val >= 0.0 => Ok(()),
+                     _ => Err(String::from("Specified electrostatic softening length is not a non-negative number."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("escape_distance")
+             .env("GRAV_ESCAPE_DISTANCE")
+             .help("Enables detection of escaped (unbound) entities beyond the specified distance from the center of mass.")
+             .long("--escape-distance")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val > 0.0 => Ok(()),
+                     _ => Err(String::from("Specified escape distance is not a positive floating-point value."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("eta_window")
+             .default_value("50")
+             .env("GRAV_ETA_WINDOW")
+             .help("Specifies the number of recent steps averaged when estimating the remaining time in the progress bar (0 uses the default estimate).")
+             .long("--eta-window")
+             .validator( | val_str | {
+                 match val_str.parse::<usize>() {
+                     Ok(_) => Ok(()),
+                     _ => Err(String::from("Specified ETA window is not a non-negative integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("force_symmetry")
+             .default_value("symmetric")
+             .env("GRAV_FORCE_SYMMETRY")
+             .help("Specifies whether pairwise forces are applied symmetrically (per Newton's third law) or computed independently for each entity.")
+             .long("--force-symmetry")
+             .possible_values(&[
+                 "independent",
+                 "symmetric"
+             ])
+             .value_name("MODE")
+        )
+        .arg(clap::Arg::with_name("frame_resolution")
+             .default_value("512")
+             .env("GRAV_FRAME_RESOLUTION")
+             .help("Specifies the width and height of rendered frames, in pixels.")
+             .long("--frame-resolution")
+             .validator( | val_str | {
+                 match val_str.parse::<u32>() {
+                     Ok(val) if val > 0 => Ok(()),
+                     _ => Err(String::from("Specified frame resolution is not a positive integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("frames")
+             .env("GRAV_FRAMES")
+             .help("Specifies a directory to render each step to as a PNG image.")
+             .long("--frames")
+             .value_name("DIR")
+        )
+        .arg(clap::Arg::with_name("gravitational_softening")
+             .default_value("0.0")
+             .env("GRAV_GRAVITATIONAL_SOFTENING")
+             .help("Specifies the (Plummer) softening length applied to gravitational interactions.")
+             .long("--gravitational-softening")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val >= 0.0 => Ok(()),
+                     _ => Err(String::from("Specified gravitational softening length is not a non-negative number."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("gravity")
+             .default_value("1.0")
+             .env("GRAV_GRAVITY")
+             .help("Specifies the universal gravitational constant, or \"si\" to use its real value with masses, positions, velocities and times in SI units (which are normalized internally).")
+             .long("--gravity")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val.is_finite() => Ok(()),
+                     _ if val_str == "si" => Ok(()),
+                     _ => Err(String::from("Specified gravitational constant is not a finite number or \"si\"."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("henon_units")
+             .help("Rescales the initial distribution to Hénon units (unit gravitational constant and total mass, and a total energy of -1/4).")
+             .long("--henon-units")
+        )
+        .arg(clap::Arg::with_name("hubble_factor")
+             .default_value("0.1")
+             .env("GRAV_HUBBLE_FACTOR")
+             .help("Specifies the ratio of velocity to position of entities in the \"big-bang\" distribution.")
+             .long("--hubble-factor")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(_) => Ok(()),
+                     _ => Err(String::from("Specified hubble factor is not a number."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("initial_conditions")
+             .env("GRAV_INITIAL_CONDITIONS")
+             .help("Specifies a binary file of little-endian (x, y, z, vx, vy, vz, mass) records to use as the initial state of the simulation.")
+             .long("--initial-conditions")
+             .value_name("FILE")
+        )
+        .arg(clap::Arg::with_name("initial_conditions_format")
+             .default_value("f64")
+             .env("GRAV_INITIAL_CONDITIONS_FORMAT")
+             .help("Specifies the floating-point width of the values in the initial conditions file.")
+             .long("--initial-conditions-format")
+             .possible_values(&[
+                 "f32",
+                 "f64"
+             ])
+             .value_name("FORMAT")
+        )
+        .arg(clap::Arg::with_name("integrator")
+             .default_value("euler")
+             .env("GRAV_INTEGRATOR")
+             .help("Specifies the method used to integrate the motion of entities.")
+             .long("--integrator")
+             .possible_values(&[
+                 "euler",
+                 "velocity-verlet"
+             ])
+             .value_name("METHOD")
+        )
+        .arg(clap::Arg::with_name("load")
+             .env("GRAV_LOAD")
+             .help("Plays back a previously written output file, re-emitting each step to standard output, instead of running a simulation.")
+             .long("--load")
+             .value_name("FILE")
+        )
         .arg(clap::Arg::with_name("log_file")
              .default_value("grav.log")
              .env("GRAV_LOG_FILE")
@@ -59,6 +415,96 @@ pub fn get_arguments<'a>() -> clap::ArgMatches<'a> {
              .short("-m")
              .value_name("MODE")
         )
+        .arg(clap::Arg::with_name("maximum_charge")
+             .allow_hyphen_values(true)
+             .default_value("inf")
+             .env("GRAV_MAXIMUM_CHARGE")
+             .help("Specifies the maximum charge an entity may have after merging or splitting.")
+             .long("--maximum-charge")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if !val.is_nan() => Ok(()),
+                     _ => Err(String::from("Specified maximum charge is not a number."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("merge_preserves_identity")
+             .help("Merges collided entities into the heaviest of them (keeping its particle ID) instead of a new entity.")
+             .long("--merge-preserves-identity")
+        )
+        .arg(clap::Arg::with_name("minimum_charge")
+             .allow_hyphen_values(true)
+             .default_value("-inf")
+             .env("GRAV_MINIMUM_CHARGE")
+             .help("Specifies the minimum charge an entity may have after merging or splitting.")
+             .long("--minimum-charge")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if !val.is_nan() => Ok(()),
+                     _ => Err(String::from("Specified minimum charge is not a number."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("mond_acceleration")
+             .env("GRAV_MOND_ACCELERATION")
+             .help("Enables MOND-modified gravity with the specified acceleration scale.")
+             .long("--mond-acceleration")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val > 0.0 => Ok(()),
+                     _ => Err(String::from("Specified MOND acceleration scale is not a positive floating-point value."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("neighbors")
+             .env("GRAV_NEIGHBORS")
+             .help("Specifies a file to write periodic histograms of nearest-neighbor distances to.")
+             .long("--neighbors")
+             .value_name("FILE")
+        )
+        .arg(clap::Arg::with_name("neighbors_bins")
+             .default_value("20")
+             .env("GRAV_NEIGHBORS_BINS")
+             .help("Specifies the number of bins in each nearest-neighbor distance histogram.")
+             .long("--neighbors-bins")
+             .validator( | val_str | {
+                 match val_str.parse::<usize>() {
+                     Ok(val) if val > 0 => Ok(()),
+                     _ => Err(String::from("Specified nearest-neighbor bin count is not a positive integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("neighbors_interval")
+             .default_value("10")
+             .env("GRAV_NEIGHBORS_INTERVAL")
+             .help("Specifies the number of steps between nearest-neighbor distance entries.")
+             .long("--neighbors-interval")
+             .validator( | val_str | {
+                 match val_str.parse::<u128>() {
+                     Ok(val) if val > 0 => Ok(()),
+                     _ => Err(String::from("Specified nearest-neighbor interval is not a positive integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("num_entities")
+             .default_value("1000")
+             .env("GRAV_NUM_ENTITIES")
+             .help("Specifies the number of entities in the initial distribution.")
+             .long("--num-entities")
+             .short("-n")
+             .validator( | val_str | {
+                 match val_str.parse::<u32>() {
+                     Ok(_) => Ok(()),
+                     _ => Err(String::from("Specified number of entities is not a non-negative integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
         .arg(clap::Arg::with_name("output")
              .default_value("output.yaml")
              .env("GRAV_OUTPUT")
@@ -67,25 +513,353 @@ pub fn get_arguments<'a>() -> clap::ArgMatches<'a> {
              .short("-o")
              .value_name("FILE")
         )
+        .arg(clap::Arg::with_name("output_from")
+             .env("GRAV_OUTPUT_FROM")
+             .help("Specifies the first step to write to the output file.")
+             .long("--output-from")
+             .validator( | val_str | {
+                 match val_str.parse::<u128>() {
+                     Ok(_) => Ok(()),
+                     _ => Err(String::from("Specified output start step is not a non-negative integer value."))
+                 }
+             })
+             .value_name("STEP")
+        )
+        .arg(clap::Arg::with_name("output_index")
+             .help("Writes an index of the step and byte offset of each output entry to the output file path with an \".index\" suffix.")
+             .long("--output-index")
+        )
+        .arg(clap::Arg::with_name("output_lineage")
+             .help("Includes the lifetime and parent particle IDs of each entity in the output.")
+             .long("--output-lineage")
+        )
+        .arg(clap::Arg::with_name("output_reference")
+             .env("GRAV_OUTPUT_REFERENCE")
+             .help("Specifies the particle ID of an entity to write output coordinates relative to.")
+             .long("--output-reference")
+             .short("-r")
+             .validator( | val_str | {
+                 match val_str.parse::<u64>() {
+                     Ok(_) => Ok(()),
+                     _ => Err(String::from("Specified particle ID is not a non-negative integer value."))
+                 }
+             })
+             .value_name("ID")
+        )
+        .arg(clap::Arg::with_name("output_tracers_only")
+             .help("Only writes entities marked as tracers to the output.")
+             .long("--output-tracers-only")
+        )
+        .arg(clap::Arg::with_name("output_to")
+             .env("GRAV_OUTPUT_TO")
+             .help("Specifies the last step to write to the output file.")
+             .long("--output-to")
+             .validator( | val_str | {
+                 match val_str.parse::<u128>() {
+                     Ok(_) => Ok(()),
+                     _ => Err(String::from("Specified output end step is not a non-negative integer value."))
+                 }
+             })
+             .value_name("STEP")
+        )
+        .arg(clap::Arg::with_name("probes")
+             .default_value("0")
+             .env("GRAV_PROBES")
+             .help("Specifies the number of probes (entities which feel but do not exert forces) to add to the simulation.")
+             .long("--probes")
+             .validator( | val_str | {
+                 match val_str.parse::<usize>() {
+                     Ok(_) => Ok(()),
+                     _ => Err(String::from("Specified probe count is not a non-negative integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("profile")
+             .env("GRAV_PROFILE")
+             .help("Specifies a file to write periodic radial density profiles about the center of mass to.")
+             .long("--profile")
+             .value_name("FILE")
+        )
+        .arg(clap::Arg::with_name("profile_bins")
+             .default_value("20")
+             .env("GRAV_PROFILE_BINS")
+             .help("Specifies the number of radial shells in each density profile.")
+             .long("--profile-bins")
+             .validator( | val_str | {
+                 match val_str.parse::<usize>() {
+                     Ok(val) if val > 0 => Ok(()),
+                     _ => Err(String::from("Specified profile bin count is not a positive integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("profile_interval")
+             .default_value("10")
+             .env("GRAV_PROFILE_INTERVAL")
+             .help("Specifies the number of steps between density profile entries.")
+             .long("--profile-interval")
+             .validator( | val_str | {
+                 match val_str.parse::<u128>() {
+                     Ok(val) if val > 0 => Ok(()),
+                     _ => Err(String::from("Specified profile interval is not a positive integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("progress_interval")
+             .default_value("10")
+             .env("GRAV_PROGRESS_INTERVAL")
+             .help("Specifies the number of steps between updates of the entity count and energy in the progress bar.")
+             .long("--progress-interval")
+             .validator( | val_str | {
+                 match val_str.parse::<u128>() {
+                     Ok(val) if val > 0 => Ok(()),
+                     _ => Err(String::from("Specified progress interval is not a positive integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("remove_escaped")
+             .help("Removes escaped entities from the simulation instead of only marking them.")
+             .long("--remove-escaped")
+             .requires("escape_distance")
+        )
+        .arg(clap::Arg::with_name("resume_from_output")
+             .env("GRAV_RESUME_FROM_OUTPUT")
+             .help("Specifies an output file whose last entry is used as the initial state of the simulation.")
+             .long("--resume-from-output")
+             .value_name("FILE")
+        )
+        .arg(clap::Arg::with_name("scenario")
+             .env("GRAV_SCENARIO")
+             .help("Specifies a built-in physical scenario to simulate instead of the initial distribution.")
+             .long("--scenario")
+             .possible_values(&[
+                 "plasma-oscillation",
+                 "tidally-locked"
+             ])
+             .value_name("SCENARIO")
+        )
+        .arg(clap::Arg::with_name("seed")
+             .env("GRAV_SEED")
+             .help("Seeds the random number generator, so that the simulation can be reproduced.")
+             .long("--seed")
+             .validator( | val_str | {
+                 match val_str.parse::<u64>() {
+                     Ok(_) => Ok(()),
+                     _ => Err(String::from("Specified seed is not a non-negative integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("single_precision_forces")
+             .help("Computes gravitational and electrostatic forces in single precision.")
+             .long("--single-precision-forces")
+        )
+        .arg(clap::Arg::with_name("species")
+             .env("GRAV_SPECIES")
+             .help("Populates the world with the specified number of entities of each species (dark, gas, or star) instead of the initial distribution.")
+             .long("--species")
+             .multiple(true)
+             .validator( | val_str | {
+                 let registry = crate::ecs::resources::SpeciesRegistry::default();
+                 let mut parts = val_str.splitn(2, ':');
+                 match (parts.next(), parts.next().map(|c| c.parse::<u32>())) {
+                     (Some(name), Some(Ok(_))) if registry.0.contains_key(name) => Ok(()),
+                     _ => Err(String::from("Specified species is not of the form NAME:COUNT with a known species name."))
+                 }
+             })
+             .value_name("NAME:COUNT")
+        )
         .arg(clap::Arg::with_name("steps")
-             .default_value("1000")
              .env("GRAV_STEPS")
-             .help("Specifies the number of steps to simulate.")
+             .help("Specifies the number of steps to simulate (1000 if no duration is specified).")
              .long("--steps")
              .short("-s")
              .validator( | val_str | {
                  match val_str.parse::<u128>() {
                      Ok(val) if val > 0 => Ok(()),
-                     _ => Err(String::from("Specified port is not a positive integer value."))
+                     _ => Err(String::from("Specified step count is not a positive integer value."))
                  }
              })
              .value_name("INT")
         )
+        .arg(clap::Arg::with_name("stirring_amplitude")
+             .default_value("0.0")
+             .env("GRAV_STIRRING_AMPLITUDE")
+             .help("Specifies the typical magnitude of each component of the random stirring force (disabled if zero).")
+             .long("--stirring-amplitude")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val >= 0.0 && val.is_finite() => Ok(()),
+                     _ => Err(String::from("Specified stirring amplitude is not a non-negative number."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("stirring_correlation_time")
+             .default_value("10.0")
+             .env("GRAV_STIRRING_CORRELATION_TIME")
+             .help("Specifies the time over which the random stirring force decorrelates.")
+             .long("--stirring-correlation-time")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val > 0.0 && val.is_finite() => Ok(()),
+                     _ => Err(String::from("Specified stirring correlation time is not a positive number."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("summary")
+             .env("GRAV_SUMMARY")
+             .help("Specifies a file to write periodic summaries of the shape and angular momentum of the mass distribution to.")
+             .long("--summary")
+             .value_name("FILE")
+        )
+        .arg(clap::Arg::with_name("summary_interval")
+             .default_value("10")
+             .env("GRAV_SUMMARY_INTERVAL")
+             .help("Specifies the number of steps between summary entries.")
+             .long("--summary-interval")
+             .validator( | val_str | {
+                 match val_str.parse::<u128>() {
+                     Ok(val) if val > 0 => Ok(()),
+                     _ => Err(String::from("Specified summary interval is not a positive integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("threads")
+             .env("GRAV_THREADS")
+             .help("Specifies the number of threads used to run systems (the number of cores if not specified).")
+             .long("--threads")
+             .short("-t")
+             .validator( | val_str | {
+                 match val_str.parse::<usize>() {
+                     Ok(val) if val > 0 => Ok(()),
+                     _ => Err(String::from("Specified thread count is not a positive integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("tidal_torque")
+             .default_value("0.0")
+             .env("GRAV_TIDAL_TORQUE")
+             .help("Specifies the strength of the tidal torque driving the satellite toward synchronous rotation in the tidally-locked scenario.")
+             .long("--tidal-torque")
+             .validator( | val_str | {
+                 match val_str.parse::<f64>() {
+                     Ok(val) if val >= 0.0 => Ok(()),
+                     _ => Err(String::from("Specified tidal torque strength is not a non-negative number."))
+                 }
+             })
+             .value_name("FLOAT")
+        )
+        .arg(clap::Arg::with_name("time_dilation")
+             .help("Ages entities according to their proper time, treating the maximum velocity as the speed of light.")
+             .long("--time-dilation")
+        )
+        .arg(clap::Arg::with_name("tracers")
+             .default_value("0")
+             .env("GRAV_TRACERS")
+             .help("Specifies the number of entities to mark as tracers.")
+             .long("--tracers")
+             .validator( | val_str | {
+                 match val_str.parse::<usize>() {
+                     Ok(_) => Ok(()),
+                     _ => Err(String::from("Specified tracer count is not a non-negative integer value."))
+                 }
+             })
+             .value_name("INT")
+        )
+        .arg(clap::Arg::with_name("two_dimensional")
+             .help("Confines entities to the plane z = 0, computing Barnes-Hut gravity with a quadtree.")
+             .long("--two-dimensional")
+        )
+        .arg(clap::Arg::with_name("velocity_limit_mode")
+             .default_value("hard-scale")
+             .env("GRAV_VELOCITY_LIMIT_MODE")
+             .help("Specifies how the speed of entities is kept below the maximum velocity.")
+             .long("--velocity-limit-mode")
+             .possible_values(&[
+                 "hard-scale",
+                 "soft-drag"
+             ])
+             .value_name("MODE")
+        )
         .settings(
             &[
                 clap::AppSettings::ColoredHelp,
                 clap::AppSettings::VersionlessSubcommands
             ]
+        )
+        .subcommand(clap::SubCommand::with_name("ensemble")
+            .about("Simulates several copies of the same initial conditions, each with its own seed and a small random displacement of every entity.")
+            .arg(clap::Arg::with_name("jitter")
+                 .default_value("0.0")
+                 .help("Specifies the standard deviation of the random displacement of each entity.")
+                 .long("--jitter")
+                 .validator( | val_str | {
+                     match val_str.parse::<f64>() {
+                         Ok(val) if val.is_finite() && val >= 0.0 => Ok(()),
+                         _ => Err(String::from("Specified jitter is not a non-negative floating-point value."))
+                     }
+                 })
+                 .value_name("FLOAT")
+            )
+            .arg(clap::Arg::with_name("members")
+                 .help("Specifies the number of members in the ensemble.")
+                 .long("--members")
+                 .required(true)
+                 .validator( | val_str | {
+                     match val_str.parse::<u64>() {
+                         Ok(val) if val > 0 => Ok(()),
+                         _ => Err(String::from("Specified member count is not a positive integer value."))
+                     }
+                 })
+                 .value_name("INT")
+            )
+            .arg(clap::Arg::with_name("parallel")
+                 .help("Simulates the members in parallel.")
+                 .long("--parallel")
+            )
+        )
+        .subcommand(clap::SubCommand::with_name("sweep")
+            .about("Simulates the same initial conditions for every combination of the gravitational constant and softening in the specified ranges.")
+            .arg(clap::Arg::with_name("gravity_range")
+                 .help("Specifies the range of gravitational constants to sweep, as evenly-spaced values FROM:TO:COUNT.")
+                 .long("--gravity-range")
+                 .validator( | val_str | crate::helper::parse_range(&val_str).map(|_| ()))
+                 .value_name("RANGE")
+            )
+            .arg(clap::Arg::with_name("parallel")
+                 .help("Simulates the combinations in parallel.")
+                 .long("--parallel")
+            )
+            .arg(clap::Arg::with_name("softening_range")
+                 .help("Specifies the range of gravitational softening lengths to sweep, as evenly-spaced values FROM:TO:COUNT.")
+                 .long("--softening-range")
+                 .validator( | val_str | crate::helper::parse_range(&val_str).map(|_| ()))
+                 .value_name("RANGE")
+            )
         );
-    argument_parser.get_matches()
+    argument_parser.get_matches_from_safe(arguments)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_positive_time_steps_are_rejected() {
+        let error = try_get_arguments_from(vec!["grav", "--delta-time", "0"]).unwrap_err();
+        assert_eq!(error.kind, clap::ErrorKind::ValueValidation);
+        assert!(error.message.contains("zero"), "{}", error.message);
+        let error = try_get_arguments_from(vec!["grav", "--delta-time=-0.5"]).unwrap_err();
+        assert_eq!(error.kind, clap::ErrorKind::ValueValidation);
+        assert!(error.message.contains("negative"), "{}", error.message);
+        assert!(try_get_arguments_from(vec!["grav", "--delta-time", "0.25"]).is_ok());
+    }
 }
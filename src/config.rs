@@ -0,0 +1,165 @@
+//! Contains the definition of simulation configuration files.
+
+use crate::cli::{validate_delta_time, DISTRIBUTIONS};
+use crate::ecs::resources::{CollisionLimits, DynamicsLimits};
+use crate::math::Vector;
+
+/// Represents a single entity listed in a simulation configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntityConfig {
+    /// Whether the entity is held in place.
+    #[serde(default)]
+    pub anchored: bool,
+
+    /// The charge of the entity.
+    #[serde(default)]
+    pub charge: f64,
+
+    /// Whether collision detection is enabled for the entity.
+    #[serde(default = "EntityConfig::default_collisions_enabled")]
+    pub collisions_enabled: bool,
+
+    /// The name of the group the entity belongs to.
+    #[serde(default)]
+    pub group: Option<String>,
+
+    /// Whether the entity never splits.
+    #[serde(default)]
+    pub immortal: bool,
+
+    /// The mass of the entity.
+    pub mass: f64,
+
+    /// The initial position of the entity.
+    pub position: Vector,
+
+    /// The radius of the entity.
+    #[serde(default = "EntityConfig::default_radius")]
+    pub radius: f64,
+
+    /// The initial velocity of the entity.
+    #[serde(default)]
+    pub velocity: Vector
+}
+
+impl EntityConfig {
+    /// Returns the default value of `collisions_enabled`.
+    fn default_collisions_enabled() -> bool { true }
+
+    /// Returns the default value of `radius`.
+    fn default_radius() -> f64 { 1.0 }
+}
+
+/// Represents a simulation configuration file.
+///
+/// Every field is optional. Fields which are omitted fall back to the value of
+/// the corresponding command-line argument (or its default), and arguments
+/// which are explicitly passed on the command line take precedence over the
+/// configuration.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationConfig {
+    /// The limits involving collision detection.
+    pub collision_limits: Option<CollisionLimits>,
+
+    /// The amount of simulated time between steps.
+    pub delta_time: Option<f64>,
+
+    /// The initial distribution of entities (`random` or `big-bang`).
+    pub distribution: Option<String>,
+
+    /// The limits on the acceleration, position, and velocity of entities.
+    pub dynamics_limits: Option<DynamicsLimits>,
+
+    /// The electrostatic constant.
+    pub electrostatic_constant: Option<f64>,
+
+    /// The entities to populate the world with, in place of a distribution.
+    pub entities: Option<Vec<EntityConfig>>,
+
+    /// The universal gravitational constant.
+    pub gravitational_constant: Option<f64>,
+
+    /// The number of entities in the initial distribution.
+    pub num_entities: Option<u32>,
+
+    /// The path to the output file.
+    pub output: Option<String>,
+
+    /// The number of steps to simulate.
+    pub steps: Option<u128>
+}
+
+impl SimulationConfig {
+    /// Loads the simulation configuration from the specified YAML file.
+    ///
+    /// Values are held to the same rules as the corresponding command-line
+    /// arguments, so an invalid delta time or an unknown distribution results
+    /// in an error.
+    pub fn load(path: &str) -> std::io::Result<SimulationConfig> {
+        let file = std::fs::File::open(path)?;
+        let config: SimulationConfig = serde_yaml::from_reader(file).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        config.validate().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok(config)
+    }
+
+    /// Returns whether the configured values are valid, or the reason the
+    /// first invalid one is not.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(delta_time) = self.delta_time {
+            validate_delta_time(delta_time)?;
+        }
+        if let Some(distribution) = &self.distribution {
+            if !DISTRIBUTIONS.contains(&distribution.as_str()) {
+                return Err(format!("Specified distribution \"{}\" is not one of: {}.", distribution, DISTRIBUTIONS.join(", ")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the value of the specified command-line argument, unless it was
+    /// not explicitly passed and the specified configured value is present.
+    pub fn resolve<T>(args: &clap::ArgMatches, name: &str, configured: &Option<T>) -> Option<String>
+    where T: std::string::ToString
+    {
+        match (args.occurrences_of(name), configured) {
+            (0, Some(value)) => Some(value.to_string()),
+            _ => args.value_of(name).map(|value| value.to_string())
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn misspelled_fields_are_rejected_by_name() {
+        let path = std::env::temp_dir().join(format!("grav-{}-typo.yaml", std::process::id()));
+        std::fs::write(&path, "delta_time: 0.5\nnum_entites: 10\n").unwrap();
+        let result = SimulationConfig::load(&path.to_string_lossy());
+        std::fs::remove_file(&path).unwrap();
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("num_entites"), "{}", message);
+    }
+
+    #[test]
+    fn invalid_configured_values_are_rejected() {
+        let path = std::env::temp_dir().join(format!("grav-{}-invalid.yaml", std::process::id()));
+        for (contents, expected) in &[
+            ("delta_time: 0\n", "zero"),
+            ("delta_time: -1\n", "negative"),
+            ("distribution: big_bang\n", "big_bang")
+        ] {
+            std::fs::write(&path, contents).unwrap();
+            let message = SimulationConfig::load(&path.to_string_lossy()).unwrap_err().to_string();
+            assert!(message.contains(expected), "{}", message);
+        }
+        std::fs::write(&path, "delta_time: 0.25\ndistribution: big-bang\n").unwrap();
+        let result = SimulationConfig::load(&path.to_string_lossy());
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_ok());
+    }
+}
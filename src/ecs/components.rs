@@ -1,10 +1,17 @@
 //! Contains definitions for the various simulation entity components.
 
 use crate::math::*;
-use specs::{Component, Entity, VecStorage};
+use specs::{Component, Entity, NullStorage, VecStorage};
 use std::collections::HashMap;
 
 
+/// Marks an entity as "anchored", which holds it in place regardless of the
+/// forces acting on it.
+#[derive(Clone, Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Anchored;
+
+
 /// Represents the "camera" component.
 #[derive(Clone, Component, Debug)]
 #[storage(VecStorage)]
@@ -58,12 +65,85 @@ pub struct Dynamics {
 }
 
 
+/// Marks an entity as having escaped the system, meaning it is gravitationally
+/// unbound and beyond the escape distance from the center of mass.
+#[derive(Clone, Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Escaped;
+
+
+/// Represents the source of a particular force acting on an entity.
+///
+/// Sources which are other entities are keyed by the `Entity` itself, which
+/// hashes as its numeric index and generation, so keys stay compact no matter
+/// how many times entity IDs have been recycled.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ForceSource {
+    /// The mean-field force arising from the background density.
+    Background,
+
+    /// The drag force opposing the motion of the entity.
+    Drag,
+
+    /// The electrostatic force imparted by the specified entity.
+    Electrostatics(Entity),
+
+    /// The gravitational force imparted by the specified entity.
+    Gravity(Entity),
+
+    /// The net gravitational force imparted by all other entities, as
+    /// approximated by the Barnes-Hut octree.
+    GravityTree,
+
+    /// The force imparted by the interactive (cursor-controlled) point.
+    Interactive,
+
+    /// The time-correlated random force used to stir the system.
+    Stirring,
+
+    /// The tidal torque driving an entity toward synchronous rotation.
+    Tides
+}
+
+impl ForceSource {
+    /// Returns the entity which imparted this force, if any.
+    pub fn entity(&self) -> Option<Entity> {
+        match self {
+            ForceSource::Electrostatics(e) | ForceSource::Gravity(e) => Some(*e),
+            _ => None
+        }
+    }
+}
+
+
 /// Represents the "forces" component. This component keeps track of the various
 /// forces acting on an object. The key of this `HashMap` corresponds to the
-/// name of the force + the entity which imparted that force on this one.
+/// kind of force + the entity which imparted that force on this one.
 #[derive(Clone, Component, Debug, Default)]
 #[storage(VecStorage)]
-pub struct Forces(pub HashMap<String, Vector>);
+pub struct Forces(pub HashMap<ForceSource, Vector>);
+
+
+/// Represents the name of the group an entity belongs to, as assigned in the
+/// simulation configuration.
+///
+/// The group is only a label: it is carried through merges, but no system
+/// treats entities differently based on it.
+#[derive(Clone, Component, Debug)]
+#[storage(VecStorage)]
+pub struct Group(pub String);
+
+
+/// Marks an entity as "immortal", which prevents it from ever splitting.
+#[derive(Clone, Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Immortal;
+
+
+/// Represents the step at which an entity was last the product of a merge.
+#[derive(Clone, Component, Debug)]
+#[storage(VecStorage)]
+pub struct LastMerge(pub u128);
 
 
 /// Represents the "lifetime" of an entity, which is the number of steps this
@@ -101,6 +181,29 @@ pub struct Orientation {
 }
 
 
+/// Represents the particle IDs of the particles an entity was created from,
+/// either by merging or by splitting.
+#[derive(Clone, Component, Debug, Default)]
+#[storage(VecStorage)]
+pub struct Parents(pub Vec<u64>);
+
+
+/// Represents the unique identifier of a particle.
+///
+/// Unlike specs entity IDs, particle IDs are never recycled, so every particle
+/// ever created over the course of a simulation has a distinct ID.
+#[derive(Clone, Component, Debug)]
+#[storage(VecStorage)]
+pub struct ParticleId(pub u64);
+
+
+/// Represents the (possibly fractional) number of steps experienced by an
+/// entity in its own frame of reference, used when time dilation is enabled.
+#[derive(Clone, Component, Debug, Default)]
+#[storage(VecStorage)]
+pub struct ProperTime(pub f64);
+
+
 /// Represents the "physicality" component. All objects with physicality have a
 /// bounding/size definition and may or may not be subject to collision detection.
 #[derive(Clone, Component, Debug)]
@@ -117,3 +220,39 @@ pub struct Physicality {
 impl std::default::Default for Physicality {
     fn default() -> Self { Physicality { shape: Shape::Point, collisions_enabled: true } }
 }
+
+
+/// Represents the acceleration of an entity during the previous step, as used by
+/// the velocity Verlet integrator.
+#[derive(Clone, Component, Debug, Default)]
+#[storage(VecStorage)]
+pub struct PreviousAcceleration(pub Vector);
+
+
+/// Marks an entity as a "probe", which feels gravitational and electrostatic
+/// forces but does not exert them on other entities.
+#[derive(Clone, Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Probe;
+
+
+/// Represents the current value of the time-correlated random stirring force
+/// acting on an entity.
+#[derive(Clone, Component, Debug, Default)]
+#[storage(VecStorage)]
+pub struct StirringForce(pub Vector);
+
+
+/// Represents the "torques" component. Like `Forces`, this keeps track of the
+/// various torques acting on an object, keyed by their source.
+#[derive(Clone, Component, Debug, Default)]
+#[storage(VecStorage)]
+pub struct Torques(pub HashMap<ForceSource, Vector>);
+
+
+/// Marks an entity as a "tracer", which may be singled-out in the output.
+///
+/// Entities created from a tracer (by merging or splitting) are also tracers.
+#[derive(Clone, Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Tracer;
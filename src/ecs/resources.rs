@@ -2,8 +2,133 @@
 //!
 //! Resources are common sets of data which is shared between systems.
 
-/// Represents the various limits involving collision detection.
+use crate::math::{Shape, Vector};
+
+/// Represents how the gravitational acceleration of an entity is modified
+/// before being combined with its other accelerations.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccelerationModifier {
+    /// The gravitational acceleration is used as-is.
+    Newtonian,
+
+    /// The gravitational acceleration is modified according to MOND with the
+    /// specified acceleration scale, using the standard interpolating function
+    /// `mu(x) = x / sqrt(1 + x^2)`.
+    Mond {
+        /// The acceleration scale below which gravity deviates from Newtonian.
+        a0: f64
+    }
+}
+
+impl AccelerationModifier {
+    /// Returns the modified acceleration corresponding to the specified
+    /// Newtonian gravitational acceleration.
+    ///
+    /// For MOND, this solves `mu(|a| / a0) * a = g_N` for `a`, which reduces to
+    /// `g_N` when `|g_N| >> a0` and to `sqrt(|g_N| * a0)` (in the direction of
+    /// `g_N`) when `|g_N| << a0`.
+    pub fn apply(&self, newtonian: Vector) -> Vector {
+        match self {
+            AccelerationModifier::Newtonian => newtonian,
+            AccelerationModifier::Mond { a0 } => {
+                let g = newtonian.magnitude();
+                if g == 0.0 {
+                    return newtonian;
+                }
+                let y = g / a0;
+                newtonian * (0.5 + (0.25 + 1.0 / (y * y)).sqrt()).sqrt()
+            }
+        }
+    }
+}
+
+/// Implements `std::default::Default` for `AccelerationModifier`.
+impl std::default::Default for AccelerationModifier {
+    fn default() -> Self { AccelerationModifier::Newtonian }
+}
+
+
+/// Represents the density of the smooth background that entities are embedded
+/// in, which gives rise to a mean-field gravitational force towards regions of
+/// overdensity.
+///
+/// A density of zero disables the mean-field force.
+#[derive(Clone, Debug, Default)]
+pub struct BackgroundDensity(pub f64);
+
+
+/// Represents how charges are assigned to entities in the initial distribution.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChargeDistribution {
+    /// Charges cycle through `0`, `-1` and `1` in order of entity index.
+    Cyclic,
+
+    /// Each entity is independently given a charge of `-1`, `0` or `1` with
+    /// equal probability, so that charge is uncorrelated with entity index (and
+    /// therefore with initial position).
+    Uniform
+}
+
+impl ChargeDistribution {
+    /// Returns the charge of the entity with the specified index, drawing any
+    /// random numbers from the specified generator.
+    pub fn sample<R: rand::Rng>(&self, index: u32, rng: &mut R) -> f64 {
+        match self {
+            ChargeDistribution::Cyclic => match index % 3 {
+                0 => 0.0,
+                1 => -1.0,
+                _ => 1.0
+            },
+            ChargeDistribution::Uniform => f64::from(rng.gen_range(-1, 2))
+        }
+    }
+}
+
+/// Implements `std::default::Default` for `ChargeDistribution`.
+impl std::default::Default for ChargeDistribution {
+    fn default() -> Self { ChargeDistribution::Uniform }
+}
+
+
+/// Represents the maximum and minimum charge an entity may have as the result
+/// of merging or splitting.
 #[derive(Clone, Debug)]
+pub struct ChargeLimits {
+    /// The maximum charge.
+    pub maximum_charge: f64,
+
+    /// The minimum charge.
+    pub minimum_charge: f64
+}
+
+impl ChargeLimits {
+    /// Clamps the specified charge to these limits.
+    pub fn clamp(&self, charge: f64) -> f64 {
+        if charge > self.maximum_charge {
+            debug!("Clamping charge {} to maximum of {}.", charge, self.maximum_charge);
+            self.maximum_charge
+        } else if charge < self.minimum_charge {
+            debug!("Clamping charge {} to minimum of {}.", charge, self.minimum_charge);
+            self.minimum_charge
+        } else {
+            charge
+        }
+    }
+}
+
+/// Implements `std::default::Default` for `ChargeLimits`.
+impl std::default::Default for ChargeLimits {
+    fn default() -> Self {
+        ChargeLimits {
+            maximum_charge: f64::INFINITY,
+            minimum_charge: f64::NEG_INFINITY
+        }
+    }
+}
+
+
+/// Represents the various limits involving collision detection.
+#[derive(Clone, Debug, Deserialize)]
 pub struct CollisionLimits {
     /// The maximum distance two entities can be from each other and still be
     /// subject to collision detection.
@@ -30,6 +155,54 @@ impl std::default::Default for CollisionLimits {
 }
 
 
+/// Represents the number of substeps the dynamics and collision handling of
+/// each step is divided into.
+///
+/// Each substep advances the simulation by `DeltaTime / CollisionSubsteps`,
+/// re-detecting and resolving collisions at the finer resolution.
+#[derive(Clone, Debug)]
+pub struct CollisionSubsteps(pub u32);
+
+/// Implements `std::default::Default` for `CollisionSubsteps`.
+impl std::default::Default for CollisionSubsteps {
+    fn default() -> Self { CollisionSubsteps(1) }
+}
+
+
+/// Represents the settings used when writing the two-point correlation file.
+#[derive(Clone, Debug)]
+pub struct CorrelationSettings {
+    /// The number of separation bins in each entry.
+    pub bins: usize,
+
+    /// The path to the two-point correlation file.
+    pub file: String,
+
+    /// The number of steps between correlation entries.
+    pub interval: u128,
+
+    /// The largest pair separation considered.
+    pub maximum_separation: f64
+}
+
+/// Implements `std::default::Default` for `CorrelationSettings`.
+impl std::default::Default for CorrelationSettings {
+    fn default() -> Self {
+        CorrelationSettings {
+            bins: 20,
+            file: String::from("correlation.yaml"),
+            interval: 10,
+            maximum_separation: 10.0
+        }
+    }
+}
+
+
+/// Represents the (one-based) index of the step currently being simulated.
+#[derive(Clone, Debug, Default)]
+pub struct CurrentStep(pub u128);
+
+
 /// Represents the amount of time between iterations.
 #[derive(Clone, Debug)]
 pub struct DeltaTime(pub f64);
@@ -39,9 +212,43 @@ impl std::default::Default for DeltaTime {
     fn default() -> Self { DeltaTime(1.0) }
 }
 
+
+/// Represents the number of spatial dimensions in which entities move.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dimensionality {
+    /// Entities move in the plane `z = 0`, and the z components of their
+    /// positions are ignored when computing gravity.
+    Two,
+
+    /// Entities move freely in three dimensions.
+    Three
+}
+
+impl Dimensionality {
+    /// Projects the specified vector onto the space entities move in.
+    pub fn project(&self, v: Vector) -> Vector {
+        match self {
+            Dimensionality::Two => Vector(v.0, v.1, 0.0),
+            Dimensionality::Three => v
+        }
+    }
+}
+
+/// Implements `std::default::Default` for `Dimensionality`.
+impl std::default::Default for Dimensionality {
+    fn default() -> Self { Dimensionality::Three }
+}
+
+
+/// Represents the drag coefficient applied to the cross-sectional area of each
+/// entity (disabled if zero).
+#[derive(Clone, Debug, Default)]
+pub struct DragCoefficient(pub f64);
+
+
 /// Represents the maximum and minimum magnitudes for acceleration, position,
 /// and velocity.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct DynamicsLimits {
     /// The maximum acceleration magnitude.
     pub maximum_acceleration: f64,
@@ -66,9 +273,9 @@ pub struct DynamicsLimits {
 impl std::default::Default for DynamicsLimits {
     fn default() -> Self {
         DynamicsLimits {
-            maximum_acceleration: std::f64::INFINITY,
-            maximum_position: std::f64::INFINITY,
-            maximum_velocity: std::f64::INFINITY,
+            maximum_acceleration: f64::INFINITY,
+            maximum_position: f64::INFINITY,
+            maximum_velocity: f64::INFINITY,
             minimum_acceleration: 0.0,
             minimum_position: 0.0,
             minimum_velocity: 0.0
@@ -77,6 +284,28 @@ impl std::default::Default for DynamicsLimits {
 }
 
 
+/// Represents the settings used when detecting escaped entities.
+#[derive(Clone, Debug)]
+pub struct EscapeSettings {
+    /// The minimum distance from the center of mass at which an unbound entity
+    /// is considered to have escaped.
+    pub distance: f64,
+
+    /// Whether escaped entities are removed from the simulation.
+    pub remove: bool
+}
+
+/// Implements `std::default::Default` for `EscapeSettings`.
+impl std::default::Default for EscapeSettings {
+    fn default() -> Self {
+        EscapeSettings {
+            distance: 50.0,
+            remove: false
+        }
+    }
+}
+
+
 /// Represents the electrostatic constant.
 #[derive(Clone, Debug)]
 pub struct ElectrostaticConstant(pub f64);
@@ -87,6 +316,113 @@ impl std::default::Default for ElectrostaticConstant {
 }
 
 
+/// Represents the softening length applied to electrostatic interactions.
+#[derive(Clone, Debug, Default)]
+pub struct ElectrostaticSoftening(pub f64);
+
+
+/// Represents the floating-point precision used to compute pairwise
+/// inverse-square forces.
+///
+/// Single precision incurs a relative error of around `1e-7` in each force
+/// (versus `1e-16`), and overflows to infinity when the strength of an
+/// interaction exceeds `f32::MAX` (about `3.4e38`). Forces are stored in double
+/// precision either way.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ForcePrecision {
+    /// Forces are computed in `f64`.
+    Double,
+
+    /// Forces are computed in `f32`.
+    Single
+}
+
+impl ForcePrecision {
+    /// Returns the inverse-square force of the specified strength along the
+    /// specified separation, or `None` if the separation is zero.
+    ///
+    /// A positive strength produces a force along the separation (attraction,
+    /// when the separation points from the entity to the source). A non-zero
+    /// softening length `e` replaces the squared distance `r^2` with
+    /// `r^2 + e^2` (Plummer softening), which keeps the force finite as the
+    /// separation approaches zero.
+    pub fn inverse_square(&self, separation: Vector, strength: f64, softening: f64) -> Option<Vector> {
+        match self {
+            ForcePrecision::Double => {
+                let dmag_squared = separation.magnitude_squared();
+                if dmag_squared == 0.0 {
+                    return None;
+                }
+                let softened = dmag_squared + (softening * softening);
+                Some(separation * (strength / (softened * softened.sqrt())))
+            },
+            ForcePrecision::Single => {
+                let (x, y, z) = (separation.0 as f32, separation.1 as f32, separation.2 as f32);
+                let dmag_squared = (x * x) + (y * y) + (z * z);
+                if dmag_squared == 0.0 {
+                    return None;
+                }
+                let softened = dmag_squared + (softening as f32) * (softening as f32);
+                let scale = (strength as f32) / (softened * softened.sqrt());
+                Some(Vector(f64::from(x * scale), f64::from(y * scale), f64::from(z * scale)))
+            }
+        }
+    }
+}
+
+/// Implements `std::default::Default` for `ForcePrecision`.
+impl std::default::Default for ForcePrecision {
+    fn default() -> Self { ForcePrecision::Double }
+}
+
+
+/// Represents how pairwise forces are computed by the brute-force interaction
+/// systems.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ForceSymmetry {
+    /// The force on each entity of a pair is computed independently.
+    Independent,
+
+    /// The force on each entity of a pair is computed once and applied to the
+    /// other entity with the opposite sign, per Newton's third law.
+    Symmetric
+}
+
+/// Implements `std::default::Default` for `ForceSymmetry`.
+impl std::default::Default for ForceSymmetry {
+    fn default() -> Self { ForceSymmetry::Symmetric }
+}
+
+
+/// Represents the settings used when rendering frames to image files.
+#[derive(Clone, Debug)]
+pub struct FrameSettings {
+    /// The directory to write rendered frames to.
+    pub directory: String,
+
+    /// The distance from the origin to the edges of each frame.
+    pub extent: f64,
+
+    /// The width and height of each frame, in pixels.
+    pub resolution: u32
+}
+
+/// Implements `std::default::Default` for `FrameSettings`.
+impl std::default::Default for FrameSettings {
+    fn default() -> Self {
+        FrameSettings {
+            directory: String::from("frames"),
+            extent: 100.0,
+            resolution: 512
+        }
+    }
+}
+
+
+/// The universal gravitational constant in SI units (m³ kg⁻¹ s⁻²).
+pub const SI_GRAVITATIONAL_CONSTANT: f64 = 6.674e-11;
+
+
 /// Represents the universal gravitational constant.
 #[derive(Clone, Debug)]
 pub struct GravitationalConstant(pub f64);
@@ -97,6 +433,130 @@ impl std::default::Default for GravitationalConstant {
 }
 
 
+/// Represents the softening length applied to gravitational interactions.
+#[derive(Clone, Debug, Default)]
+pub struct GravitationalSoftening(pub f64);
+
+
+/// Represents the method used to compute gravitational interactions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GravityMethod {
+    /// The force between every pair of entities is computed exactly.
+    BruteForce,
+
+    /// Distant groups of entities are approximated by their center of mass
+    /// using an octree (or a quadtree in two dimensions).
+    BarnesHut {
+        /// The ratio of the size of a group to its distance below which the
+        /// group is approximated (the opening angle).
+        theta: f64
+    }
+}
+
+/// Implements `std::default::Default` for `GravityMethod`.
+impl std::default::Default for GravityMethod {
+    fn default() -> Self { GravityMethod::BruteForce }
+}
+
+
+/// Represents the counter from which new particle IDs are allocated.
+#[derive(Clone, Debug, Default)]
+pub struct IdCounter(pub u64);
+
+impl IdCounter {
+    /// Returns the next unused particle ID, advancing the counter.
+    pub fn allocate(&mut self) -> u64 {
+        let id = self.0;
+        self.0 += 1;
+        id
+    }
+}
+
+
+/// Represents the method used to integrate the motion of entities.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Integrator {
+    /// Semi-implicit (symplectic) Euler integration.
+    Euler,
+
+    /// Velocity Verlet integration, which averages the accelerations of the
+    /// previous and current steps when updating velocities.
+    VelocityVerlet
+}
+
+/// Implements `std::default::Default` for `Integrator`.
+impl std::default::Default for Integrator {
+    fn default() -> Self { Integrator::Euler }
+}
+
+
+/// Represents an externally-controlled point (such as a cursor) which attracts
+/// or repels all entities with an inverse-square force.
+///
+/// This is intended to be updated by input handling. A strength of zero
+/// disables the force.
+#[derive(Clone, Debug, Default)]
+pub struct InteractiveForce {
+    /// The position the force is directed toward (or away from).
+    pub position: Vector,
+
+    /// The strength of the force. Positive values attract entities and negative
+    /// values repel them.
+    pub strength: f64
+}
+
+impl InteractiveForce {
+    /// Returns the force acting on an entity of the specified mass at the
+    /// specified position, or `None` if the entity is located exactly at the
+    /// position of the interactive force.
+    ///
+    /// The magnitude of the force is `strength * mass / r^2`, so every entity
+    /// at the same distance experiences the same acceleration.
+    pub fn force_on(&self, position: Vector, mass: f64) -> Option<Vector> {
+        let dvec = self.position - position;
+        dvec.try_direction().map(|direction| direction * (self.strength * mass / dvec.magnitude_squared()))
+    }
+}
+
+
+/// Represents whether the world requires maintenance, which is the case
+/// whenever entities have been created or deleted.
+#[derive(Clone, Debug, Default)]
+pub struct MaintenanceRequired(pub bool);
+
+
+/// Represents whether merged bodies adopt the identity of their heaviest input
+/// rather than being created as new entities.
+#[derive(Clone, Debug, Default)]
+pub struct MergePreservesIdentity(pub bool);
+
+
+/// Represents the settings used when writing the nearest-neighbor distance
+/// file.
+#[derive(Clone, Debug)]
+pub struct NeighborsSettings {
+    /// The number of bins in each histogram.
+    pub bins: usize,
+
+    /// The path to the nearest-neighbor distance file.
+    pub file: String,
+
+    /// The number of steps between histogram entries.
+    pub interval: u128
+}
+
+/// Implements `std::default::Default` for `NeighborsSettings`.
+impl std::default::Default for NeighborsSettings {
+    fn default() -> Self {
+        NeighborsSettings {
+            bins: 20,
+            file: String::from("neighbors.yaml"),
+            interval: 10
+        }
+    }
+}
+
+
 /// Represents the maximum and minimum magnitudes for angular acceleration,
 /// and velocity.
 ///
@@ -121,8 +581,8 @@ pub struct OrientationLimits {
 impl std::default::Default for OrientationLimits {
     fn default() -> Self {
         OrientationLimits {
-            maximum_angular_acceleration: std::f64::INFINITY,
-            maximum_angular_velocity: std::f64::INFINITY,
+            maximum_angular_acceleration: f64::INFINITY,
+            maximum_angular_velocity: f64::INFINITY,
             minimum_angular_acceleration: 0.0,
             minimum_angular_velocity: 0.0
         }
@@ -140,9 +600,199 @@ impl std::default::Default for OutputFile {
 }
 
 
+/// Represents settings controlling what is written to the output file.
+#[derive(Clone, Debug, Default)]
+pub struct OutputSettings {
+    /// The first step to write to the output file, if any.
+    pub from_step: Option<u128>,
+
+    /// Whether to include the lifetime and parent particle IDs of each entity.
+    pub lineage: bool,
+
+    /// The last step to write to the output file, if any.
+    pub to_step: Option<u128>,
+
+    /// Whether to only write entities marked as tracers.
+    pub tracers_only: bool
+}
+
+impl OutputSettings {
+    /// Returns whether the specified step lies within the (inclusive) range of
+    /// steps written to the output file.
+    pub fn includes_step(&self, step: u128) -> bool {
+        self.from_step.is_none_or(|from| step >= from) && self.to_step.is_none_or(|to| step <= to)
+    }
+}
+
+
+/// Represents the frame of reference in which output coordinates are written.
+#[derive(Clone, Debug)]
+pub enum OutputReference {
+    /// Positions and velocities are written relative to the entity with the
+    /// specified particle ID.
+    RelativeTo(u64),
+
+    /// Positions and velocities are written as-is (relative to the origin).
+    World
+}
+
+/// Implements `std::default::Default` for `OutputReference`.
+impl std::default::Default for OutputReference {
+    fn default() -> Self { OutputReference::World }
+}
+
+
+/// Represents the measurement of the oscillation of electrons relative to ions
+/// in the plasma oscillation scenario.
+#[derive(Clone, Debug, Default)]
+pub struct PlasmaOscillation {
+    /// The analytic (angular) plasma frequency.
+    pub analytic_frequency: f64,
+
+    /// The times at which the mean displacement of electrons relative to ions
+    /// changed sign.
+    pub crossing_times: Vec<f64>,
+
+    /// The mean displacement along the x axis at the end of the previous step.
+    pub previous_displacement: Option<f64>
+}
+
+impl PlasmaOscillation {
+    /// Returns the (angular) frequency of the oscillation measured so far, if
+    /// at least one half-period has been observed.
+    pub fn measured_frequency(&self) -> Option<f64> {
+        match (self.crossing_times.first(), self.crossing_times.last()) {
+            (Some(first), Some(last)) if last > first => {
+                Some(std::f64::consts::PI * (self.crossing_times.len() - 1) as f64 / (last - first))
+            },
+            _ => None
+        }
+    }
+}
+
+
+/// Represents the settings used when writing the radial density profile file.
+#[derive(Clone, Debug)]
+pub struct ProfileSettings {
+    /// The number of radial shells in each profile.
+    pub bins: usize,
+
+    /// The path to the profile file.
+    pub file: String,
+
+    /// The number of steps between profile entries.
+    pub interval: u128
+}
+
+/// Implements `std::default::Default` for `ProfileSettings`.
+impl std::default::Default for ProfileSettings {
+    fn default() -> Self {
+        ProfileSettings {
+            bins: 20,
+            file: String::from("profile.yaml"),
+            interval: 10
+        }
+    }
+}
+
+
+/// Represents the random number generator shared by every system and helper
+/// which needs randomness, so that a simulation can be reproduced from its seed.
+#[derive(Clone, Debug)]
+pub struct SharedRng(pub rand::rngs::StdRng);
+
+impl SharedRng {
+    /// Creates a new random number generator from the specified seed.
+    pub fn seeded(seed: u64) -> Self {
+        use rand::SeedableRng;
+        SharedRng(rand::rngs::StdRng::seed_from_u64(seed))
+    }
+}
+
+/// Implements `std::default::Default` for `SharedRng`.
+///
+/// The default generator is seeded from the operating system.
+impl std::default::Default for SharedRng {
+    fn default() -> Self {
+        use rand::SeedableRng;
+        SharedRng(rand::rngs::StdRng::from_entropy())
+    }
+}
+
+
+/// Represents a named bundle of default components used when populating the
+/// world with entities of a particular kind (such as stars or dark matter).
+#[derive(Clone, Debug)]
+pub struct Species {
+    /// Whether entities of this species are assigned a charge from the charge
+    /// distribution (otherwise they are uncharged).
+    pub charged: bool,
+
+    /// Whether collision detection is enabled for entities of this species.
+    pub collisions_enabled: bool,
+
+    /// The maximum mass of an entity of this species.
+    pub maximum_mass: f64,
+
+    /// The minimum mass of an entity of this species.
+    pub minimum_mass: f64,
+
+    /// The shape of entities of this species.
+    pub shape: Shape
+}
+
+impl Species {
+    /// Returns a mass drawn uniformly between the minimum and maximum masses of
+    /// this species, using the specified generator.
+    pub fn sample_mass<R: rand::Rng>(&self, rng: &mut R) -> f64 {
+        self.minimum_mass + (rng.gen::<f64>() * (self.maximum_mass - self.minimum_mass))
+    }
+}
+
+
+/// Represents the registry of species available when populating the world,
+/// keyed by name.
+#[derive(Clone, Debug)]
+pub struct SpeciesRegistry(pub std::collections::HashMap<String, Species>);
+
+/// Implements `std::default::Default` for `SpeciesRegistry`.
+impl std::default::Default for SpeciesRegistry {
+    fn default() -> Self {
+        let mut species = std::collections::HashMap::new();
+        species.insert(String::from("dark"), Species {
+            charged: false,
+            collisions_enabled: false,
+            maximum_mass: 10.0,
+            minimum_mass: 1.0,
+            shape: Shape::Point
+        });
+        species.insert(String::from("gas"), Species {
+            charged: true,
+            collisions_enabled: true,
+            maximum_mass: 1.5,
+            minimum_mass: 0.5,
+            shape: Shape::Sphere(1.0)
+        });
+        species.insert(String::from("star"), Species {
+            charged: false,
+            collisions_enabled: true,
+            maximum_mass: 200.0,
+            minimum_mass: 50.0,
+            shape: Shape::Sphere(2.0)
+        });
+        SpeciesRegistry(species)
+    }
+}
+
+
 /// Represents splitting settings.
 #[derive(Clone, Debug)]
 pub struct SplittingSettings {
+    /// The minimum number of steps which must pass after an entity was last
+    /// the product of a merge before it may divide, regardless of its mass or
+    /// lifetime.
+    pub cooldown: u128,
+
     /// The maximum lifetime an entity may be before it divides.
     pub maximum_lifetime: u128,
 
@@ -162,6 +812,7 @@ pub struct SplittingSettings {
 impl std::default::Default for SplittingSettings {
     fn default() -> Self {
         SplittingSettings {
+            cooldown: 200,
             maximum_lifetime: 1000,
             minimum_lifetime: 100,
             separation_multiplier: 2.0,
@@ -169,3 +820,158 @@ impl std::default::Default for SplittingSettings {
         }
     }
 }
+
+
+/// Represents the settings of the random force used to continually inject
+/// energy into the system.
+///
+/// The force acting on each entity follows an Ornstein-Uhlenbeck process, such
+/// that its components have a standard deviation of `amplitude` and decorrelate
+/// over `correlation_time`.
+#[derive(Clone, Debug)]
+pub struct Stirring {
+    /// The typical magnitude of each component of the stirring force.
+    pub amplitude: f64,
+
+    /// The time over which the stirring force decorrelates.
+    pub correlation_time: f64
+}
+
+/// Implements `std::default::Default` for `Stirring`.
+impl std::default::Default for Stirring {
+    fn default() -> Self {
+        Stirring {
+            amplitude: 0.0,
+            correlation_time: 10.0
+        }
+    }
+}
+
+
+/// Represents the settings used when writing the simulation summary file.
+#[derive(Clone, Debug)]
+pub struct SummarySettings {
+    /// The path to the summary file.
+    pub file: String,
+
+    /// The number of steps between summary entries.
+    pub interval: u128
+}
+
+/// Implements `std::default::Default` for `SummarySettings`.
+impl std::default::Default for SummarySettings {
+    fn default() -> Self {
+        SummarySettings {
+            file: String::from("summary.yaml"),
+            interval: 10
+        }
+    }
+}
+
+
+/// Represents the tidal torque driving entities with an orientation toward
+/// synchronous rotation in the tidally-locked scenario, along with the most
+/// recently measured spin and orbital periods.
+#[derive(Clone, Debug, Default)]
+pub struct TidalLocking {
+    /// The most recently measured orbital period.
+    pub orbital_period: Option<f64>,
+
+    /// The most recently measured spin period.
+    pub spin_period: Option<f64>,
+
+    /// The rate at which the spin relaxes toward the orbital angular velocity.
+    /// A strength of zero disables the tidal torque.
+    pub strength: f64
+}
+
+
+/// Represents the total kinetic energy removed by clamping the velocity of
+/// entities to the maximum velocity magnitude over the course of the
+/// simulation.
+#[derive(Clone, Debug, Default)]
+pub struct TotalClampedEnergy(pub f64);
+
+
+/// Represents the total kinetic energy dissipated by collisions over the course
+/// of the simulation.
+#[derive(Clone, Debug, Default)]
+pub struct TotalDissipatedEnergy(pub f64);
+
+
+/// Represents whether entities age according to their proper time, treating
+/// the maximum velocity magnitude as the speed of light.
+#[derive(Clone, Debug, Default)]
+pub struct TimeDilation(pub bool);
+
+
+/// Represents the size (in SI units) of the units the simulation is computed
+/// in, when its inputs are given in SI units.
+///
+/// Each scale is one unless the inputs have been normalized.
+#[derive(Clone, Debug)]
+pub struct UnitScale {
+    /// The unit of length, in meters.
+    pub length: f64,
+
+    /// The unit of mass, in kilograms.
+    pub mass: f64,
+
+    /// The unit of time, in seconds.
+    pub time: f64
+}
+
+/// Implements `std::default::Default` for `UnitScale`.
+impl std::default::Default for UnitScale {
+    fn default() -> Self { UnitScale { length: 1.0, mass: 1.0, time: 1.0 } }
+}
+
+
+/// Represents how the speed of entities is kept below the maximum velocity
+/// magnitude.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VelocityLimitMode {
+    /// Velocities exceeding the maximum are scaled down to it, discarding the
+    /// excess kinetic energy.
+    HardScale,
+
+    /// The part of each velocity change which would increase an entity's speed
+    /// is damped by `(speed / maximum)^2`, like a drag which grows as the speed
+    /// approaches the maximum. Entities therefore approach the maximum speed
+    /// smoothly instead of being cut off at it. Velocities which would still
+    /// exceed the maximum (such as after a very large acceleration) fall back
+    /// to being scaled down.
+    SoftDrag
+}
+
+/// Implements `std::default::Default` for `VelocityLimitMode`.
+impl std::default::Default for VelocityLimitMode {
+    fn default() -> Self { VelocityLimitMode::HardScale }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mond_is_newtonian_at_high_accelerations_and_deep_below_a0() {
+        let mond = AccelerationModifier::Mond { a0: 1.0 };
+        let strong = Vector(0.0, 1e6, 0.0);
+        assert!((mond.apply(strong).magnitude() / strong.magnitude() - 1.0).abs() < 1e-6);
+        let weak = Vector(-1e-6, 0.0, 0.0);
+        let deep = mond.apply(weak);
+        assert!((deep.magnitude() / (weak.magnitude() * 1.0).sqrt() - 1.0).abs() < 1e-3);
+        assert!(deep.direction().approx_eq(weak.direction(), 1e-12));
+        assert_eq!(AccelerationModifier::Newtonian.apply(weak), weak);
+    }
+
+    #[test]
+    fn interactive_force_is_inverse_square_toward_the_cursor() {
+        let attractor = InteractiveForce { position: Vector(1.0, 1.0, 0.0), strength: 4.0 };
+        assert_eq!(attractor.force_on(Vector(1.0, -1.0, 0.0), 3.0), Some(Vector(0.0, 3.0, 0.0)));
+        let repeller = InteractiveForce { strength: -4.0, ..attractor.clone() };
+        assert_eq!(repeller.force_on(Vector(1.0, 1.0, 2.0), 1.0), Some(Vector(0.0, 0.0, 1.0)));
+        assert_eq!(attractor.force_on(attractor.position, 1.0), None);
+    }
+}
@@ -2,11 +2,24 @@
 
 use crate::ecs::components;
 use crate::ecs::resources;
+use crate::grid::SpatialHash;
 use crate::math::*;
+use crate::octree::Octree;
 use crate::output::*;
+use crate::render;
 use specs::prelude::*;
 
 
+/// The number of cells along each axis of the grid used to compute the
+/// mean-field background force.
+const BACKGROUND_GRID_RESOLUTION: usize = 8;
+
+
+/// The fraction of entities whose velocity may be clamped in a single substep
+/// before a warning is logged suggesting that the time step is too large.
+const VELOCITY_CLAMPING_WARNING_FRACTION: f64 = 0.1;
+
+
 /// Clears/resets the collisions between all entities.
 pub struct ClearCollisions;
 impl<'a> System<'a> for ClearCollisions {
@@ -20,21 +33,36 @@ impl<'a> System<'a> for ClearCollisions {
 }
 
 
-/// Clears/resets the forces acting on all entities.
+/// Clears/resets the forces and torques acting on all entities.
 pub struct ClearForces;
 impl<'a> System<'a> for ClearForces {
-    type SystemData = WriteStorage<'a, components::Forces>;
-    fn run(&mut self, mut forces: Self::SystemData) {
+    type SystemData = (
+        WriteStorage<'a, components::Forces>,
+        WriteStorage<'a, components::Torques>
+    );
+    fn run(&mut self, (mut forces, mut torques): Self::SystemData) {
         debug!("Clearing forces...");
         for f in (&mut forces).join() {
             f.0 = std::collections::HashMap::new();
         }
+        for t in (&mut torques).join() {
+            t.0 = std::collections::HashMap::new();
+        }
     }
 }
 
 
 /// Detects collisions within the game world.
 /// Objects which have collided are assigned a collision component.
+///
+/// Pairs whose bounding spheres do not overlap are skipped before performing
+/// the (potentially expensive) exact shape test. Cuboids are treated as being
+/// axis-aligned. Pairs are tested on their current positions alone, so pairs
+/// which overlap are detected regardless of whether they are moving apart.
+///
+/// Since no pair further apart than the maximum detection threshold can ever
+/// collide, candidate pairs are found with a spatial hash grid whose cells are
+/// the size of that threshold, rather than by testing every pair.
 pub struct CollisionDetection;
 impl<'a> System<'a> for CollisionDetection {
     type SystemData = (
@@ -46,32 +74,68 @@ impl<'a> System<'a> for CollisionDetection {
     );
     fn run(&mut self, (entities, limits, dyns, phys, mut collisions): Self::SystemData) {
         debug!("Detecting collisions...");
-        for (i, (i_entity, i_dyns, i_phys)) in (&*entities, &dyns, &phys).join().enumerate() {
+        let bodies: Vec<(Entity, &components::Dynamics, &components::Physicality)> = (&*entities, &dyns, &phys).join().collect();
+        let positions: Vec<Vector> = bodies.iter().map(|(_, d, _)| d.position).collect();
+        let grid = SpatialHash::new(limits.maximum_detection_theshold, &positions);
+        for (i, &(i_entity, i_dyns, i_phys)) in bodies.iter().enumerate() {
             if i_phys.collisions_enabled {
-                for (j, (j_entity, j_dyns, j_phys)) in (&*entities, &dyns, &phys).join().enumerate() {
+                for j in grid.neighbors(i_dyns.position) {
+                    let (j_entity, j_dyns, j_phys) = bodies[j];
                     if let Some(i_collisions) = collisions.get_mut(i_entity) {
                         if i != j && j_phys.collisions_enabled && !i_collisions.0.contains(&j_entity) {
                            trace!("DETECTING COLLISIONS: {:?} <-> {:?}", i_entity, j_entity);
-                           let dist = (j_dyns.position - i_dyns.position).magnitude();
-                           if dist < limits.maximum_detection_theshold {
-                               if dist < limits.minimum_detection_theshold {
+                           let dvec = j_dyns.position - i_dyns.position;
+                           let dist_squared = i_dyns.position.distance_squared(j_dyns.position);
+                           let bounding_sum = i_phys.shape.bounding_radius() + j_phys.shape.bounding_radius();
+                           if dist_squared < limits.maximum_detection_theshold * limits.maximum_detection_theshold {
+                               if dist_squared < limits.minimum_detection_theshold * limits.minimum_detection_theshold {
                                    trace!("THRESHOLD COLLISION: {:?} <-> {:?}", i_entity, j_entity);
                                    i_collisions.0.push(j_entity);
                                    if let Some(j_collisions) = collisions.get_mut(j_entity) {
                                        j_collisions.0.push(i_entity);
                                    }
-                               } else {
+                               } else if dist_squared <= bounding_sum * bounding_sum {
                                    match (i_phys.shape, j_phys.shape) {
-                                       (Shape::Cuboid(_x1, _y1, _z1), Shape::Cuboid(_x2, _y2, _z2)) => {
+                                       (Shape::Cuboid(x1, y1, z1), Shape::Cuboid(x2, y2, z2)) => {
+                                           if dvec.0.abs() <= x1 + x2 && dvec.1.abs() <= y1 + y2 && dvec.2.abs() <= z1 + z2 {
+                                               trace!("CUBOID-CUBOID COLLISION: {:?} <-> {:?}", i_entity, j_entity);
+                                               i_collisions.0.push(j_entity);
+                                               if let Some(j_collisions) = collisions.get_mut(j_entity) {
+                                                   j_collisions.0.push(i_entity);
+                                               }
+                                           }
                                        },
-                                       (Shape::Cuboid(_x, _y, _z), Shape::Point) => {
+                                       (Shape::Cuboid(x, y, z), Shape::Point) => {
+                                           if dvec.0.abs() <= x && dvec.1.abs() <= y && dvec.2.abs() <= z {
+                                               trace!("CUBOID-POINT COLLISION: {:?} <-> {:?}", i_entity, j_entity);
+                                               i_collisions.0.push(j_entity);
+                                               if let Some(j_collisions) = collisions.get_mut(j_entity) {
+                                                   j_collisions.0.push(i_entity);
+                                               }
+                                           }
                                        },
-                                       (Shape::Cuboid(_x, _y, _z), Shape::Sphere(_r)) => {
+                                       (Shape::Cuboid(x, y, z), Shape::Sphere(r)) => {
+                                           let closest = dvec.clamp(Vector(-x, -y, -z), Vector(x, y, z));
+                                           if (dvec - closest).magnitude_squared() <= r * r {
+                                               trace!("CUBOID-SPHERE COLLISION: {:?} <-> {:?}", i_entity, j_entity);
+                                               i_collisions.0.push(j_entity);
+                                               if let Some(j_collisions) = collisions.get_mut(j_entity) {
+                                                   j_collisions.0.push(i_entity);
+                                               }
+                                           }
                                        },
-                                       (Shape::Sphere(_r), Shape::Cuboid(_x, _y, _z)) => {
+                                       (Shape::Sphere(r), Shape::Cuboid(x, y, z)) => {
+                                           let closest = dvec.clamp(Vector(-x, -y, -z), Vector(x, y, z));
+                                           if (dvec - closest).magnitude_squared() <= r * r {
+                                               trace!("SPHERE-CUBOID COLLISION: {:?} <-> {:?}", i_entity, j_entity);
+                                               i_collisions.0.push(j_entity);
+                                               if let Some(j_collisions) = collisions.get_mut(j_entity) {
+                                                   j_collisions.0.push(i_entity);
+                                               }
+                                           }
                                        },
                                        (Shape::Sphere(r), Shape::Point) => {
-                                           if dist - r <= 0.0 {
+                                           if dist_squared <= r * r {
                                                trace!("SPHERE-POINT COLLISION: {:?} <-> {:?}", i_entity, j_entity);
                                                i_collisions.0.push(j_entity);
                                                if let Some(j_collisions) = collisions.get_mut(j_entity) {
@@ -80,7 +144,7 @@ impl<'a> System<'a> for CollisionDetection {
                                            }
                                        },
                                        (Shape::Sphere(r1), Shape::Sphere(r2)) => {
-                                           if dist - (r1 + r2) <= 0.0 {
+                                           if dist_squared <= (r1 + r2) * (r1 + r2) {
                                                trace!("SPHERE-SPHERE COLLISION: {:?} <-> {:?}", i_entity, j_entity);
                                                i_collisions.0.push(j_entity);
                                                if let Some(j_collisions) = collisions.get_mut(j_entity) {
@@ -88,14 +152,21 @@ impl<'a> System<'a> for CollisionDetection {
                                                }
                                            }
                                        },
-                                       (Shape::Point, Shape::Cuboid(_x, _y, _z)) => {
+                                       (Shape::Point, Shape::Cuboid(x, y, z)) => {
+                                           if dvec.0.abs() <= x && dvec.1.abs() <= y && dvec.2.abs() <= z {
+                                               trace!("POINT-CUBOID COLLISION: {:?} <-> {:?}", i_entity, j_entity);
+                                               i_collisions.0.push(j_entity);
+                                               if let Some(j_collisions) = collisions.get_mut(j_entity) {
+                                                   j_collisions.0.push(i_entity);
+                                               }
+                                           }
                                        },
                                        (Shape::Point, Shape::Point) => {
                                            // Points only collide when they are on top of each other, which should
                                            // be catched by `min_detection_theshold` above.
                                        },
                                        (Shape::Point, Shape::Sphere(r)) => {
-                                           if dist - r <= 0.0 {
+                                           if dist_squared <= r * r {
                                                trace!("POINT-SPHERE COLLISION: {:?} <-> {:?}", i_entity, j_entity);
                                                i_collisions.0.push(j_entity);
                                                if let Some(j_collisions) = collisions.get_mut(j_entity) {
@@ -115,31 +186,163 @@ impl<'a> System<'a> for CollisionDetection {
 }
 
 
+/// Handles the mean-field gravitational force arising from the background
+/// density.
+///
+/// Entity masses are binned into a coarse grid spanning their bounding box, and
+/// each cell's overdensity (relative to the mean cell mass) acts as a point
+/// source located at the cell's center. Entities do not feel the cell they are
+/// located in. A uniform distribution of mass therefore produces no force.
+/// Probes feel the force but do not contribute to the mass of any cell.
+pub struct HandleBackground;
+impl<'a> System<'a> for HandleBackground {
+    type SystemData = (
+        Read<'a, resources::BackgroundDensity>,
+        Read<'a, resources::GravitationalConstant>,
+        ReadStorage<'a, components::Dynamics>,
+        ReadStorage<'a, components::Mass>,
+        ReadStorage<'a, components::Probe>,
+        WriteStorage<'a, components::Forces>
+    );
+    fn run(&mut self, (density, g, dynamics, masses, probes, mut forces): Self::SystemData) {
+        if density.0 == 0.0 || (&dynamics).join().next().is_none() {
+            return;
+        }
+        debug!("Computing mean-field background interactions...");
+        let n = BACKGROUND_GRID_RESOLUTION;
+        let mut min = Vector(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Vector(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for d in (&dynamics).join() {
+            min = Vector(min.0.min(d.position.0), min.1.min(d.position.1), min.2.min(d.position.2));
+            max = Vector(max.0.max(d.position.0), max.1.max(d.position.1), max.2.max(d.position.2));
+        }
+        let cell_size = ((max - min) / n as f64) + 1e-9;
+        let cell_volume = cell_size.0 * cell_size.1 * cell_size.2;
+        let cell_of = | p: Vector | -> usize {
+            let c = (p - min) / cell_size;
+            let clamp = | v: f64 | (v.floor().max(0.0) as usize).min(n - 1);
+            (clamp(c.0) * n * n) + (clamp(c.1) * n) + clamp(c.2)
+        };
+        let mut cell_masses = vec![0.0; n * n * n];
+        let mut total_mass = 0.0;
+        for (d, m, _) in (&dynamics, &masses, !&probes).join() {
+            cell_masses[cell_of(d.position)] += m.0;
+            total_mass += m.0;
+        }
+        let mean_mass = total_mass / cell_masses.len() as f64;
+        if mean_mass == 0.0 {
+            return;
+        }
+        for (d, m, f) in (&dynamics, &masses, &mut forces).join() {
+            let own_cell = cell_of(d.position);
+            let mut force = Vector::default();
+            for (c, cell_mass) in cell_masses.iter().enumerate() {
+                if c == own_cell {
+                    continue;
+                }
+                let overdensity = (cell_mass - mean_mass) / mean_mass;
+                let center = min + (cell_size * Vector((c / (n * n)) as f64 + 0.5, ((c / n) % n) as f64 + 0.5, (c % n) as f64 + 0.5));
+                let dvec = center - d.position;
+                if let Some(direction) = dvec.try_direction() {
+                    force += direction * ((g.0 * m.0 * density.0 * overdensity * cell_volume) / dvec.magnitude_squared());
+                }
+            }
+            trace!("BACKGROUND FORCE: {:?}", force);
+            f.0.insert(components::ForceSource::Background, force);
+        }
+    }
+}
+
+
 /// Handles the entities which have been detected as collided.
+///
+/// Collided entities are merged into a single entity which conserves their
+/// total momentum. The kinetic energy lost in each merge is logged and added to
+/// the `TotalDissipatedEnergy` resource. The current step is recorded as the
+/// merged body's `LastMerge`, so that it does not split again until the
+/// splitting cooldown has passed.
+///
+/// Each entity is consumed by at most one merge per substep, even if it appears
+/// in the collision lists of several entities, so its mass is never counted
+/// twice.
+///
+/// By default, the merged body is a newly-created entity with a new particle
+/// ID. When `MergePreservesIdentity` is set, the heaviest input (ties broken by
+/// the lowest particle ID) instead absorbs the others, keeping its entity and
+/// particle ID, and recording the absorbed particles as its parents. Its
+/// lifetime restarts, just as that of a newly-created entity would.
+///
+/// Either way, the merged body is anchored, immortal or a tracer if any of its
+/// inputs was, only has collisions enabled if all of its inputs did, and keeps
+/// the group of its heaviest input. A merge involving an anchored input stays
+/// at that input's position.
 pub struct HandleCollisions;
 impl<'a> System<'a> for HandleCollisions {
     type SystemData = (
         Entities<'a>,
         Read<'a, LazyUpdate>,
+        Read<'a, resources::ChargeLimits>,
+        Read<'a, resources::CurrentStep>,
+        Write<'a, resources::IdCounter>,
+        Write<'a, resources::MaintenanceRequired>,
+        Read<'a, resources::MergePreservesIdentity>,
+        Write<'a, resources::TotalDissipatedEnergy>,
+        ReadStorage<'a, components::Anchored>,
         WriteStorage<'a, components::Charge>,
         WriteStorage<'a, components::Collisions>,
         WriteStorage<'a, components::Dynamics>,
+        ReadStorage<'a, components::Group>,
+        ReadStorage<'a, components::Immortal>,
+        WriteStorage<'a, components::LastMerge>,
         WriteStorage<'a, components::Mass>,
-        WriteStorage<'a, components::Physicality>
+        WriteStorage<'a, components::Parents>,
+        WriteStorage<'a, components::ParticleId>,
+        WriteStorage<'a, components::Physicality>,
+        ReadStorage<'a, components::Tracer>
     );
-    fn run(&mut self, (entities, lazy_updater, mut all_charges, mut all_collisions, mut all_dynamics, mut all_masses, mut all_physicality): Self::SystemData) {
+    fn run(&mut self, (entities, lazy_updater, charge_limits, current_step, mut id_counter, mut maintenance, preserve_identity, mut dissipated, all_anchored, mut all_charges, mut all_collisions, mut all_dynamics, all_groups, all_immortal, mut all_merges, mut all_masses, mut all_parents, mut all_ids, mut all_physicality, all_tracers): Self::SystemData) {
         debug!("Handling collisions...");
+        let mut consumed: std::collections::HashSet<Entity> = std::collections::HashSet::new();
         for entity in (&*entities).join() {
-            let collisions: Vec<Entity> = match all_collisions.get(entity) { Some(c) => c.0.clone(), _ => Vec::new() };
-            if collisions.len() > 0 {
+            if consumed.contains(&entity) {
+                continue;
+            }
+            let collisions: Vec<Entity> = match all_collisions.get(entity) {
+                Some(c) => c.0.iter().filter(|e| !consumed.contains(*e) && entities.is_alive(**e)).cloned().collect(),
+                _ => Vec::new()
+            };
+            if !collisions.is_empty() {
+                let heaviest = {
+                    let mass = | e: Entity | all_masses.get(e).map_or(0.0, |m| m.0);
+                    let id = | e: Entity | all_ids.get(e).map_or(u64::MAX, |id| id.0);
+                    std::iter::once(entity).chain(collisions.iter().cloned()).max_by(|a, b| {
+                        mass(*a).partial_cmp(&mass(*b)).unwrap_or(std::cmp::Ordering::Equal).then_with(|| id(*b).cmp(&id(*a)))
+                    }).unwrap_or(entity)
+                };
+                let target = match preserve_identity.0 {
+                    true => Some(heaviest),
+                    false => None
+                };
+                let new_group = all_groups.get(heaviest).cloned();
+                let mut merged_ids: Vec<u64> = all_ids.get(entity).map(|id| id.0).into_iter().collect();
+                let mut is_anchored = all_anchored.contains(entity);
+                let mut is_immortal = all_immortal.contains(entity);
+                let mut is_tracer = all_tracers.contains(entity);
+                let mut collisions_enabled = all_physicality.get(entity).is_none_or(|p| p.collisions_enabled);
+                let mut anchor_position: Option<Vector> = None;
                 let mut new_charge: f64 = match all_charges.get(entity) { Some(charge) => charge.0, _ => 0.0 };
                 let mut new_mass: f64 = match all_masses.get(entity) { Some(mass) => mass.0, _ => 0.0 };
                 let mut new_position: Vector = Vector::default();
-                let mut new_velocity: Vector = Vector::default();
+                let mut new_momentum: Vector = Vector::default();
                 let mut new_radius: f64 = 0.0;
+                let mut initial_energy: f64 = 0.0;
                 if let Some(dynamics) = all_dynamics.get(entity) {
                     new_position = dynamics.position;
-                    new_velocity = dynamics.velocity;
+                    new_momentum = dynamics.velocity * new_mass;
+                    initial_energy += 0.5 * new_mass * dynamics.velocity.dot(dynamics.velocity);
+                    if is_anchored {
+                        anchor_position = Some(dynamics.position);
+                    }
                 }
                 if let Some(physicality) = all_physicality.get(entity) {
                     new_radius = match physicality.shape {
@@ -153,7 +356,13 @@ impl<'a> System<'a> for HandleCollisions {
                     }
                     if let Some(other_dynamics) = all_dynamics.get(*other_entity) {
                         new_position += (other_dynamics.position - new_position) / 2.0;
-                        new_velocity += other_dynamics.velocity;
+                        if anchor_position.is_none() && all_anchored.contains(*other_entity) {
+                            anchor_position = Some(other_dynamics.position);
+                        }
+                        if let Some(other_mass) = all_masses.get(*other_entity) {
+                            new_momentum += other_dynamics.velocity * other_mass.0;
+                            initial_energy += 0.5 * other_mass.0 * other_dynamics.velocity.dot(other_dynamics.velocity);
+                        }
                     }
                     if let Some(other_mass) = all_masses.get(*other_entity) {
                         new_mass += other_mass.0;
@@ -162,32 +371,82 @@ impl<'a> System<'a> for HandleCollisions {
                         if let Shape::Sphere(r) = other_physicality.shape {
                             new_radius += r / 2.0;
                         }
+                        collisions_enabled &= other_physicality.collisions_enabled;
+                    }
+                    if let Some(other_id) = all_ids.get(*other_entity) {
+                        merged_ids.push(other_id.0);
                     }
+                    is_anchored |= all_anchored.contains(*other_entity);
+                    is_immortal |= all_immortal.contains(*other_entity);
+                    is_tracer |= all_tracers.contains(*other_entity);
+                    consumed.insert(*other_entity);
                     all_collisions.remove(*other_entity);
-                    entities.delete(*other_entity).expect("Unable to delete other entity");
+                    if target != Some(*other_entity) {
+                        entities.delete(*other_entity).expect("Unable to delete other entity");
+                    }
+                }
+                new_charge = charge_limits.clamp(new_charge);
+                if let Some(position) = anchor_position {
+                    new_position = position;
                 }
                 trace!("NEW CHARGE: {}", new_charge);
                 trace!("NEW MASS: {}", new_mass);
                 trace!("NEW POSITION: {:?}", new_position);
                 trace!("NEW RADIUS: {}", new_radius);
+                let new_velocity = match new_mass > 0.0 {
+                    true => new_momentum / new_mass,
+                    false => Vector::default()
+                };
                 trace!("NEW VELOCITY: {:?}", new_velocity);
-                let new_entity = entities.create();
+                let dissipated_energy = initial_energy - (0.5 * new_mass * new_velocity.dot(new_velocity));
+                dissipated.0 += dissipated_energy;
+                let (new_entity, new_id) = match target {
+                    Some(t) => (t, all_ids.get(t).map_or_else(|| id_counter.allocate(), |id| id.0)),
+                    None => (entities.create(), id_counter.allocate())
+                };
+                debug!("Merged particles {:?} into particle {}, dissipating {:.4e} energy.", merged_ids, new_id, dissipated_energy);
                 all_charges.insert(new_entity, components::Charge(new_charge)).expect("Unable to update charge");
-                lazy_updater.insert(new_entity, components::Collisions::default());
                 all_dynamics.insert(new_entity, components::Dynamics {
                     acceleration: Vector::default(),
                     position: new_position,
                     velocity: new_velocity
                 }).expect("Unable to update dynamics");
-                lazy_updater.insert(new_entity, components::Forces::default());
-                lazy_updater.insert(new_entity, components::Lifetime::default());
+                if target.is_some() {
+                    merged_ids.retain(|id| *id != new_id);
+                    all_collisions.insert(new_entity, components::Collisions::default()).expect("Unable to update collisions");
+                    lazy_updater.insert(new_entity, components::Lifetime::default());
+                    lazy_updater.remove::<components::ProperTime>(new_entity);
+                } else {
+                    lazy_updater.insert(new_entity, components::Collisions::default());
+                    lazy_updater.insert(new_entity, components::Forces::default());
+                    lazy_updater.insert(new_entity, components::Lifetime::default());
+                }
+                all_merges.insert(new_entity, components::LastMerge(current_step.0)).expect("Unable to update last merge");
                 all_masses.insert(new_entity, components::Mass(new_mass)).expect("Unable to update mass");
+                all_ids.insert(new_entity, components::ParticleId(new_id)).expect("Unable to update particle ID");
+                all_parents.insert(new_entity, components::Parents(merged_ids)).expect("Unable to update parents");
+                if is_anchored {
+                    lazy_updater.insert(new_entity, components::Anchored);
+                }
+                if let Some(group) = new_group {
+                    lazy_updater.insert(new_entity, group);
+                }
+                if is_immortal {
+                    lazy_updater.insert(new_entity, components::Immortal);
+                }
+                if is_tracer {
+                    lazy_updater.insert(new_entity, components::Tracer);
+                }
                 all_physicality.insert(new_entity, components::Physicality {
-                    collisions_enabled: true,
+                    collisions_enabled,
                     shape: Shape::Sphere(new_radius)
                 }).expect("Unable to update physicality");
-                all_collisions.remove(entity);
-                entities.delete(entity).expect("Unable to delete entity");
+                consumed.insert(entity);
+                if target != Some(entity) {
+                    all_collisions.remove(entity);
+                    entities.delete(entity).expect("Unable to delete entity");
+                }
+                maintenance.0 = true;
             }
         }
     }
@@ -197,46 +456,96 @@ impl<'a> System<'a> for HandleCollisions {
 /// Handles updating the position and velocity of an entity from its
 /// acceleration.
 ///
+/// Since this system is run once per collision substep, each invocation only
+/// advances the simulation by a fraction of `DeltaTime`.
+///
 /// This system will also automatically truncate the various values according to
-/// their limits, with the exception of "position", which will be toroidally
-/// wrapped because our universe has periodic boundary conditions.
+/// their limits. Entities which pass the maximum position are placed back on
+/// the (spherical) boundary, and their velocity is reflected off it.
+/// The kinetic energy removed by clamping velocities is added to the `TotalClampedEnergy`
+/// resource. How the maximum velocity is enforced depends on the
+/// `VelocityLimitMode` resource.
+///
+/// With the velocity Verlet integrator, the velocity is advanced by the mean of
+/// the previous and current accelerations (completing the previous step) and
+/// the position by a second-order step using the current acceleration. Entities
+/// without a previous acceleration (such as newly created ones) keep their
+/// velocity on their first step. Anchored entities are never moved.
+///
+/// In two dimensions, the z components of accelerations and velocities are
+/// discarded, so that entities stay in the plane they started in.
 pub struct HandleDynamics;
 impl<'a> System<'a> for HandleDynamics {
     type SystemData = (
+        Entities<'a>,
+        Read<'a, resources::CollisionSubsteps>,
         Read<'a, resources::DeltaTime>,
+        Read<'a, resources::Dimensionality>,
         Read<'a, resources::DynamicsLimits>,
-        WriteStorage<'a, components::Dynamics>
+        Read<'a, resources::Integrator>,
+        Write<'a, resources::TotalClampedEnergy>,
+        Read<'a, resources::VelocityLimitMode>,
+        ReadStorage<'a, components::Anchored>,
+        ReadStorage<'a, components::Mass>,
+        WriteStorage<'a, components::Dynamics>,
+        WriteStorage<'a, components::PreviousAcceleration>
     );
     fn run(&mut self, data: Self::SystemData) {
         debug!("Updating newtonian dynamics...");
-        let (dt, limits, mut objects) = data;
-        for obj in (&mut objects).join() {
+        let (entities, substeps, dt, dimensionality, limits, integrator, mut clamped_energy, limit_mode, anchored, masses, mut objects, mut previous) = data;
+        let dt = dt.0 / f64::from(substeps.0);
+        let mut num_objects: usize = 0;
+        let mut num_clamped: usize = 0;
+        for (entity, obj, mass, _) in (&*entities, &mut objects, masses.maybe(), !&anchored).join() {
+            num_objects += 1;
             trace!(
                 "OLD DYNAMICS: [{:?}, {:?}, {:?}]",
                 &obj.acceleration,
                 &obj.velocity,
                 &obj.position
             );
-            let acc_mag = obj.acceleration.magnitude();
-            if acc_mag < limits.minimum_acceleration {
-                obj.acceleration *= limits.minimum_acceleration / acc_mag;
-            } else if acc_mag > limits.maximum_acceleration {
-                obj.acceleration *= limits.maximum_acceleration / acc_mag;
+            obj.acceleration = dimensionality.project(obj.acceleration).clamp_magnitude(limits.minimum_acceleration, limits.maximum_acceleration);
+            let mut dv = match *integrator {
+                resources::Integrator::Euler => obj.acceleration * dt,
+                resources::Integrator::VelocityVerlet => match previous.get(entity) {
+                    Some(p) => (p.0 + obj.acceleration) * (0.5 * dt),
+                    None => Vector::default()
+                }
+            };
+            if *limit_mode == resources::VelocityLimitMode::SoftDrag && limits.maximum_velocity > 0.0 {
+                if let Some(direction) = obj.velocity.try_direction() {
+                    let speedup = dv.dot(direction);
+                    if speedup > 0.0 {
+                        let ratio = (obj.velocity.magnitude() / limits.maximum_velocity).min(1.0);
+                        dv -= direction * (speedup * ratio * ratio);
+                    }
+                }
             }
-            obj.velocity += obj.acceleration * dt.0;
+            obj.velocity = dimensionality.project(obj.velocity + dv);
             let vel_mag = obj.velocity.magnitude();
-            if vel_mag < limits.minimum_velocity {
-                obj.velocity *= limits.minimum_velocity / vel_mag;
-            } else if vel_mag > limits.maximum_velocity {
-                obj.velocity *= limits.maximum_velocity / vel_mag;
+            obj.velocity = obj.velocity.clamp_magnitude(limits.minimum_velocity, limits.maximum_velocity);
+            if vel_mag > limits.maximum_velocity {
+                if let Some(m) = mass {
+                    let removed = 0.5 * m.0 * ((vel_mag * vel_mag) - (limits.maximum_velocity * limits.maximum_velocity));
+                    trace!("CLAMPED ENERGY: {}", removed);
+                    clamped_energy.0 += removed;
+                }
+                num_clamped += 1;
             }
-            obj.position += obj.velocity * dt.0;
+            obj.position += match *integrator {
+                resources::Integrator::Euler => obj.velocity * dt,
+                resources::Integrator::VelocityVerlet => {
+                    previous.insert(entity, components::PreviousAcceleration(obj.acceleration)).expect("Unable to set previous acceleration");
+                    (obj.velocity * dt) + (obj.acceleration * (0.5 * dt * dt))
+                }
+            };
             let pos_mag = obj.position.magnitude();
-            if pos_mag < limits.minimum_position {
-                obj.position *= limits.minimum_position / pos_mag;
-            } else if pos_mag > limits.maximum_position {
-                obj.position *= limits.maximum_position / pos_mag;
-                obj.velocity = (-obj.velocity / 2.0);
+            obj.position = obj.position.clamp_magnitude(limits.minimum_position, limits.maximum_position);
+            if pos_mag > limits.maximum_position {
+                let normal = obj.position.direction();
+                if obj.velocity.dot(normal) > 0.0 {
+                    obj.velocity = obj.velocity.reflect(normal);
+                }
             }
             trace!(
                 "NEW DYNAMICS: [{:?}, {:?}, {:?}]",
@@ -245,40 +554,62 @@ impl<'a> System<'a> for HandleDynamics {
                 &obj.position
             );
         }
+        if num_clamped as f64 > VELOCITY_CLAMPING_WARNING_FRACTION * num_objects as f64 {
+            warn!("Clamped the velocity of {} of {} entities - the time step may be too large.", num_clamped, num_objects);
+        }
     }
 }
 
 
 /// Handles electrostatic interactions.
+///
+/// Pairs of entities occupying the exact same position are skipped, since the
+/// force between them is undefined. Probes feel the force of other entities
+/// but do not exert any force themselves. Forces are softened by the
+/// electrostatic softening length.
 pub struct HandleElectrostatics;
 impl<'a> System<'a> for HandleElectrostatics {
     type SystemData = (
         Entities<'a>,
         Read<'a, resources::ElectrostaticConstant>,
+        Read<'a, resources::ElectrostaticSoftening>,
+        Read<'a, resources::ForcePrecision>,
+        Read<'a, resources::ForceSymmetry>,
         ReadStorage<'a, components::Charge>,
         ReadStorage<'a, components::Dynamics>,
+        ReadStorage<'a, components::Probe>,
         WriteStorage<'a, components::Forces>
     );
-    fn run(&mut self, (entities, k, charges, dynamics, mut forces): Self::SystemData) {
+    fn run(&mut self, (entities, k, softening, precision, symmetry, charges, dynamics, probes, mut forces): Self::SystemData) {
         debug!("Computing electrostatic interactions...");
         for (i, (i_entity, i_charge, i_dynamics)) in (&*entities, &charges, &dynamics).join().enumerate() {
             for (j, (j_entity, j_charge, j_dynamics)) in (&*entities, &charges, &dynamics).join().enumerate() {
+                if probes.contains(j_entity) {
+                    continue;
+                }
                 if let Some(i_forces) = forces.get_mut(i_entity) {
-                    if i != j && !i_forces.0.contains_key(&format!("electrostatics:{:?}", j_entity)) {
+                    if i != j && (*symmetry == resources::ForceSymmetry::Independent || !i_forces.0.contains_key(&components::ForceSource::Electrostatics(j_entity))) {
                         trace!("COMPUTING ELECTROSTATICS: {:?} <-> {:?}", i_entity, j_entity);
                         let dvec = j_dynamics.position - i_dynamics.position;
-                        let dmag = dvec.magnitude();
-                        let es = dvec.direction() * ((-1.0 * k.0 * i_charge.0 * j_charge.0) / (dmag * dmag));
+                        let es = match precision.inverse_square(dvec, -k.0 * i_charge.0 * j_charge.0, softening.0) {
+                            Some(force) => force,
+                            None => {
+                                trace!("SKIPPING COINCIDENT ELECTROSTATICS: {:?} <-> {:?}", i_entity, j_entity);
+                                continue;
+                            }
+                        };
                         trace!("ELECTROSTATIC FORCE: {:?}", es);
                         i_forces.0.insert(
-                            format!("electrostatics:{:?}", j_entity),
+                            components::ForceSource::Electrostatics(j_entity),
                             es
                         );
-                        if let Some(j_forces) = forces.get_mut(j_entity) {
-                            j_forces.0.insert(
-                                format!("electrostatics:{:?}", i_entity),
-                                -es
-                            );
+                        if *symmetry == resources::ForceSymmetry::Symmetric && !probes.contains(i_entity) {
+                            if let Some(j_forces) = forces.get_mut(j_entity) {
+                                j_forces.0.insert(
+                                    components::ForceSource::Electrostatics(i_entity),
+                                    -es
+                                );
+                            }
                         }
                     }
                 } else {
@@ -290,20 +621,131 @@ impl<'a> System<'a> for HandleElectrostatics {
 }
 
 
+/// Handles the drag force acting on each entity, which is proportional to the
+/// cross-sectional area of its shape and the square of its speed.
+pub struct HandleDrag;
+impl<'a> System<'a> for HandleDrag {
+    type SystemData = (
+        Read<'a, resources::DragCoefficient>,
+        ReadStorage<'a, components::Dynamics>,
+        ReadStorage<'a, components::Physicality>,
+        WriteStorage<'a, components::Forces>
+    );
+    fn run(&mut self, (cd, dynamics, physicality, mut forces): Self::SystemData) {
+        if cd.0 == 0.0 {
+            return;
+        }
+        debug!("Computing drag forces...");
+        for (d, p, f) in (&dynamics, &physicality, &mut forces).join() {
+            let area = p.shape.cross_sectional_area(d.velocity);
+            let drag = d.velocity * (-0.5 * cd.0 * area * d.velocity.magnitude());
+            trace!("DRAG FORCE: {:?}", drag);
+            f.0.insert(components::ForceSource::Drag, drag);
+        }
+    }
+}
+
+
+/// Handles entities which have escaped the system.
+///
+/// An entity has escaped when it is farther than the escape distance from the
+/// center of mass of the system and its specific orbital energy relative to
+/// the rest of the system (treated as a point mass at the center of mass) is
+/// positive. Escaped entities are marked with the `Escaped` component, or
+/// deleted if removal is enabled. Probes may escape, but do not contribute to
+/// the center of mass.
+pub struct HandleEscapes;
+impl<'a> System<'a> for HandleEscapes {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, resources::EscapeSettings>,
+        Read<'a, resources::GravitationalConstant>,
+        Write<'a, resources::MaintenanceRequired>,
+        ReadStorage<'a, components::Dynamics>,
+        ReadStorage<'a, components::Mass>,
+        ReadStorage<'a, components::Probe>,
+        WriteStorage<'a, components::Escaped>
+    );
+    fn run(&mut self, (entities, settings, g, mut maintenance, dynamics, masses, probes, mut escaped): Self::SystemData) {
+        debug!("Detecting escaped entities...");
+        let mut total_mass = 0.0;
+        let mut weighted_position = Vector::default();
+        let mut weighted_velocity = Vector::default();
+        for (d, m, _) in (&dynamics, &masses, !&probes).join() {
+            total_mass += m.0;
+            weighted_position += d.position * m.0;
+            weighted_velocity += d.velocity * m.0;
+        }
+        if total_mass == 0.0 {
+            return;
+        }
+        let center_position = weighted_position / total_mass;
+        let center_velocity = weighted_velocity / total_mass;
+        let mut newly_escaped = Vec::new();
+        for (entity, d, m, _) in (&*entities, &dynamics, &masses, !&escaped).join() {
+            let distance = d.position.distance(center_position);
+            if distance <= settings.distance {
+                continue;
+            }
+            let other_mass = match probes.contains(entity) {
+                true => total_mass,
+                false => total_mass - m.0
+            };
+            let speed_squared = (d.velocity - center_velocity).magnitude_squared();
+            let energy = (0.5 * speed_squared) - (g.0 * other_mass / distance);
+            if energy > 0.0 {
+                newly_escaped.push((entity, distance, energy));
+            }
+        }
+        for (entity, distance, energy) in newly_escaped {
+            info!("{:?} has escaped (distance: {:.4e}, specific energy: {:.4e}).", entity, distance, energy);
+            if settings.remove {
+                entities.delete(entity).expect("Unable to delete entity");
+                maintenance.0 = true;
+            } else {
+                escaped.insert(entity, components::Escaped).expect("Unable to mark entity as escaped");
+            }
+        }
+    }
+}
+
+
 /// Handles the translation of all forces into an acceleration vector.
+///
+/// When an acceleration modifier other than `Newtonian` is in effect, it is
+/// applied to the acceleration arising from the (pairwise) gravitational forces
+/// alone, before the acceleration from all other forces is added.
+///
+/// Forces imparted by entities which are no longer alive (for example, those
+/// consumed in a collision) are ignored, so that stale entries never contribute
+/// even if they were not cleared beforehand.
 pub struct HandleForces;
 impl<'a> System<'a> for HandleForces {
     type SystemData = (
+        Entities<'a>,
+        Read<'a, resources::AccelerationModifier>,
         ReadStorage<'a, components::Forces>,
         ReadStorage<'a, components::Mass>,
         WriteStorage<'a, components::Dynamics>
     );
-    fn run(&mut self, (forces, masses, mut dynamics): Self::SystemData) {
+    fn run(&mut self, (entities, modifier, forces, masses, mut dynamics): Self::SystemData) {
         debug!("Computing net forces and acceleration...");
         for (f, m, d) in (&forces, &masses, &mut dynamics).join() {
-            let net_force: Vector = f.0.values().sum();
+            let live: Vec<(&components::ForceSource, &Vector)> = f.0.iter()
+                .filter(|(source, _)| source.entity().is_none_or(|e| entities.is_alive(e)))
+                .collect();
+            let net_force: Vector = live.iter().map(|(_, force)| *force).sum();
             trace!("NET FORCE: {:?}", net_force);
-            let acc = net_force / m.0;
+            let acc = match *modifier {
+                resources::AccelerationModifier::Newtonian => net_force / m.0,
+                _ => {
+                    let gravity: Vector = live.iter()
+                        .filter(|(source, _)| matches!(source, components::ForceSource::Gravity(_) | components::ForceSource::GravityTree))
+                        .map(|(_, force)| **force)
+                        .fold(Vector::default(), |total, force| total + force);
+                    ((net_force - gravity) / m.0) + modifier.apply(gravity / m.0)
+                }
+            };
             trace!("ACCELERATION: {:?}", acc);
             d.acceleration = acc;
         }
@@ -312,35 +754,91 @@ impl<'a> System<'a> for HandleForces {
 
 
 /// Handles gravitational interactions.
+///
+/// Pairs of entities occupying the exact same position are skipped, since the
+/// force between them is undefined. Probes feel the force of other entities
+/// but do not exert any force themselves. Forces are softened by the
+/// gravitational softening length.
+///
+/// With the Barnes-Hut method, an octree is built over all non-probe entities
+/// each step, and the net (approximate) gravitational force on each entity is
+/// stored under a single `GravityTree` source.
+///
+/// In two dimensions, the z components of positions are ignored and the
+/// Barnes-Hut method builds a quadtree instead.
 pub struct HandleGravity;
 impl<'a> System<'a> for HandleGravity {
     type SystemData = (
         Entities<'a>,
+        Read<'a, resources::Dimensionality>,
         Read<'a, resources::GravitationalConstant>,
+        Read<'a, resources::GravitationalSoftening>,
+        Read<'a, resources::ForcePrecision>,
+        Read<'a, resources::ForceSymmetry>,
+        Read<'a, resources::GravityMethod>,
         ReadStorage<'a, components::Dynamics>,
         ReadStorage<'a, components::Mass>,
+        ReadStorage<'a, components::Probe>,
         WriteStorage<'a, components::Forces>
     );
-    fn run(&mut self, (entities, g, dynamics, masses, mut forces): Self::SystemData) {
+    fn run(&mut self, (entities, dimensionality, g, softening, precision, symmetry, method, dynamics, masses, probes, mut forces): Self::SystemData) {
+        if let resources::GravityMethod::BarnesHut { theta } = *method {
+            debug!("Computing newtonian gravitational interactions (Barnes-Hut)...");
+            let sources: Vec<(Entity, Vector, f64)> = (&*entities, &dynamics, &masses, !&probes).join()
+                .map(|(e, d, m, _)| (e, d.position, m.0))
+                .collect();
+            let indices: std::collections::HashMap<Entity, usize> = sources.iter()
+                .enumerate()
+                .map(|(i, (e, _, _))| (*e, i))
+                .collect();
+            let bodies = sources.iter().map(|(_, p, m)| (*p, *m)).collect();
+            let tree = match *dimensionality {
+                resources::Dimensionality::Two => Octree::new_quadtree(bodies),
+                resources::Dimensionality::Three => Octree::new(bodies)
+            };
+            for (i_entity, i_dynamics, i_mass, i_forces) in (&*entities, &dynamics, &masses, &mut forces).join() {
+                let exclude = indices.get(&i_entity).copied();
+                let i_position = dimensionality.project(i_dynamics.position);
+                let mut grav = Vector::default();
+                tree.visit_sources(i_position, exclude, theta, |position, mass| {
+                    if let Some(force) = precision.inverse_square(position - i_position, g.0 * i_mass.0 * mass, softening.0) {
+                        grav += force;
+                    }
+                });
+                trace!("FORCE OF GRAVITY: {:?}", grav);
+                i_forces.0.insert(components::ForceSource::GravityTree, grav);
+            }
+            return;
+        }
         debug!("Computing newtonian gravitational interactions...");
         for (i, (i_entity, i_dynamics, i_mass)) in (&*entities, &dynamics, &masses).join().enumerate() {
             for (j, (j_entity, j_dynamics, j_mass)) in (&*entities, &dynamics, &masses).join().enumerate() {
+                if probes.contains(j_entity) {
+                    continue;
+                }
                 if let Some(i_forces) = forces.get_mut(i_entity) {
-                    if i != j && !i_forces.0.contains_key(&format!("gravity:{:?}", j_entity)) {
+                    if i != j && (*symmetry == resources::ForceSymmetry::Independent || !i_forces.0.contains_key(&components::ForceSource::Gravity(j_entity))) {
                         trace!("COMPUTING GRAVITY: {:?} <-> {:?}", i_entity, j_entity);
-                        let dvec = j_dynamics.position - i_dynamics.position;
-                        let dmag = dvec.magnitude();
-                        let grav = dvec.direction() * ((g.0 * i_mass.0 * j_mass.0) / (dmag * dmag));
+                        let dvec = dimensionality.project(j_dynamics.position - i_dynamics.position);
+                        let grav = match precision.inverse_square(dvec, g.0 * i_mass.0 * j_mass.0, softening.0) {
+                            Some(force) => force,
+                            None => {
+                                trace!("SKIPPING COINCIDENT GRAVITY: {:?} <-> {:?}", i_entity, j_entity);
+                                continue;
+                            }
+                        };
                         trace!("FORCE OF GRAVITY: {:?}", grav);
                         i_forces.0.insert(
-                            format!("gravity:{:?}", j_entity),
+                            components::ForceSource::Gravity(j_entity),
                             grav
                         );
-                        if let Some(j_forces) = forces.get_mut(j_entity) {
-                            j_forces.0.insert(
-                                format!("gravity:{:?}", i_entity),
-                                -grav
-                            );
+                        if *symmetry == resources::ForceSymmetry::Symmetric && !probes.contains(i_entity) {
+                            if let Some(j_forces) = forces.get_mut(j_entity) {
+                                j_forces.0.insert(
+                                    components::ForceSource::Gravity(i_entity),
+                                    -grav
+                                );
+                            }
                         }
                     }
                 } else {
@@ -386,7 +884,7 @@ impl<'a> System<'a> for HandleOrientation {
                 obj.angular_velocity *= limits.maximum_angular_velocity / vec_mag;
             }
             obj.angular_position += obj.angular_velocity * dt.0;
-            obj.angular_position = obj.angular_position.direction();
+            obj.angular_position.normalize();
             trace!(
                 "NEW ORIENTATION: [{:?}, {:?}, {:?}]",
                 &obj.angular_acceleration,
@@ -398,22 +896,63 @@ impl<'a> System<'a> for HandleOrientation {
 }
 
 
+/// Handles the force imparted on all entities by the interactive point.
+pub struct HandleInteractiveForce;
+impl<'a> System<'a> for HandleInteractiveForce {
+    type SystemData = (
+        Read<'a, resources::InteractiveForce>,
+        ReadStorage<'a, components::Dynamics>,
+        ReadStorage<'a, components::Mass>,
+        WriteStorage<'a, components::Forces>
+    );
+    fn run(&mut self, (interactive, dynamics, masses, mut forces): Self::SystemData) {
+        if interactive.strength == 0.0 {
+            return;
+        }
+        debug!("Computing interactive forces...");
+        for (d, m, f) in (&dynamics, &masses, &mut forces).join() {
+            if let Some(force) = interactive.force_on(d.position, m.0) {
+                trace!("INTERACTIVE FORCE: {:?}", force);
+                f.0.insert(components::ForceSource::Interactive, force);
+            }
+        }
+    }
+}
+
+
 /// Handles the splitting of particles into two.
+///
+/// This system runs in its own dispatcher once all collision substeps of a step
+/// have been resolved and the world has been maintained, so an entity which
+/// both collides and is old enough to split is merged first, and only the
+/// surviving entities may split. Probes and immortal entities never split, and
+/// neither do entities which were the product of a merge within the configured
+/// cooldown.
 pub struct HandleSplitting;
 impl<'a> System<'a> for HandleSplitting {
     type SystemData = (
         Entities<'a>,
         Read<'a, LazyUpdate>,
+        Read<'a, resources::ChargeLimits>,
+        Read<'a, resources::CurrentStep>,
         Read<'a, resources::SplittingSettings>,
+        Write<'a, resources::IdCounter>,
+        Write<'a, resources::MaintenanceRequired>,
+        ReadStorage<'a, components::Immortal>,
+        ReadStorage<'a, components::LastMerge>,
         ReadStorage<'a, components::Lifetime>,
         WriteStorage<'a, components::Charge>,
         WriteStorage<'a, components::Dynamics>,
         WriteStorage<'a, components::Mass>,
-        WriteStorage<'a, components::Physicality>
+        WriteStorage<'a, components::Parents>,
+        WriteStorage<'a, components::ParticleId>,
+        WriteStorage<'a, components::Physicality>,
+        ReadStorage<'a, components::Probe>,
+        ReadStorage<'a, components::Tracer>
     );
-    fn run(&mut self, (entities, lazy_updater, settings, lifetimes, mut all_charges, mut all_dynamics, mut all_masses, mut all_physicality): Self::SystemData) {
+    fn run(&mut self, (entities, lazy_updater, charge_limits, current_step, settings, mut id_counter, mut maintenance, immortals, last_merges, lifetimes, mut all_charges, mut all_dynamics, mut all_masses, mut all_parents, mut all_ids, mut all_physicality, all_probes, all_tracers): Self::SystemData) {
         debug!("Handling entity splitting...");
-        for (entity, lifetime) in (&*entities, &lifetimes).join() {
+        for (entity, lifetime, _, _) in (&*entities, &lifetimes, !&all_probes, !&immortals).join() {
             let mass: f64 = match all_masses.get(entity) { Some(m) => m.0, _ => 1.0 };
             let mut radius: f64 = 1.0;
             if let Some(physicality) = all_physicality.get(entity) {
@@ -428,6 +967,9 @@ impl<'a> System<'a> for HandleSplitting {
             } else if mass <= -10.0 {
                 split_factor /= (-mass / 10.0).floor();
             }
+            if last_merges.get(entity).is_some_and(|m| current_step.0.saturating_sub(m.0) < settings.cooldown) {
+                continue;
+            }
             if lifetime.0 > settings.minimum_lifetime && (lifetime.0 > settings.maximum_lifetime || (lifetime.0 as f64) > split_factor) {
                 // Get the original component values.
                 let charge: f64 = match all_charges.get(entity) { Some(c) => c.0, _ => 0.0 };
@@ -440,12 +982,20 @@ impl<'a> System<'a> for HandleSplitting {
                 // Setup the two new particles.
                 let p1 = entities.create();
                 let p2 = entities.create();
+                let p1_id = id_counter.allocate();
+                let p2_id = id_counter.allocate();
+                let parent_ids: Vec<u64> = all_ids.get(entity).map(|id| id.0).into_iter().collect();
+                debug!("Split particles {:?} into particles {} and {}.", parent_ids, p1_id, p2_id);
+                all_ids.insert(p1, components::ParticleId(p1_id)).expect("Unable to set particle ID");
+                all_ids.insert(p2, components::ParticleId(p2_id)).expect("Unable to set particle ID");
+                all_parents.insert(p1, components::Parents(parent_ids.clone())).expect("Unable to set parents");
+                all_parents.insert(p2, components::Parents(parent_ids)).expect("Unable to set parents");
                 if charge == 0.0 {
-                    all_charges.insert(p1, components::Charge(-1.0)).expect("Unable to set charge");
-                    all_charges.insert(p2, components::Charge(1.0)).expect("Unable to set charge");
+                    all_charges.insert(p1, components::Charge(charge_limits.clamp(-1.0))).expect("Unable to set charge");
+                    all_charges.insert(p2, components::Charge(charge_limits.clamp(1.0))).expect("Unable to set charge");
                 } else {
-                    all_charges.insert(p1, components::Charge((charge / 2.0).floor())).expect("Unable to set charge");
-                    all_charges.insert(p2, components::Charge((charge / 2.0).ceil())).expect("Unable to set charge");
+                    all_charges.insert(p1, components::Charge(charge_limits.clamp((charge / 2.0).floor()))).expect("Unable to set charge");
+                    all_charges.insert(p2, components::Charge(charge_limits.clamp((charge / 2.0).ceil()))).expect("Unable to set charge");
                 }
                 all_masses.insert(p1, components::Mass(mass / 2.0)).expect("Unable to set mass");
                 all_masses.insert(p2, components::Mass(mass / 2.0)).expect("Unable to set mass");
@@ -473,61 +1023,1892 @@ impl<'a> System<'a> for HandleSplitting {
                 lazy_updater.insert(p2, components::Forces::default());
                 lazy_updater.insert(p1, components::Lifetime::default());
                 lazy_updater.insert(p2, components::Lifetime::default());
+                if all_tracers.contains(entity) {
+                    lazy_updater.insert(p1, components::Tracer);
+                    lazy_updater.insert(p2, components::Tracer);
+                }
                 entities.delete(entity).expect("Unable to delete entity");
+                maintenance.0 = true;
             }
         }
     }
 }
 
 
+/// Handles the time-correlated random force used to stir the system.
+///
+/// Each entity's stirring force is advanced by one exact step of an
+/// Ornstein-Uhlenbeck process, so that successive values are correlated over
+/// the configured correlation time. Random numbers are drawn from the
+/// `SharedRng` resource, so that seeded runs stir identically.
+pub struct HandleStirring;
+impl<'a> System<'a> for HandleStirring {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, resources::DeltaTime>,
+        Write<'a, resources::SharedRng>,
+        Read<'a, resources::Stirring>,
+        WriteStorage<'a, components::Forces>,
+        WriteStorage<'a, components::StirringForce>
+    );
+    fn run(&mut self, (entities, dt, mut rng, stirring, mut forces, mut stirring_forces): Self::SystemData) {
+        if stirring.amplitude == 0.0 {
+            return;
+        }
+        debug!("Computing stirring forces...");
+        let decay = (-dt.0 / stirring.correlation_time).exp();
+        let diffusion = stirring.amplitude * (1.0 - (decay * decay)).sqrt();
+        for (entity, f) in (&*entities, &mut forces).join() {
+            let previous = match stirring_forces.get(entity) {
+                Some(s) => s.0,
+                None => Vector::random_normal_from(&mut rng.0) * stirring.amplitude
+            };
+            let current = (previous * decay) + (Vector::random_normal_from(&mut rng.0) * diffusion);
+            trace!("STIRRING FORCE: {:?} -> {:?}", entity, current);
+            f.0.insert(components::ForceSource::Stirring, current);
+            stirring_forces.insert(entity, components::StirringForce(current)).expect("Unable to set stirring force");
+        }
+    }
+}
+
+
+/// Handles the translation of all torques into an angular acceleration vector.
+///
+/// The net torque is divided by the moment of inertia of the entity's shape.
+/// Entities with no moment of inertia (such as points) are left unchanged, as
+/// are torques imparted by entities which are no longer alive.
+pub struct HandleTorques;
+impl<'a> System<'a> for HandleTorques {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, components::Mass>,
+        ReadStorage<'a, components::Physicality>,
+        ReadStorage<'a, components::Torques>,
+        WriteStorage<'a, components::Orientation>
+    );
+    fn run(&mut self, (entities, masses, phys, torques, mut orientations): Self::SystemData) {
+        debug!("Computing net torques and angular acceleration...");
+        for (t, m, p, o) in (&torques, &masses, &phys, &mut orientations).join() {
+            let inertia = p.shape.moment_of_inertia(m.0);
+            if inertia <= 0.0 {
+                continue;
+            }
+            let net_torque: Vector = t.0.iter()
+                .filter(|(source, _)| source.entity().is_none_or(|e| entities.is_alive(e)))
+                .map(|(_, torque)| torque)
+                .sum();
+            trace!("NET TORQUE: {:?}", net_torque);
+            o.angular_acceleration = net_torque / inertia;
+        }
+    }
+}
+
+
+/// Handles the tidal torque on entities with an orientation, and measures their
+/// spin and orbital periods.
+///
+/// The orbital angular velocity of each such entity is computed relative to
+/// the center of mass of all other entities (excluding probes), and a torque is
+/// applied which relaxes its angular velocity toward it at the configured
+/// strength.
+pub struct HandleTidalTorque;
+impl<'a> System<'a> for HandleTidalTorque {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, resources::TidalLocking>,
+        ReadStorage<'a, components::Dynamics>,
+        ReadStorage<'a, components::Mass>,
+        ReadStorage<'a, components::Orientation>,
+        ReadStorage<'a, components::Physicality>,
+        ReadStorage<'a, components::Probe>,
+        WriteStorage<'a, components::Torques>
+    );
+    fn run(&mut self, data: Self::SystemData) {
+        debug!("Computing tidal torques...");
+        let (entities, mut locking, dynamics, masses, orientations, phys, probes, mut torques) = data;
+        let (mut total_mass, mut total_position, mut total_momentum) = (0.0, Vector::default(), Vector::default());
+        for (d, m, _) in (&dynamics, &masses, !&probes).join() {
+            total_mass += m.0;
+            total_position += d.position * m.0;
+            total_momentum += d.velocity * m.0;
+        }
+        for (entity, d, m, o, p, t, _) in (&*entities, &dynamics, &masses, &orientations, &phys, &mut torques, !&probes).join() {
+            let other_mass = total_mass - m.0;
+            if other_mass <= 0.0 {
+                continue;
+            }
+            let separation = d.position - ((total_position - (d.position * m.0)) / other_mass);
+            let relative_velocity = d.velocity - ((total_momentum - (d.velocity * m.0)) / other_mass);
+            if separation.magnitude_squared() == 0.0 {
+                continue;
+            }
+            let orbital_velocity = separation.cross(relative_velocity) / separation.magnitude_squared();
+            let torque = (orbital_velocity - o.angular_velocity) * (locking.strength * p.shape.moment_of_inertia(m.0));
+            trace!("TIDAL TORQUE: {:?} -> {:?}", entity, torque);
+            t.0.insert(components::ForceSource::Tides, torque);
+            let tau = 2.0 * std::f64::consts::PI;
+            locking.orbital_period = Some(tau / orbital_velocity.magnitude());
+            locking.spin_period = Some(tau / o.angular_velocity.magnitude());
+        }
+    }
+}
+
+
+/// Measures the oscillation of electrons relative to ions in the plasma
+/// oscillation scenario.
+///
+/// The times at which the mean displacement (along the x axis) of negative
+/// charges relative to positive charges changes sign are linearly interpolated
+/// between steps.
+pub struct MeasurePlasmaOscillation;
+impl<'a> System<'a> for MeasurePlasmaOscillation {
+    type SystemData = (
+        Read<'a, resources::CurrentStep>,
+        Read<'a, resources::DeltaTime>,
+        Write<'a, resources::PlasmaOscillation>,
+        ReadStorage<'a, components::Charge>,
+        ReadStorage<'a, components::Dynamics>
+    );
+    fn run(&mut self, (current_step, dt, mut oscillation, charges, dynamics): Self::SystemData) {
+        debug!("Measuring plasma oscillation...");
+        let (mut electron_sum, mut electron_count, mut ion_sum, mut ion_count) = (0.0, 0.0, 0.0, 0.0);
+        for (c, d) in (&charges, &dynamics).join() {
+            if c.0 < 0.0 {
+                electron_sum += d.position.0;
+                electron_count += 1.0;
+            } else if c.0 > 0.0 {
+                ion_sum += d.position.0;
+                ion_count += 1.0;
+            }
+        }
+        if electron_count == 0.0 || ion_count == 0.0 {
+            return;
+        }
+        let displacement = (electron_sum / electron_count) - (ion_sum / ion_count);
+        trace!("PLASMA DISPLACEMENT: {}", displacement);
+        if let Some(previous) = oscillation.previous_displacement {
+            if previous != 0.0 && previous.signum() != displacement.signum() {
+                let time = ((current_step.0 - 1) as f64 + (previous / (previous - displacement))) * dt.0;
+                debug!("Plasma displacement changed sign at t = {}.", time);
+                oscillation.crossing_times.push(time);
+            }
+        }
+        oscillation.previous_displacement = Some(displacement);
+    }
+}
+
+
 /// Updates the lifetime of all entities.
+///
+/// When time dilation is enabled, each entity instead accumulates `1 / gamma`
+/// steps of proper time per step, where `gamma` is computed from the entity's
+/// speed relative to the maximum velocity magnitude, and its lifetime is the
+/// number of whole steps of proper time it has experienced.
 pub struct UpdateLifetimes;
 impl<'a> System<'a> for UpdateLifetimes {
-    type SystemData = WriteStorage<'a, components::Lifetime>;
-    fn run(&mut self, mut lifetimes: Self::SystemData) {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, resources::DynamicsLimits>,
+        Read<'a, resources::TimeDilation>,
+        ReadStorage<'a, components::Dynamics>,
+        WriteStorage<'a, components::Lifetime>,
+        WriteStorage<'a, components::ProperTime>
+    );
+    fn run(&mut self, (entities, limits, dilation, dynamics, mut lifetimes, mut proper_times): Self::SystemData) {
         debug!("Updating entity lifetimes...");
-        for lifetime in (&mut lifetimes).join() {
-            lifetime.0 += 1;
+        if !dilation.0 {
+            for lifetime in (&mut lifetimes).join() {
+                lifetime.0 += 1;
+            }
+            return;
+        }
+        for (entity, lifetime, d) in (&*entities, &mut lifetimes, dynamics.maybe()).join() {
+            let beta = match d {
+                Some(d) => (d.velocity.magnitude() / limits.maximum_velocity).min(1.0),
+                None => 0.0
+            };
+            let increment = (1.0 - (beta * beta)).sqrt();
+            let proper_time = match proper_times.get_mut(entity) {
+                Some(t) => {
+                    t.0 += increment;
+                    t.0
+                },
+                None => {
+                    let t = lifetime.0 as f64 + increment;
+                    proper_times.insert(entity, components::ProperTime(t)).expect("Unable to set proper time");
+                    t
+                }
+            };
+            lifetime.0 = proper_time.floor() as u128;
+        }
+    }
+}
+
+
+/// Renders the current state of the simulation to a PNG image in the frames
+/// directory, one image per step.
+#[derive(Default)]
+pub struct WriteFrames {
+    /// The index of the next frame to write.
+    pub frame: u128
+}
+impl<'a> System<'a> for WriteFrames {
+    type SystemData = (
+        Read<'a, resources::FrameSettings>,
+        ReadStorage<'a, components::Charge>,
+        ReadStorage<'a, components::Dynamics>,
+        ReadStorage<'a, components::Physicality>
+    );
+    fn run(&mut self, (settings, charges, dynamics, physicality): Self::SystemData) {
+        debug!("Writing frame...");
+        let particles: Vec<(Vector, f64, [u8; 3])> = (&dynamics, charges.maybe(), physicality.maybe()).join()
+            .map(|(d, c, p)| (
+                d.position,
+                p.map_or(0.0, |p| match p.shape {
+                    Shape::Cuboid(x, y, z) => x.max(y).max(z),
+                    Shape::Point => 0.0,
+                    Shape::Sphere(r) => r
+                }),
+                render::charge_color(c.map_or(0.0, |c| c.0))
+            ))
+            .collect();
+        let frame = render::rasterize(&particles, settings.extent, settings.resolution);
+        let path = std::path::Path::new(&settings.directory).join(format!("{:08}.png", self.frame));
+        std::fs::write(&path, render::encode_png(&frame)).expect("Unable to write frame.");
+        self.frame += 1;
+    }
+}
+
+
+/// Writes an estimate of the two-point correlation function to the two-point
+/// correlation file every configured number of steps.
+///
+/// Pairs are found with a spatial hash grid whose cells are the size of the
+/// maximum separation, and are binned into equally-spaced separation bins. The
+/// correlation in each bin is the ratio of the number of pairs to the number
+/// expected of a uniform (Poisson) distribution of the same number of entities
+/// over their bounding box, minus one. Edge effects are not corrected for, so
+/// the estimate is biased low at separations comparable to the box size.
+pub struct WriteCorrelation;
+impl<'a> System<'a> for WriteCorrelation {
+    type SystemData = (
+        Read<'a, resources::CorrelationSettings>,
+        Read<'a, resources::CurrentStep>,
+        Write<'a, CorrelationWriter>,
+        ReadStorage<'a, components::Dynamics>
+    );
+    fn run(&mut self, (settings, current_step, mut writer, dynamics): Self::SystemData) {
+        if current_step.0 % settings.interval != 0 {
+            return;
+        }
+        debug!("Writing two-point correlation function...");
+        let positions: Vec<Vector> = (&dynamics).join().map(|d| d.position).collect();
+        if positions.len() < 2 {
+            warn!("Unable to write two-point correlation function - fewer than two entities exist.");
+            return;
+        }
+        let extent = positions.iter().fold(positions[0], |acc, p| acc.component_max(*p))
+            - positions.iter().fold(positions[0], |acc, p| acc.component_min(*p));
+        let volume = extent.0 * extent.1 * extent.2;
+        if volume <= 0.0 {
+            warn!("Unable to write two-point correlation function - entities do not span a volume.");
+            return;
+        }
+        let width = settings.maximum_separation / settings.bins as f64;
+        let mut counts = vec![0; settings.bins];
+        let grid = SpatialHash::new(settings.maximum_separation, &positions);
+        for (i, p) in positions.iter().enumerate() {
+            for j in grid.neighbors(*p) {
+                if j <= i {
+                    continue;
+                }
+                let d = p.distance(positions[j]);
+                if d < settings.maximum_separation {
+                    counts[((d / width).floor() as usize).min(settings.bins - 1)] += 1;
+                }
+            }
+        }
+        let n = positions.len() as f64;
+        let total_pairs = n * (n - 1.0) / 2.0;
+        let bins = counts.into_iter().enumerate().map(|(i, pairs)| {
+            let (inner, outer) = (i as f64 * width, (i + 1) as f64 * width);
+            let shell = 4.0 / 3.0 * std::f64::consts::PI * (outer.powi(3) - inner.powi(3));
+            let expected = total_pairs * shell / volume;
+            CorrelationBin {
+                correlation: (pairs as f64 / expected) - 1.0,
+                pairs,
+                separation: outer
+            }
+        }).collect();
+        let entry = CorrelationEntry {
+            bins,
+            step: current_step.0
+        };
+        writer.write_entry(&settings.file, entry).expect("Unable to write to two-point correlation file.");
+    }
+}
+
+
+/// Writes a histogram of the distance from each entity to its nearest neighbor
+/// to the nearest-neighbor distance file every configured number of steps.
+///
+/// Nearest neighbors are found with a spatial hash grid whose cells are the
+/// size of the mean spacing between entities (within their bounding box).
+/// Distances are binned into equally-spaced bins extending out to the largest
+/// nearest-neighbor distance.
+pub struct WriteNeighbors;
+impl<'a> System<'a> for WriteNeighbors {
+    type SystemData = (
+        Read<'a, resources::CurrentStep>,
+        Read<'a, resources::NeighborsSettings>,
+        Write<'a, NeighborsWriter>,
+        ReadStorage<'a, components::Dynamics>
+    );
+    fn run(&mut self, (current_step, settings, mut writer, dynamics): Self::SystemData) {
+        if current_step.0 % settings.interval != 0 {
+            return;
         }
+        debug!("Writing nearest-neighbor distances...");
+        let positions: Vec<Vector> = (&dynamics).join().map(|d| d.position).collect();
+        if positions.len() < 2 {
+            warn!("Unable to write nearest-neighbor distances - fewer than two entities exist.");
+            return;
+        }
+        let extent = positions.iter().fold(positions[0], |acc, p| acc.component_max(*p))
+            - positions.iter().fold(positions[0], |acc, p| acc.component_min(*p));
+        let spacing = extent.0.max(extent.1).max(extent.2) / (positions.len() as f64).cbrt();
+        let grid = SpatialHash::new(if spacing > 0.0 { spacing } else { 1.0 }, &positions);
+        let distances: Vec<f64> = (0..positions.len())
+            .map(|i| grid.nearest_distance(&positions, i).unwrap_or(0.0))
+            .collect();
+        let maximum_distance = distances.iter().fold(0.0, | acc: f64, d | acc.max(*d));
+        let width = match maximum_distance > 0.0 {
+            true => maximum_distance / settings.bins as f64,
+            false => 1.0
+        };
+        let mut counts = vec![0; settings.bins];
+        for d in distances {
+            let bin = ((d / width).floor() as usize).min(settings.bins - 1);
+            counts[bin] += 1;
+        }
+        let bins = counts.into_iter().enumerate().map(|(i, count)| {
+            NeighborsBin {
+                count,
+                distance: (i + 1) as f64 * width
+            }
+        }).collect();
+        let entry = NeighborsEntry {
+            bins,
+            step: current_step.0
+        };
+        writer.write_entry(&settings.file, entry).expect("Unable to write to nearest-neighbor distance file.");
     }
 }
 
 
 /// Writes simulation data to the specified output file.
+///
+/// This system is run once the world has been maintained at the end of each
+/// step, so that each entry reflects the entities which exist after that step
+/// (excluding merged inputs and including newly-created entities).
+///
+/// Only steps within the configured output range (if any) are written. When an
+/// output reference is configured, the reference particle is looked up by its
+/// particle ID each step, and positions and velocities are written relative
+/// to its own.
+///
+/// Output is serialized on a background thread by the `OutputWriter` resource,
+/// which writes any queued entries when the world is dropped at the end of (or
+/// part-way through) the simulation.
 pub struct WriteOutput;
 impl<'a> System<'a> for WriteOutput {
     type SystemData = (
+        Entities<'a>,
+        Read<'a, resources::CurrentStep>,
+        Read<'a, resources::Dimensionality>,
         Read<'a, resources::OutputFile>,
+        Read<'a, resources::OutputSettings>,
+        Write<'a, resources::OutputReference>,
+        Write<'a, OutputWriter<OutputEntry>>,
         ReadStorage<'a, components::Charge>,
         ReadStorage<'a, components::Dynamics>,
-        ReadStorage<'a, components::Mass>
+        ReadStorage<'a, components::Lifetime>,
+        ReadStorage<'a, components::Mass>,
+        ReadStorage<'a, components::Parents>,
+        ReadStorage<'a, components::ParticleId>,
+        ReadStorage<'a, components::Tracer>
     );
-    fn run(&mut self, (output_file, charges, dynamics, masses): Self::SystemData) {
-        use std::io::Write;
+    fn run(&mut self, (entities, current_step, dimensionality, output_file, settings, mut reference, mut writer, charges, dynamics, lifetimes, masses, parents, ids, tracers): Self::SystemData) {
+        if !settings.includes_step(current_step.0) {
+            return;
+        }
         debug!("Writing output...");
+        let mut origin_position = Vector::default();
+        let mut origin_velocity = Vector::default();
+        if let resources::OutputReference::RelativeTo(reference_id) = *reference {
+            match (&ids, &dynamics).join().find(|(id, _)| id.0 == reference_id) {
+                Some((_, d)) => {
+                    origin_position = d.position;
+                    origin_velocity = d.velocity;
+                },
+                None => {
+                    warn!("Output reference particle {} no longer exists - falling back to world frame.", reference_id);
+                    *reference = resources::OutputReference::World;
+                }
+            }
+        }
         let mut output_entities: Vec<OutputEntity> = Vec::new();
-        for (i_charge, i_dynamics, i_mass) in (&charges, &dynamics, &masses).join() {
+        for (i_entity, i_charge, i_dynamics, i_lifetime, i_mass, i_parents, i_id) in (&*entities, &charges, &dynamics, lifetimes.maybe(), &masses, parents.maybe(), &ids).join() {
+            if settings.tracers_only && !tracers.contains(i_entity) {
+                continue;
+            }
             let oe = OutputEntity {
-                acceleration: i_dynamics.acceleration,
+                acceleration: OutputVector::new(i_dynamics.acceleration, *dimensionality),
                 charge: i_charge.0,
+                id: i_id.0,
+                lifetime: match settings.lineage {
+                    true => i_lifetime.map(|l| l.0),
+                    false => None
+                },
                 mass: i_mass.0,
-                position: i_dynamics.position,
-                velocity: i_dynamics.velocity
+                parents: match (settings.lineage, i_parents) {
+                    (true, Some(p)) => p.0.clone(),
+                    _ => Vec::new()
+                },
+                position: OutputVector::new(i_dynamics.position - origin_position, *dimensionality),
+                velocity: OutputVector::new(i_dynamics.velocity - origin_velocity, *dimensionality)
             };
             trace!("OUTPUT ENTITY: {:?}", oe);
             output_entities.push(oe);
         }
         let entry = OutputEntry {
-            step: 0,
+            step: current_step.0,
             entities: output_entities
         };
-        let yaml_string = format!("{}\n", serde_yaml::to_string(&entry).expect("Unable to serialize entry."));
-        let mut file = std::fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .append(true)
-            .open(&output_file.0)
-            .expect("Unable to open output file.");
-        file.write_all(yaml_string.as_bytes()).expect("Unable to write to output file.");
+        writer.write_entry(&output_file.0, entry).expect("Unable to write to output file.");
+    }
+}
+
+
+/// Writes the spherically-averaged density profile about the center of mass to
+/// the profile file every configured number of steps.
+///
+/// Mass is binned into equally-spaced radial shells extending out to the most
+/// distant entity. Probes are excluded.
+pub struct WriteProfile;
+impl<'a> System<'a> for WriteProfile {
+    type SystemData = (
+        Read<'a, resources::CurrentStep>,
+        Read<'a, resources::ProfileSettings>,
+        Write<'a, ProfileWriter>,
+        ReadStorage<'a, components::Dynamics>,
+        ReadStorage<'a, components::Mass>,
+        ReadStorage<'a, components::Probe>
+    );
+    fn run(&mut self, (current_step, settings, mut writer, dynamics, masses, probes): Self::SystemData) {
+        if current_step.0 % settings.interval != 0 {
+            return;
+        }
+        debug!("Writing density profile...");
+        let mut total_mass = 0.0;
+        let mut center_of_mass = Vector::default();
+        for (d, m, _) in (&dynamics, &masses, !&probes).join() {
+            total_mass += m.0;
+            center_of_mass += d.position * m.0;
+        }
+        if total_mass <= 0.0 {
+            warn!("Unable to write density profile - total mass is not positive.");
+            return;
+        }
+        center_of_mass /= total_mass;
+        let radii: Vec<(f64, f64)> = (&dynamics, &masses, !&probes).join()
+            .map(|(d, m, _)| (d.position.distance(center_of_mass), m.0))
+            .collect();
+        let maximum_radius = radii.iter().fold(0.0, | acc: f64, (r, _) | acc.max(*r));
+        let width = match maximum_radius > 0.0 {
+            true => maximum_radius / settings.bins as f64,
+            false => 1.0
+        };
+        let mut shell_masses = vec![0.0; settings.bins];
+        for (r, m) in radii {
+            let bin = ((r / width).floor() as usize).min(settings.bins - 1);
+            shell_masses[bin] += m;
+        }
+        let mut enclosed_mass = 0.0;
+        let shells = shell_masses.iter().enumerate().map(|(i, shell_mass)| {
+            let inner_radius = i as f64 * width;
+            let outer_radius = (i + 1) as f64 * width;
+            let volume = (4.0 / 3.0) * std::f64::consts::PI * (outer_radius.powi(3) - inner_radius.powi(3));
+            enclosed_mass += shell_mass;
+            ProfileShell {
+                density: shell_mass / volume,
+                enclosed_mass,
+                radius: outer_radius
+            }
+        }).collect();
+        let entry = ProfileEntry {
+            shells,
+            step: current_step.0
+        };
+        writer.write_entry(&settings.file, entry).expect("Unable to write to profile file.");
+    }
+}
+
+
+/// Writes a summary of the shape of the mass distribution to the summary file
+/// every configured number of steps.
+///
+/// The shape is characterized by the eigenvalues of the mass-weighted second
+/// moment tensor about the center of mass, from which both the principal
+/// moments of inertia and the axis ratios of the equivalent ellipsoid follow.
+///
+/// The total angular momentum is also reported both about the origin and about
+/// the center of mass (in the center of mass frame). Without external torques,
+/// both should be conserved. Probes are excluded.
+pub struct WriteSummary;
+impl<'a> System<'a> for WriteSummary {
+    type SystemData = (
+        Read<'a, resources::CurrentStep>,
+        Read<'a, resources::SummarySettings>,
+        Write<'a, SummaryWriter>,
+        ReadStorage<'a, components::Dynamics>,
+        ReadStorage<'a, components::Mass>,
+        ReadStorage<'a, components::Probe>
+    );
+    fn run(&mut self, (current_step, settings, mut writer, dynamics, masses, probes): Self::SystemData) {
+        if current_step.0 % settings.interval != 0 {
+            return;
+        }
+        debug!("Writing summary...");
+        let mut total_mass = 0.0;
+        let mut center_of_mass = Vector::default();
+        let mut total_momentum = Vector::default();
+        let mut angular_momentum_about_origin = Vector::default();
+        for (d, m, _) in (&dynamics, &masses, !&probes).join() {
+            total_mass += m.0;
+            center_of_mass += d.position * m.0;
+            total_momentum += d.velocity * m.0;
+            angular_momentum_about_origin += d.position.cross(d.velocity * m.0);
+        }
+        if total_mass <= 0.0 {
+            warn!("Unable to write summary - total mass is not positive.");
+            return;
+        }
+        center_of_mass /= total_mass;
+        let center_of_mass_velocity = total_momentum / total_mass;
+        let mut angular_momentum_about_center_of_mass = Vector::default();
+        let mut second_moment = Matrix::default();
+        for (d, m, _) in (&dynamics, &masses, !&probes).join() {
+            let r = d.position - center_of_mass;
+            angular_momentum_about_center_of_mass += r.cross((d.velocity - center_of_mass_velocity) * m.0);
+            let r = [r.0, r.1, r.2];
+            for i in 0..3 {
+                for j in 0..3 {
+                    second_moment.0[i][j] += m.0 * r[i] * r[j];
+                }
+            }
+        }
+        let eigenvalues = second_moment.symmetric_eigenvalues();
+        trace!("SECOND MOMENT EIGENVALUES: {:?}", eigenvalues);
+        let trace = eigenvalues.iter().sum::<f64>();
+        let axis_ratio = | eigenvalue: f64 | match eigenvalues[0] > 0.0 {
+            true => (eigenvalue.max(0.0) / eigenvalues[0]).sqrt(),
+            false => 1.0
+        };
+        trace!("ANGULAR MOMENTUM: {:?} (origin), {:?} (center of mass)", angular_momentum_about_origin, angular_momentum_about_center_of_mass);
+        let entry = SummaryEntry {
+            angular_momentum_about_center_of_mass,
+            angular_momentum_about_origin,
+            intermediate_axis_ratio: axis_ratio(eigenvalues[1]),
+            minor_axis_ratio: axis_ratio(eigenvalues[2]),
+            principal_moments: [trace - eigenvalues[0], trace - eigenvalues[1], trace - eigenvalues[2]],
+            step: current_step.0
+        };
+        writer.write_entry(&settings.file, entry).expect("Unable to write to summary file.");
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::components::*;
+
+    /// Creates a world with every component registered.
+    fn world() -> World {
+        let mut world = World::new();
+        world.register::<Anchored>();
+        world.register::<Charge>();
+        world.register::<Collisions>();
+        world.register::<Dynamics>();
+        world.register::<Escaped>();
+        world.register::<Forces>();
+        world.register::<Group>();
+        world.register::<Immortal>();
+        world.register::<LastMerge>();
+        world.register::<Lifetime>();
+        world.register::<Mass>();
+        world.register::<Orientation>();
+        world.register::<Parents>();
+        world.register::<ParticleId>();
+        world.register::<Physicality>();
+        world.register::<PreviousAcceleration>();
+        world.register::<Probe>();
+        world.register::<ProperTime>();
+        world.register::<StirringForce>();
+        world.register::<Torques>();
+        world.register::<Tracer>();
+        world
+    }
+
+    /// Simulates the specified number of steps of gravitational dynamics,
+    /// inserting the default value of any resource which is not present.
+    fn simulate(world: &mut World, steps: usize) {
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(ClearForces, "clear_forces", &[])
+            .with(HandleGravity, "handle_gravity", &["clear_forces"])
+            .with(HandleForces, "handle_forces", &["handle_gravity"])
+            .with(HandleDynamics, "handle_dynamics", &["handle_forces"])
+            .build();
+        dispatcher.setup(world);
+        for _ in 0..steps {
+            dispatcher.dispatch(world);
+            world.maintain();
+        }
+    }
+
+    /// Returns a path in the temporary directory unique to this process and the
+    /// specified name, removing any file already there.
+    fn temporary_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("grav-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Creates an entity with the specified mass, position and velocity which
+    /// is subject to forces.
+    fn create_body(world: &mut World, mass: f64, position: Vector, velocity: Vector) -> Entity {
+        world.create_entity()
+            .with(Dynamics { position, velocity, ..Dynamics::default() })
+            .with(Forces::default())
+            .with(Lifetime::default())
+            .with(Mass(mass))
+            .build()
+    }
+
+    /// Creates a pair of equal-mass bodies on a circular orbit about the
+    /// origin (for a gravitational constant of one).
+    fn create_binary(world: &mut World) -> (Entity, Entity) {
+        (
+            create_body(world, 1.0, Vector(1.0, 0.0, 0.0), Vector(0.0, 0.5, 0.0)),
+            create_body(world, 1.0, Vector(-1.0, 0.0, 0.0), Vector(0.0, -0.5, 0.0))
+        )
+    }
+
+    /// Returns the absolute change in the total energy of a circular binary
+    /// over 1000 steps with the specified integrator.
+    fn binary_energy_drift(integrator: resources::Integrator) -> f64 {
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.01));
+        world.insert(resources::ElectrostaticConstant(1.0));
+        world.insert(integrator);
+        create_binary(&mut world);
+        simulate(&mut world, 0);
+        let initial = crate::helper::total_energy(&world);
+        simulate(&mut world, 1000);
+        (crate::helper::total_energy(&world) - initial).abs()
+    }
+
+    #[test]
+    fn velocity_verlet_drifts_far_less_than_euler() {
+        let euler = binary_energy_drift(resources::Integrator::Euler);
+        let verlet = binary_energy_drift(resources::Integrator::VelocityVerlet);
+        assert!(verlet < 0.01 * euler, "{} vs {}", verlet, euler);
+    }
+
+    #[test]
+    fn constant_off_center_force_gives_steady_angular_acceleration() {
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.1));
+        world.insert(resources::OrientationLimits::default());
+        let (mass, shape) = (2.0, Shape::Sphere(0.5));
+        let torque = Vector(0.5, 0.0, 0.0).cross(Vector(0.0, 3.0, 0.0));
+        let expected = torque / shape.moment_of_inertia(mass);
+        let entity = world.create_entity()
+            .with(Mass(mass))
+            .with(Orientation { angular_position: Vector::unit_x(), ..Orientation::default() })
+            .with(Physicality { collisions_enabled: false, shape })
+            .with(Torques::default())
+            .build();
+        for step in 1..=10 {
+            ClearForces.run_now(&world);
+            world.write_storage::<Torques>().get_mut(entity).unwrap().0.insert(ForceSource::Interactive, torque);
+            HandleTorques.run_now(&world);
+            HandleOrientation.run_now(&world);
+            let orientations = world.read_storage::<Orientation>();
+            let o = orientations.get(entity).unwrap();
+            assert!(o.angular_acceleration.approx_eq(expected, 1e-12));
+            assert!(o.angular_velocity.approx_eq(expected * (0.1 * step as f64), 1e-9));
+        }
+    }
+
+    /// Returns the ratio of the spin period to the orbital period of the
+    /// satellite in the tidally-locked scenario after simulating the specified
+    /// number of steps with the specified tidal torque strength.
+    fn tidal_period_ratio(strength: f64, steps: usize) -> f64 {
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.1));
+        world.insert(resources::GravitationalConstant(1.0));
+        world.insert(resources::IdCounter::default());
+        world.insert(resources::TidalLocking { strength, ..resources::TidalLocking::default() });
+        crate::helper::populate_tidally_locked(&mut world, 50.0, 5.0);
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(ClearForces, "clear_forces", &[])
+            .with(HandleGravity, "handle_gravity", &["clear_forces"])
+            .with(HandleTidalTorque, "handle_tidal_torque", &["clear_forces"])
+            .with(HandleForces, "handle_forces", &["handle_gravity"])
+            .with(HandleTorques, "handle_torques", &["handle_tidal_torque"])
+            .with(HandleDynamics, "handle_dynamics", &["handle_forces"])
+            .with(HandleOrientation, "handle_orientation", &["handle_torques"])
+            .build();
+        dispatcher.setup(&mut world);
+        for _ in 0..steps {
+            dispatcher.dispatch(&world);
+            world.maintain();
+        }
+        let locking = world.read_resource::<resources::TidalLocking>();
+        locking.spin_period.unwrap() / locking.orbital_period.unwrap()
+    }
+
+    #[test]
+    fn tidal_torque_drives_the_spin_period_toward_the_orbital_period() {
+        assert!((tidal_period_ratio(0.0, 2000) - 0.2).abs() < 0.01);
+        let early = tidal_period_ratio(0.05, 200);
+        let late = tidal_period_ratio(0.05, 2000);
+        assert!(early > 0.2 && early < late, "{} vs {}", early, late);
+        assert!((late - 1.0).abs() < 0.01, "{}", late);
+    }
+
+    /// Returns the change in the total energy (including the energy dissipated
+    /// by merges) over two steps of a fast head-on collision between a pair of
+    /// spheres, resolved with the specified number of collision substeps.
+    ///
+    /// The spheres pass through each other within the first step, so a single
+    /// substep misses their collision and the next step kicks them with the
+    /// force at close range.
+    fn head_on_collision_energy_error(substeps: u32) -> f64 {
+        let mut world = world();
+        world.insert(resources::CollisionLimits { maximum_detection_theshold: 100.0, minimum_detection_theshold: 0.0 });
+        world.insert(resources::CollisionSubsteps(substeps));
+        world.insert(resources::ElectrostaticConstant(1.0));
+        for x in [-4.4875, 4.4875] {
+            let entity = create_body(&mut world, 1.0, Vector(x, 0.0, 0.0), Vector(-5.0 * x.signum(), 0.0, 0.0));
+            world.write_storage::<Collisions>().insert(entity, Collisions::default()).unwrap();
+            world.write_storage::<Physicality>().insert(entity, Physicality { collisions_enabled: true, shape: Shape::Sphere(0.5) }).unwrap();
+        }
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(ClearForces, "clear_forces", &[])
+            .with(HandleGravity, "handle_gravity", &["clear_forces"])
+            .with(HandleForces, "handle_forces", &["handle_gravity"])
+            .build();
+        let mut substep_dispatcher = DispatcherBuilder::new()
+            .with(ClearCollisions, "clear_collisions", &[])
+            .with(HandleDynamics, "handle_dynamics", &[])
+            .with(CollisionDetection, "collision_detection", &["clear_collisions", "handle_dynamics"])
+            .with(HandleCollisions, "handle_collisions", &["collision_detection"])
+            .build();
+        dispatcher.setup(&mut world);
+        substep_dispatcher.setup(&mut world);
+        let initial = crate::helper::total_energy(&world);
+        for _ in 0..2 {
+            dispatcher.dispatch(&world);
+            for _ in 0..substeps {
+                substep_dispatcher.dispatch(&world);
+                world.maintain();
+            }
+        }
+        let dissipated = world.read_resource::<resources::TotalDissipatedEnergy>().0;
+        (crate::helper::total_energy(&world) + dissipated - initial).abs()
+    }
+
+    #[test]
+    fn collision_substeps_improve_energy_conservation_in_fast_collisions() {
+        let single = head_on_collision_energy_error(1);
+        let substepped = head_on_collision_energy_error(8);
+        assert!(substepped < 0.1 * single, "{} vs {}", substepped, single);
+    }
+
+    #[test]
+    fn uniform_density_feels_no_background_force() {
+        let mut world = world();
+        world.insert(resources::BackgroundDensity(2.0));
+        let n = BACKGROUND_GRID_RESOLUTION;
+        for i in 0..(n * n * n) {
+            let position = Vector((i / (n * n)) as f64, ((i / n) % n) as f64, (i % n) as f64);
+            create_body(&mut world, 1.0, position, Vector::default());
+        }
+        let mut background = HandleBackground;
+        System::setup(&mut background, &mut world);
+        background.run_now(&world);
+        for f in world.read_storage::<Forces>().join() {
+            assert_eq!(f.0.get(&ForceSource::Background), Some(&Vector::zero()));
+        }
+    }
+
+    #[test]
+    fn stirring_forces_are_absent_without_amplitude_and_correlated_with_it() {
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.1));
+        world.insert(resources::Stirring { amplitude: 0.0, correlation_time: 10.0 });
+        for _ in 0..200 {
+            create_body(&mut world, 1.0, Vector::default(), Vector::default());
+        }
+        let mut stirring = HandleStirring;
+        System::setup(&mut stirring, &mut world);
+        stirring.run_now(&world);
+        assert!(world.read_storage::<Forces>().join().all(|f| !f.0.contains_key(&ForceSource::Stirring)));
+        world.insert(resources::Stirring { amplitude: 2.0, correlation_time: 10.0 });
+        let sample = |world: &World| -> Vec<f64> {
+            HandleStirring.run_now(world);
+            world.read_storage::<Forces>().join()
+                .flat_map(|f| f.0[&ForceSource::Stirring].to_array().to_vec())
+                .collect()
+        };
+        let first = sample(&world);
+        let second = sample(&world);
+        let dot = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(x, y)| x * y).sum::<f64>();
+        let correlation = dot(&first, &second) / (dot(&first, &first) * dot(&second, &second)).sqrt();
+        assert!(correlation > 0.9, "{}", correlation);
+    }
+
+    #[test]
+    fn only_particles_given_escape_velocity_escape() {
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.001));
+        world.insert(resources::EscapeSettings { distance: 10.0, remove: false });
+        create_body(&mut world, 100.0, Vector::default(), Vector::default());
+        let escaping = create_body(&mut world, 1.0, Vector(1.0, 0.0, 0.0), Vector(20.0, 0.0, 0.0));
+        let bound = create_body(&mut world, 1.0, Vector(0.0, 2.0, 0.0), Vector(0.0, 0.0, 50.0_f64.sqrt()));
+        let mut escapes = HandleEscapes;
+        System::setup(&mut escapes, &mut world);
+        for _ in 0..10 {
+            simulate(&mut world, 100);
+            escapes.run_now(&world);
+        }
+        let escaped = world.read_storage::<Escaped>();
+        assert!(escaped.contains(escaping));
+        assert!(!escaped.contains(bound));
+    }
+
+    #[test]
+    fn larger_spheres_decelerate_faster_under_drag() {
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.01));
+        world.insert(resources::DragCoefficient(0.5));
+        let velocity = Vector(3.0, 0.0, 0.0);
+        let small = create_body(&mut world, 1.0, Vector::default(), velocity);
+        let large = create_body(&mut world, 1.0, Vector(0.0, 100.0, 0.0), velocity);
+        world.write_storage::<Physicality>().insert(small, Physicality { shape: Shape::Sphere(1.0), ..Physicality::default() }).unwrap();
+        world.write_storage::<Physicality>().insert(large, Physicality { shape: Shape::Sphere(2.0), ..Physicality::default() }).unwrap();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(ClearForces, "clear_forces", &[])
+            .with(HandleDrag, "handle_drag", &["clear_forces"])
+            .with(HandleForces, "handle_forces", &["handle_drag"])
+            .with(HandleDynamics, "handle_dynamics", &["handle_forces"])
+            .build();
+        dispatcher.setup(&mut world);
+        for _ in 0..10 {
+            dispatcher.dispatch(&world);
+            world.maintain();
+        }
+        let dynamics = world.read_storage::<Dynamics>();
+        let small_speed = dynamics.get(small).unwrap().velocity.magnitude();
+        let large_speed = dynamics.get(large).unwrap().velocity.magnitude();
+        assert!(small_speed < velocity.magnitude());
+        assert!(large_speed < small_speed, "{} vs {}", large_speed, small_speed);
+    }
+
+    #[test]
+    fn plasma_oscillates_at_the_plasma_frequency() {
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.1));
+        world.insert(resources::ElectrostaticConstant(1.0));
+        world.insert(resources::GravitationalConstant(0.0));
+        world.insert(resources::IdCounter::default());
+        let analytic = crate::helper::populate_plasma_oscillation(&mut world, 1, 8, 2.0, 0.1);
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(ClearForces, "clear_forces", &[])
+            .with(HandleElectrostatics, "handle_electrostatics", &["clear_forces"])
+            .with(HandleForces, "handle_forces", &["handle_electrostatics"])
+            .with(HandleDynamics, "handle_dynamics", &["handle_forces"])
+            .build();
+        let mut measure = MeasurePlasmaOscillation;
+        dispatcher.setup(&mut world);
+        System::setup(&mut measure, &mut world);
+        for step in 1..=80 {
+            world.insert(resources::CurrentStep(step));
+            dispatcher.dispatch(&world);
+            measure.run_now(&world);
+        }
+        let measured = world.read_resource::<resources::PlasmaOscillation>().measured_frequency().unwrap();
+        assert!((measured / analytic - 1.0).abs() < 0.2, "{} vs {}", measured, analytic);
+    }
+
+    #[test]
+    fn coincident_bodies_feel_finite_forces() {
+        let mut world = world();
+        for charge in [1.0, -2.0] {
+            let entity = create_body(&mut world, 1e6, Vector(1.0, 2.0, 3.0), Vector::default());
+            world.write_storage::<Charge>().insert(entity, Charge(charge)).unwrap();
+        }
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(ClearForces, "clear_forces", &[])
+            .with(HandleElectrostatics, "handle_electrostatics", &["clear_forces"])
+            .with(HandleGravity, "handle_gravity", &["clear_forces"])
+            .with(HandleForces, "handle_forces", &["handle_electrostatics", "handle_gravity"])
+            .build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+        for d in world.read_storage::<Dynamics>().join() {
+            assert!(d.acceleration.to_array().iter().all(|a| a.is_finite()));
+        }
+    }
+
+    #[test]
+    fn softening_bounds_the_force_between_nearly_coincident_masses() {
+        let softening = 0.1;
+        let mass = 1e6;
+        let mut world = world();
+        world.insert(resources::GravitationalSoftening(softening));
+        let a = create_body(&mut world, mass, Vector(1.0, 2.0, 3.0), Vector::default());
+        create_body(&mut world, mass, Vector(1.0 + 1e-12, 2.0, 3.0), Vector::default());
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(ClearForces, "clear_forces", &[])
+            .with(HandleGravity, "handle_gravity", &["clear_forces"])
+            .build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+        let forces = world.read_storage::<Forces>();
+        let force: Vector = forces.get(a).unwrap().0.values().sum();
+        let maximum = 2.0 / 27.0_f64.sqrt() * mass * mass / (softening * softening);
+        assert!(force.magnitude() > 0.0 && force.magnitude() <= maximum, "{:?}", force);
+    }
+
+    #[test]
+    fn fast_bodies_age_more_slowly_with_time_dilation() {
+        let mut world = world();
+        world.insert(resources::DynamicsLimits { maximum_velocity: 10.0, ..resources::DynamicsLimits::default() });
+        world.insert(resources::TimeDilation(true));
+        let stationary = create_body(&mut world, 1.0, Vector::default(), Vector::default());
+        let fast = create_body(&mut world, 1.0, Vector::default(), Vector(0.0, 9.95, 0.0));
+        let mut lifetimes = UpdateLifetimes;
+        System::setup(&mut lifetimes, &mut world);
+        for _ in 0..100 {
+            lifetimes.run_now(&world);
+        }
+        let storage = world.read_storage::<Lifetime>();
+        assert_eq!(storage.get(stationary).unwrap().0, 100);
+        let expected = (100.0 * (1.0 - 0.995f64.powi(2)).sqrt()).floor() as u128;
+        assert_eq!(storage.get(fast).unwrap().0, expected);
+    }
+
+    #[test]
+    fn probes_never_perturb_a_two_body_orbit() {
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.01));
+        let (a, b) = create_binary(&mut world);
+        let mut probed_world = self::world();
+        probed_world.insert(resources::DeltaTime(0.01));
+        let (probed_a, probed_b) = create_binary(&mut probed_world);
+        let probe = create_body(&mut probed_world, 1.0, Vector(0.0, 3.0, 0.0), Vector::default());
+        probed_world.write_storage::<Probe>().insert(probe, Probe).unwrap();
+        simulate(&mut world, 500);
+        simulate(&mut probed_world, 500);
+        let dynamics = world.read_storage::<Dynamics>();
+        let probed_dynamics = probed_world.read_storage::<Dynamics>();
+        for (entity, probed_entity) in [(a, probed_a), (b, probed_b)] {
+            assert_eq!(dynamics.get(entity).unwrap().position, probed_dynamics.get(probed_entity).unwrap().position);
+            assert_eq!(dynamics.get(entity).unwrap().velocity, probed_dynamics.get(probed_entity).unwrap().velocity);
+        }
+        assert!(probed_dynamics.get(probe).unwrap().position.1 < 3.0);
+    }
+
+    #[test]
+    fn only_splits_require_maintenance() {
+        let mut world = world();
+        let entity = create_body(&mut world, 1.0, Vector::default(), Vector::default());
+        let mut splitting = HandleSplitting;
+        System::setup(&mut splitting, &mut world);
+        splitting.run_now(&world);
+        assert!(!world.read_resource::<resources::MaintenanceRequired>().0);
+        world.write_storage::<Lifetime>().insert(entity, Lifetime(10_000)).unwrap();
+        splitting.run_now(&world);
+        assert!(world.read_resource::<resources::MaintenanceRequired>().0);
+    }
+
+    #[test]
+    fn probes_never_split() {
+        let mut world = world();
+        let probe = create_body(&mut world, 100.0, Vector::default(), Vector::default());
+        world.write_storage::<Lifetime>().insert(probe, Lifetime(10_000)).unwrap();
+        world.write_storage::<Probe>().insert(probe, Probe).unwrap();
+        let mut splitting = HandleSplitting;
+        System::setup(&mut splitting, &mut world);
+        splitting.run_now(&world);
+        world.maintain();
+        assert_eq!(world.entities().join().count(), 1);
+        assert!(world.entities().is_alive(probe));
+    }
+
+    #[test]
+    fn colliding_bodies_eligible_to_split_conserve_mass() {
+        let mut world = world();
+        let a = create_body(&mut world, 30.0, Vector::default(), Vector(1.0, 0.0, 0.0));
+        let b = create_body(&mut world, 50.0, Vector(0.5, 0.0, 0.0), Vector::default());
+        let c = create_body(&mut world, 20.0, Vector(10.0, 0.0, 0.0), Vector::default());
+        for entity in [a, b, c] {
+            world.write_storage::<Lifetime>().insert(entity, Lifetime(10_000)).unwrap();
+        }
+        world.write_storage::<Collisions>().insert(a, Collisions(vec![b])).unwrap();
+        world.write_storage::<Collisions>().insert(b, Collisions(vec![a])).unwrap();
+        let mut collisions = HandleCollisions;
+        let mut splitting = HandleSplitting;
+        System::setup(&mut collisions, &mut world);
+        System::setup(&mut splitting, &mut world);
+        collisions.run_now(&world);
+        world.maintain();
+        splitting.run_now(&world);
+        world.maintain();
+        let masses: Vec<f64> = world.read_storage::<Mass>().join().map(|m| m.0).collect();
+        assert_eq!(masses.len(), 3);
+        assert!((masses.iter().sum::<f64>() - 100.0).abs() < 1e-12);
+    }
+
+    /// Returns the net gravitational force on each body of a small, jittered
+    /// lattice of bodies, as computed with the specified method and precision.
+    fn cluster_gravity(method: resources::GravityMethod, precision: resources::ForcePrecision) -> Vec<Vector> {
+        let mut world = world();
+        world.insert(method);
+        world.insert(precision);
+        for i in 0..64 {
+            let jitter = Vector(((i * 37) % 17) as f64, ((i * 53) % 19) as f64, ((i * 71) % 23) as f64) / 40.0;
+            let position = Vector((i % 4) as f64, ((i / 4) % 4) as f64, (i / 16) as f64) + jitter;
+            create_body(&mut world, 1.0 + (i % 3) as f64, position, Vector::default());
+        }
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(ClearForces, "clear_forces", &[])
+            .with(HandleGravity, "handle_gravity", &["clear_forces"])
+            .build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+        let forces = world.read_storage::<Forces>();
+        forces.join().map(|f| f.0.values().sum()).collect()
+    }
+
+    #[test]
+    fn barnes_hut_gravity_matches_brute_force() {
+        let net_forces = | method | cluster_gravity(method, resources::ForcePrecision::Double);
+        let exact = net_forces(resources::GravityMethod::BruteForce);
+        let approximate = net_forces(resources::GravityMethod::BarnesHut { theta: 0.5 });
+        let scale = exact.iter().fold(0.0, | acc: f64, f | acc.max(f.magnitude()));
+        for (e, a) in exact.iter().zip(approximate.iter()) {
+            assert!(e.approx_eq(*a, 0.02 * scale), "{:?} vs {:?}", e, a);
+        }
+        let opened = net_forces(resources::GravityMethod::BarnesHut { theta: 0.0 });
+        for (e, o) in exact.iter().zip(opened.iter()) {
+            assert!(e.approx_eq(*o, 1e-9 * scale));
+        }
+    }
+
+    #[test]
+    fn gravity_is_keyed_correctly_by_recycled_entities() {
+        let mut world = world();
+        for _ in 0..1000 {
+            let entity = world.create_entity().build();
+            world.delete_entity(entity).unwrap();
+            world.maintain();
+        }
+        let a = create_body(&mut world, 2.0, Vector::default(), Vector::default());
+        let b = create_body(&mut world, 3.0, Vector(2.0, 0.0, 0.0), Vector::default());
+        assert!(a.gen().id() > 1000 || b.gen().id() > 1000);
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(ClearForces, "clear_forces", &[])
+            .with(HandleGravity, "handle_gravity", &["clear_forces"])
+            .build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+        let forces = world.read_storage::<Forces>();
+        let on_a = forces.get(a).unwrap();
+        let on_b = forces.get(b).unwrap();
+        assert_eq!(on_a.0.len(), 1);
+        assert_eq!(on_b.0.len(), 1);
+        assert!(on_a.0[&ForceSource::Gravity(b)].approx_eq(Vector(1.5, 0.0, 0.0), 1e-12));
+        assert!(on_b.0[&ForceSource::Gravity(a)].approx_eq(Vector(-1.5, 0.0, 0.0), 1e-12));
+    }
+
+    #[test]
+    fn stale_forces_from_deleted_sources_are_ignored() {
+        let mut world = world();
+        let a = create_body(&mut world, 1.0, Vector::default(), Vector::default());
+        let b = create_body(&mut world, 1.0, Vector(1.0, 0.0, 0.0), Vector::default());
+        let mut gravity = HandleGravity;
+        let mut forces = HandleForces;
+        System::setup(&mut gravity, &mut world);
+        System::setup(&mut forces, &mut world);
+        gravity.run_now(&world);
+        forces.run_now(&world);
+        assert!(world.read_storage::<Dynamics>().get(a).unwrap().acceleration.approx_eq(Vector(1.0, 0.0, 0.0), 1e-12));
+        world.delete_entity(b).unwrap();
+        world.maintain();
+        forces.run_now(&world);
+        assert!(world.read_storage::<Forces>().get(a).unwrap().0.contains_key(&ForceSource::Gravity(b)));
+        assert_eq!(world.read_storage::<Dynamics>().get(a).unwrap().acceleration, Vector::zero());
+    }
+
+    #[test]
+    fn symmetric_and_independent_gravity_agree() {
+        let net_forces = | symmetry | {
+            let mut world = world();
+            world.insert(symmetry);
+            for i in 0..8 {
+                let position = Vector((i % 2) as f64, ((i / 2) % 2) as f64, (i / 4) as f64) + Vector(0.1 * i as f64, 0.0, -0.05 * i as f64);
+                create_body(&mut world, 1.0 + i as f64, position, Vector::default());
+            }
+            let mut dispatcher = DispatcherBuilder::new()
+                .with(ClearForces, "clear_forces", &[])
+                .with(HandleGravity, "handle_gravity", &["clear_forces"])
+                .build();
+            dispatcher.setup(&mut world);
+            dispatcher.dispatch(&world);
+            let forces = world.read_storage::<Forces>();
+            forces.join().map(|f| f.0.values().sum()).collect::<Vec<Vector>>()
+        };
+        let symmetric = net_forces(resources::ForceSymmetry::Symmetric);
+        let independent = net_forces(resources::ForceSymmetry::Independent);
+        for (s, i) in symmetric.iter().zip(independent.iter()) {
+            assert!(s.approx_eq(*i, 1e-12 * s.magnitude().max(1.0)), "{:?} vs {:?}", s, i);
+        }
+    }
+
+    /// Returns the net gravitational force on each body of a planar cluster in
+    /// two dimensions, computed with the specified method.
+    fn planar_cluster_gravity(method: resources::GravityMethod) -> Vec<Vector> {
+        let mut world = world();
+        world.insert(method);
+        world.insert(resources::Dimensionality::Two);
+        for i in 0..64 {
+            let jitter = Vector(((i * 37) % 17) as f64, ((i * 53) % 19) as f64, 0.0) / 40.0;
+            let position = Vector((i % 8) as f64, (i / 8) as f64, 0.0) + jitter;
+            create_body(&mut world, 1.0 + (i % 3) as f64, position, Vector::default());
+        }
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(ClearForces, "clear_forces", &[])
+            .with(HandleGravity, "handle_gravity", &["clear_forces"])
+            .build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+        let forces = world.read_storage::<Forces>();
+        forces.join().map(|f| f.0.values().sum()).collect()
+    }
+
+    #[test]
+    fn quadtree_gravity_matches_brute_force_in_two_dimensions() {
+        let exact = planar_cluster_gravity(resources::GravityMethod::BruteForce);
+        let approximate = planar_cluster_gravity(resources::GravityMethod::BarnesHut { theta: 0.5 });
+        let scale = exact.iter().fold(0.0, | acc: f64, f | acc.max(f.magnitude()));
+        for (e, a) in exact.iter().zip(approximate.iter()) {
+            assert_eq!(a.2, 0.0);
+            assert!(e.approx_eq(*a, 0.02 * scale), "{:?} vs {:?}", e, a);
+        }
+    }
+
+    #[test]
+    fn merged_bodies_do_not_split_within_the_cooldown() {
+        let mut world = world();
+        let settings = resources::SplittingSettings::default();
+        let cooldown = settings.cooldown;
+        world.insert(settings);
+        world.insert(resources::CurrentStep(1));
+        let a = create_body(&mut world, 50.0, Vector::default(), Vector::default());
+        let b = create_body(&mut world, 50.0, Vector(0.5, 0.0, 0.0), Vector::default());
+        for (entity, other) in [(a, b), (b, a)] {
+            world.write_storage::<Collisions>().insert(entity, Collisions(vec![other])).unwrap();
+            world.write_storage::<Lifetime>().insert(entity, Lifetime(500)).unwrap();
+        }
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(HandleCollisions, "handle_collisions", &[])
+            .build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+        world.maintain();
+        assert_eq!(world.entities().join().count(), 1);
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(UpdateLifetimes, "update_lifetimes", &[])
+            .with(HandleSplitting, "handle_splitting", &["update_lifetimes"])
+            .build();
+        dispatcher.setup(&mut world);
+        for step in 1..(cooldown + 1) {
+            world.insert(resources::CurrentStep(step));
+            dispatcher.dispatch(&world);
+            world.maintain();
+            assert_eq!(world.entities().join().count(), 1, "split at step {}", step);
+        }
+        world.insert(resources::CurrentStep(cooldown + 1));
+        dispatcher.dispatch(&world);
+        world.maintain();
+        assert_eq!(world.entities().join().count(), 2);
+    }
+
+    #[test]
+    fn merged_bodies_get_a_fresh_particle_id() {
+        let mut world = world();
+        world.insert(resources::IdCounter(10));
+        let a = create_body(&mut world, 3.0, Vector::default(), Vector::default());
+        let b = create_body(&mut world, 5.0, Vector(0.5, 0.0, 0.0), Vector::default());
+        for (entity, other, id) in [(a, b, 3), (b, a, 4)] {
+            world.write_storage::<Collisions>().insert(entity, Collisions(vec![other])).unwrap();
+            world.write_storage::<ParticleId>().insert(entity, ParticleId(id)).unwrap();
+        }
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(HandleCollisions, "handle_collisions", &[])
+            .build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+        world.maintain();
+        let ids: Vec<u64> = world.read_storage::<ParticleId>().join().map(|id| id.0).collect();
+        assert_eq!(ids, vec![10]);
+        assert_eq!(world.write_resource::<resources::IdCounter>().allocate(), 11);
+    }
+
+    #[test]
+    fn head_on_merges_dissipate_half_the_relative_kinetic_energy() {
+        let mut world = world();
+        let speed = 2.0;
+        let a = create_body(&mut world, 1.0, Vector::default(), Vector(speed, 0.0, 0.0));
+        let b = create_body(&mut world, 1.0, Vector(0.5, 0.0, 0.0), Vector(-speed, 0.0, 0.0));
+        world.write_storage::<Collisions>().insert(a, Collisions(vec![b])).unwrap();
+        world.write_storage::<Collisions>().insert(b, Collisions(vec![a])).unwrap();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(HandleCollisions, "handle_collisions", &[])
+            .build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+        world.maintain();
+        let relative_energy = 0.5 * 1.0 * (2.0 * speed).powi(2);
+        let dissipated = world.read_resource::<resources::TotalDissipatedEnergy>().0;
+        assert!((dissipated - 0.5 * relative_energy).abs() < 1e-12, "{}", dissipated);
+    }
+
+    #[test]
+    fn merged_bodies_list_their_inputs_as_parents_in_the_output() {
+        let path = temporary_path("lineage.yaml");
+        let mut world = world();
+        world.insert(resources::IdCounter(10));
+        world.insert(resources::OutputFile(path.clone()));
+        world.insert(resources::OutputSettings { from_step: None, lineage: true, to_step: None, tracers_only: false });
+        let a = create_body(&mut world, 3.0, Vector::default(), Vector::default());
+        let b = create_body(&mut world, 5.0, Vector(0.5, 0.0, 0.0), Vector::default());
+        for (entity, other, id) in [(a, b, 3), (b, a, 4)] {
+            world.write_storage::<Charge>().insert(entity, Charge(0.0)).unwrap();
+            world.write_storage::<Collisions>().insert(entity, Collisions(vec![other])).unwrap();
+            world.write_storage::<ParticleId>().insert(entity, ParticleId(id)).unwrap();
+        }
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(HandleCollisions, "handle_collisions", &[])
+            .with(WriteOutput, "write_output", &["handle_collisions"])
+            .build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+        world.maintain();
+        dispatcher.dispatch(&world);
+        world.write_resource::<OutputWriter<OutputEntry>>().finish().unwrap();
+        let entries = read_entries(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let merged = &entries.last().unwrap().entities;
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].id, 10);
+        let mut parents = merged[0].parents.clone();
+        parents.sort();
+        assert_eq!(parents, vec![3, 4]);
+    }
+
+    #[test]
+    fn merging_like_charges_never_exceeds_the_maximum_charge() {
+        let mut world = world();
+        world.insert(resources::ChargeLimits { maximum_charge: 5.0, minimum_charge: -5.0 });
+        let bodies: Vec<Entity> = (0..10)
+            .map(|i| create_body(&mut world, 1.0, Vector(0.1 * i as f64, 0.0, 0.0), Vector::default()))
+            .collect();
+        for &entity in &bodies {
+            let others = bodies.iter().copied().filter(|other| *other != entity).collect();
+            world.write_storage::<Charge>().insert(entity, Charge(1.0)).unwrap();
+            world.write_storage::<Collisions>().insert(entity, Collisions(others)).unwrap();
+        }
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(HandleCollisions, "handle_collisions", &[])
+            .build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+        world.maintain();
+        let charges: Vec<f64> = world.read_storage::<Charge>().join().map(|c| c.0).collect();
+        assert_eq!(charges, vec![5.0]);
+    }
+
+    #[test]
+    fn preserved_merges_keep_the_heaviest_identity_and_restart_its_lifetime() {
+        let mut world = world();
+        world.insert(resources::MergePreservesIdentity(true));
+        let light = create_body(&mut world, 3.0, Vector::default(), Vector::default());
+        let heavy = create_body(&mut world, 5.0, Vector(0.5, 0.0, 0.0), Vector::default());
+        for (entity, other, id) in [(light, heavy, 7), (heavy, light, 9)] {
+            world.write_storage::<Collisions>().insert(entity, Collisions(vec![other])).unwrap();
+            world.write_storage::<Lifetime>().insert(entity, Lifetime(500)).unwrap();
+            world.write_storage::<ParticleId>().insert(entity, ParticleId(id)).unwrap();
+        }
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(HandleCollisions, "handle_collisions", &[])
+            .build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+        world.maintain();
+        assert!(!world.entities().is_alive(light));
+        assert!(world.entities().is_alive(heavy));
+        assert_eq!(world.read_storage::<ParticleId>().get(heavy).unwrap().0, 9);
+        assert_eq!(world.read_storage::<Parents>().get(heavy).unwrap().0, vec![7]);
+        assert_eq!(world.read_storage::<Mass>().get(heavy).unwrap().0, 8.0);
+        assert_eq!(world.read_storage::<Lifetime>().get(heavy).unwrap().0, 0);
+    }
+
+    #[test]
+    fn grid_collision_pairs_match_brute_force_for_clustered_and_isolated_bodies() {
+        let mut world = world();
+        let limits = resources::CollisionLimits { maximum_detection_theshold: 3.0, minimum_detection_theshold: 0.0 };
+        let centers = [Vector::zero(), Vector(-40.0, 25.0, -7.5), Vector(2.9, -3.1, 3.05)];
+        let mut bodies = Vec::new();
+        for i in 0..150 {
+            let offset = Vector(((i * 37) % 29) as f64, ((i * 53) % 31) as f64, ((i * 71) % 23) as f64) / 8.0 - Vector::splat(1.75);
+            let position = match i % 10 {
+                9 => Vector(i as f64 * 100.0, -(i as f64) * 50.0, 0.0),
+                _ => centers[i % 3] + offset
+            };
+            let radius = 0.2 + ((i % 4) as f64 / 4.0);
+            let entity = create_body(&mut world, 1.0, position, Vector::default());
+            world.write_storage::<Collisions>().insert(entity, Collisions::default()).unwrap();
+            world.write_storage::<Physicality>().insert(entity, Physicality { collisions_enabled: true, shape: Shape::Sphere(radius) }).unwrap();
+            bodies.push((entity, position, radius));
+        }
+        let mut expected = std::collections::HashSet::new();
+        for (i, (i_entity, i_position, i_radius)) in bodies.iter().enumerate() {
+            for (j_entity, j_position, j_radius) in bodies.iter().skip(i + 1) {
+                if i_position.distance(*j_position) <= i_radius + j_radius {
+                    expected.insert((*i_entity, *j_entity));
+                    expected.insert((*j_entity, *i_entity));
+                }
+            }
+        }
+        assert!(!expected.is_empty());
+        world.insert(limits);
+        CollisionDetection.run_now(&world);
+        let collisions = world.read_storage::<Collisions>();
+        let detected: std::collections::HashSet<(Entity, Entity)> = (&world.entities(), &collisions).join()
+            .flat_map(|(entity, c)| c.0.iter().map(move |other| (entity, *other)))
+            .collect();
+        assert_eq!(detected, expected);
+    }
+
+    #[test]
+    fn collision_detection_never_misses_separating_pairs() {
+        let mut world = world();
+        let limits = resources::CollisionLimits::default();
+        let mut bodies = Vec::new();
+        for i in 0..200 {
+            let position = Vector(((i * 37) % 101) as f64, ((i * 53) % 89) as f64, ((i * 71) % 97) as f64) / 8.0;
+            let velocity = Vector::unit_x().rotate_around_axis(Vector::unit_z(), i as f64) * 3.0;
+            let radius = 0.25 + ((i % 5) as f64 / 4.0);
+            let entity = create_body(&mut world, 1.0, position, velocity);
+            world.write_storage::<Collisions>().insert(entity, Collisions::default()).unwrap();
+            world.write_storage::<Physicality>().insert(entity, Physicality { collisions_enabled: true, shape: Shape::Sphere(radius) }).unwrap();
+            bodies.push((entity, position, radius));
+        }
+        let mut expected = std::collections::HashSet::new();
+        for (i, (i_entity, i_position, i_radius)) in bodies.iter().enumerate() {
+            for (j_entity, j_position, j_radius) in bodies.iter().skip(i + 1) {
+                let dist = i_position.distance(*j_position);
+                if dist < limits.minimum_detection_theshold || dist <= i_radius + j_radius {
+                    expected.insert((*i_entity, *j_entity));
+                    expected.insert((*j_entity, *i_entity));
+                }
+            }
+        }
+        assert!(!expected.is_empty());
+        world.insert(limits);
+        CollisionDetection.run_now(&world);
+        let collisions = world.read_storage::<Collisions>();
+        let detected: std::collections::HashSet<(Entity, Entity)> = (&world.entities(), &collisions).join()
+            .flat_map(|(entity, c)| c.0.iter().map(move |other| (entity, *other)))
+            .collect();
+        assert_eq!(detected, expected);
+    }
+
+    #[test]
+    fn bounding_sphere_prefilter_never_misses_cuboid_collisions() {
+        let mut world = world();
+        let limits = resources::CollisionLimits::default();
+        let mut bodies = Vec::new();
+        for i in 0..150 {
+            let position = Vector(((i * 37) % 101) as f64, ((i * 53) % 89) as f64, ((i * 71) % 97) as f64) / 7.0;
+            let extents = Vector(0.25 + ((i % 5) as f64 / 4.0), 0.25 + ((i % 3) as f64 / 2.0), 0.25 + ((i % 7) as f64 / 6.0));
+            let entity = create_body(&mut world, 1.0, position, Vector::default());
+            world.write_storage::<Collisions>().insert(entity, Collisions::default()).unwrap();
+            world.write_storage::<Physicality>().insert(entity, Physicality { collisions_enabled: true, shape: Shape::Cuboid(extents.0, extents.1, extents.2) }).unwrap();
+            bodies.push((entity, position, extents));
+        }
+        let (mut expected, mut prefiltered) = (std::collections::HashSet::new(), 0);
+        for (i, (i_entity, i_position, i_extents)) in bodies.iter().enumerate() {
+            for (j_entity, j_position, j_extents) in bodies.iter().skip(i + 1) {
+                let dvec = *j_position - *i_position;
+                let sum = *i_extents + *j_extents;
+                let overlaps = dvec.0.abs() <= sum.0 && dvec.1.abs() <= sum.1 && dvec.2.abs() <= sum.2;
+                if dvec.magnitude() < limits.minimum_detection_theshold || overlaps {
+                    expected.insert((*i_entity, *j_entity));
+                    expected.insert((*j_entity, *i_entity));
+                }
+                if dvec.magnitude() <= i_extents.magnitude() + j_extents.magnitude() {
+                    prefiltered += 1;
+                }
+            }
+        }
+        assert!(!expected.is_empty());
+        assert!(prefiltered < bodies.len() * (bodies.len() - 1) / 10);
+        world.insert(limits);
+        CollisionDetection.run_now(&world);
+        let collisions = world.read_storage::<Collisions>();
+        let detected: std::collections::HashSet<(Entity, Entity)> = (&world.entities(), &collisions).join()
+            .flat_map(|(entity, c)| c.0.iter().map(move |other| (entity, *other)))
+            .collect();
+        assert_eq!(detected, expected);
+    }
+
+    #[test]
+    fn points_collide_with_cuboids_at_the_face_crossing() {
+        let mut world = world();
+        world.insert(resources::CollisionLimits { maximum_detection_theshold: 100.0, minimum_detection_theshold: 0.0 });
+        let point = create_body(&mut world, 1.0, Vector(3.0, 0.5, -0.5), Vector(-0.5, 0.0, 0.0));
+        let cuboid = create_body(&mut world, 1.0, Vector::default(), Vector::default());
+        for (entity, shape) in [(point, Shape::Point), (cuboid, Shape::Cuboid(1.0, 1.0, 1.0))] {
+            world.write_storage::<Collisions>().insert(entity, Collisions::default()).unwrap();
+            world.write_storage::<Physicality>().insert(entity, Physicality { collisions_enabled: true, shape }).unwrap();
+        }
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(ClearCollisions, "clear_collisions", &[])
+            .with(HandleDynamics, "handle_dynamics", &[])
+            .with(CollisionDetection, "collision_detection", &["clear_collisions", "handle_dynamics"])
+            .build();
+        dispatcher.setup(&mut world);
+        let mut first_collision = None;
+        for step in 1..=6 {
+            dispatcher.dispatch(&world);
+            let collisions = world.read_storage::<Collisions>();
+            if first_collision.is_none() && collisions.get(point).unwrap().0 == vec![cuboid] {
+                assert_eq!(collisions.get(cuboid).unwrap().0, vec![point]);
+                first_collision = Some((step, world.read_storage::<Dynamics>().get(point).unwrap().position.0));
+            }
+        }
+        assert_eq!(first_collision, Some((4, 1.0)));
+    }
+
+    #[test]
+    fn single_precision_gravity_matches_double_precision() {
+        let double = cluster_gravity(resources::GravityMethod::BruteForce, resources::ForcePrecision::Double);
+        let single = cluster_gravity(resources::GravityMethod::BruteForce, resources::ForcePrecision::Single);
+        let scale = double.iter().fold(0.0, | acc: f64, f | acc.max(f.magnitude()));
+        for (d, s) in double.iter().zip(single.iter()) {
+            assert!(d.approx_eq(*s, 1e-5 * scale), "{:?} vs {:?}", d, s);
+        }
+    }
+
+    #[test]
+    fn output_reference_particle_stays_at_the_origin() {
+        let path = temporary_path("output-reference.yaml");
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.01));
+        world.insert(resources::OutputFile(path.clone()));
+        world.insert(resources::OutputReference::RelativeTo(11));
+        let (a, b) = create_binary(&mut world);
+        for (entity, id) in [(a, 10), (b, 11)] {
+            world.write_storage::<Charge>().insert(entity, Charge(0.0)).unwrap();
+            world.write_storage::<ParticleId>().insert(entity, ParticleId(id)).unwrap();
+        }
+        let mut output = WriteOutput;
+        System::setup(&mut output, &mut world);
+        for step in 1..=20 {
+            simulate(&mut world, 1);
+            world.insert(resources::CurrentStep(step));
+            output.run_now(&world);
+        }
+        world.write_resource::<OutputWriter<OutputEntry>>().finish().unwrap();
+        let entries = read_entries(&path).unwrap();
+        assert_eq!(entries.len(), 20);
+        for entry in entries {
+            let reference = entry.entities.iter().find(|e| e.id == 11).unwrap();
+            let other = entry.entities.iter().find(|e| e.id == 10).unwrap();
+            assert_eq!(reference.position, Vector::zero().into());
+            assert_eq!(reference.velocity, Vector::zero().into());
+            assert!(Vector::from(other.position).magnitude() > 1.9);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resuming_from_output_continues_the_trajectory() {
+        let path = temporary_path("resume.yaml");
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.01));
+        world.insert(resources::OutputFile(path.clone()));
+        let (a, b) = create_binary(&mut world);
+        for (entity, id) in [(a, 10), (b, 11)] {
+            world.write_storage::<Charge>().insert(entity, Charge(0.0)).unwrap();
+            world.write_storage::<ParticleId>().insert(entity, ParticleId(id)).unwrap();
+        }
+        simulate(&mut world, 5);
+        world.insert(resources::CurrentStep(5));
+        let mut output = WriteOutput;
+        System::setup(&mut output, &mut world);
+        output.run_now(&world);
+        world.write_resource::<OutputWriter<OutputEntry>>().finish().unwrap();
+        let mut resumed = self::world();
+        resumed.insert(resources::DeltaTime(0.01));
+        resumed.insert(resources::IdCounter::default());
+        assert_eq!(crate::helper::populate_from_output(&mut resumed, &path).unwrap(), 5);
+        std::fs::remove_file(&path).unwrap();
+        simulate(&mut world, 5);
+        simulate(&mut resumed, 5);
+        let resumed_ids = resumed.read_storage::<ParticleId>();
+        let resumed_dynamics = resumed.read_storage::<Dynamics>();
+        for (id, d) in (&world.read_storage::<ParticleId>(), &world.read_storage::<Dynamics>()).join() {
+            let (_, r) = (&resumed_ids, &resumed_dynamics).join().find(|(r_id, _)| r_id.0 == id.0).unwrap();
+            assert!(d.position.approx_eq(r.position, 1e-12), "{:?} vs {:?}", d.position, r.position);
+            assert!(d.velocity.approx_eq(r.velocity, 1e-12), "{:?} vs {:?}", d.velocity, r.velocity);
+        }
+    }
+
+    #[test]
+    fn tracers_only_output_contains_exactly_the_tracers() {
+        let path = temporary_path("tracers.yaml");
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.01));
+        world.insert(resources::OutputFile(path.clone()));
+        world.insert(resources::OutputSettings { from_step: None, lineage: false, to_step: None, tracers_only: true });
+        for i in 0..20 {
+            let entity = create_body(&mut world, 1.0, Vector(i as f64, 0.0, 0.0), Vector::default());
+            world.write_storage::<Charge>().insert(entity, Charge(0.0)).unwrap();
+            world.write_storage::<ParticleId>().insert(entity, ParticleId(i)).unwrap();
+            if i == 4 || i == 13 {
+                world.write_storage::<Tracer>().insert(entity, Tracer).unwrap();
+            }
+        }
+        let mut output = WriteOutput;
+        System::setup(&mut output, &mut world);
+        for step in 1..=3 {
+            simulate(&mut world, 1);
+            world.insert(resources::CurrentStep(step));
+            output.run_now(&world);
+        }
+        world.write_resource::<OutputWriter<OutputEntry>>().finish().unwrap();
+        let entries = read_entries(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        for entry in entries {
+            let ids: Vec<u64> = entry.entities.iter().map(|e| e.id).collect();
+            assert_eq!(ids, vec![4, 13]);
+        }
+    }
+
+    #[test]
+    fn output_entries_carry_increasing_step_numbers() {
+        let path = temporary_path("steps.yaml");
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.01));
+        world.insert(resources::OutputFile(path.clone()));
+        for i in 0..2 {
+            let entity = create_body(&mut world, 1.0, Vector(i as f64, 0.0, 0.0), Vector::default());
+            world.write_storage::<Charge>().insert(entity, Charge(0.0)).unwrap();
+            world.write_storage::<ParticleId>().insert(entity, ParticleId(i)).unwrap();
+        }
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(WriteOutput, "write_output", &[])
+            .build();
+        dispatcher.setup(&mut world);
+        for step in 1..=5 {
+            world.write_resource::<resources::CurrentStep>().0 = step;
+            simulate(&mut world, 1);
+            dispatcher.dispatch(&world);
+        }
+        world.write_resource::<OutputWriter<OutputEntry>>().finish().unwrap();
+        let steps: Vec<u128> = read_entries(&path).unwrap().iter().map(|e| e.step).collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(steps, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn output_is_only_written_within_the_step_range() {
+        let path = temporary_path("range.yaml");
+        let mut world = world();
+        world.insert(resources::OutputFile(path.clone()));
+        world.insert(resources::OutputSettings { from_step: Some(40), lineage: false, to_step: Some(50), tracers_only: false });
+        let entity = create_body(&mut world, 1.0, Vector::default(), Vector::default());
+        world.write_storage::<Charge>().insert(entity, Charge(0.0)).unwrap();
+        world.write_storage::<ParticleId>().insert(entity, ParticleId(0)).unwrap();
+        let mut output = WriteOutput;
+        System::setup(&mut output, &mut world);
+        for step in 1..=100 {
+            world.insert(resources::CurrentStep(step));
+            output.run_now(&world);
+        }
+        world.write_resource::<OutputWriter<OutputEntry>>().finish().unwrap();
+        let steps: Vec<u128> = read_entries(&path).unwrap().iter().map(|e| e.step).collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(steps, (40..=50).collect::<Vec<u128>>());
+    }
+
+    /// Returns the two-point correlation function estimated for the specified
+    /// positions, with four bins out to a separation of two.
+    fn correlation(positions: &[Vector]) -> CorrelationEntry {
+        let path = temporary_path("correlation.yaml");
+        let mut world = world();
+        world.insert(resources::CorrelationSettings { bins: 4, file: path.clone(), interval: 1, maximum_separation: 2.0 });
+        for position in positions {
+            create_body(&mut world, 1.0, *position, Vector::default());
+        }
+        let mut correlation = WriteCorrelation;
+        System::setup(&mut correlation, &mut world);
+        correlation.run_now(&world);
+        world.write_resource::<CorrelationWriter>().finish().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        serde_yaml::from_str(&contents).unwrap()
+    }
+
+    #[test]
+    fn clustered_distributions_are_correlated_at_small_separations() {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        let mut uniform_point = || Vector(rng.gen_range(0.0, 20.0), rng.gen_range(0.0, 20.0), rng.gen_range(0.0, 20.0));
+        let uniform: Vec<Vector> = (0..1000).map(|_| uniform_point()).collect();
+        let clustered: Vec<Vector> = (0..20)
+            .flat_map(|_| {
+                let center = uniform_point();
+                (0..50).map(move |_| center + Vector::random(0.0, 0.5))
+            })
+            .collect();
+        let uniform = correlation(&uniform);
+        let clustered = correlation(&clustered);
+        assert!(uniform.bins[0].correlation.abs() < 1.0, "{:?}", uniform.bins);
+        assert!(clustered.bins[0].correlation > 10.0, "{:?}", clustered.bins);
+    }
+
+    #[test]
+    fn uniform_sphere_density_is_constant_out_to_its_radius() {
+        let path = temporary_path("profile.yaml");
+        let mut world = world();
+        world.insert(resources::ProfileSettings { bins: 10, file: path.clone(), interval: 1 });
+        let spacing = 0.1;
+        for i in -20..=20 {
+            for j in -20..=20 {
+                for k in -20..=20 {
+                    let position = Vector(i as f64, j as f64, k as f64) * spacing;
+                    if position.magnitude() <= 2.0 {
+                        create_body(&mut world, 1.0, position, Vector::default());
+                    }
+                }
+            }
+        }
+        create_body(&mut world, 1e-9, Vector(4.0, 0.0, 0.0), Vector::default());
+        let mut profile = WriteProfile;
+        System::setup(&mut profile, &mut world);
+        profile.run_now(&world);
+        world.write_resource::<ProfileWriter>().finish().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let entry: ProfileEntry = serde_yaml::from_str(&contents).unwrap();
+        let density = 1.0 / spacing.powi(3);
+        for shell in &entry.shells[..5] {
+            assert!((shell.density / density - 1.0).abs() < 0.1, "{}", shell.density);
+        }
+        for shell in &entry.shells[5..] {
+            assert!(shell.density / density < 0.01, "{}", shell.density);
+        }
+    }
+
+    #[test]
+    fn summary_axis_ratios_match_an_ellipsoid() {
+        let path = temporary_path("summary.yaml");
+        let mut world = world();
+        world.insert(resources::SummarySettings { file: path.clone(), interval: 1 });
+        let spacing = 0.15;
+        for i in -30..=30 {
+            for j in -15..=15 {
+                for k in -8..=8 {
+                    let p = Vector(i as f64, j as f64, k as f64) * spacing;
+                    if (p.0 / 4.0).powi(2) + (p.1 / 2.0).powi(2) + p.2.powi(2) <= 1.0 {
+                        let position = p.rotate_around_axis(Vector::unit_z(), 0.5).rotate_around_axis(Vector::unit_x(), 0.3);
+                        create_body(&mut world, 1.0, position + Vector(5.0, -3.0, 1.0), Vector::default());
+                    }
+                }
+            }
+        }
+        let mut summary = WriteSummary;
+        System::setup(&mut summary, &mut world);
+        summary.run_now(&world);
+        world.write_resource::<SummaryWriter>().finish().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let entry: SummaryEntry = serde_yaml::from_str(&contents).unwrap();
+        assert!((entry.intermediate_axis_ratio - 0.5).abs() < 0.01, "{}", entry.intermediate_axis_ratio);
+        assert!((entry.minor_axis_ratio - 0.25).abs() < 0.01, "{}", entry.minor_axis_ratio);
+    }
+
+    #[test]
+    fn binary_angular_momentum_is_conserved() {
+        let path = temporary_path("angular-momentum.yaml");
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.01));
+        world.insert(resources::ElectrostaticConstant(1.0));
+        world.insert(resources::SummarySettings { file: path.clone(), interval: 1 });
+        create_binary(&mut world);
+        let mut summary = WriteSummary;
+        System::setup(&mut summary, &mut world);
+        for step in 1..=10 {
+            simulate(&mut world, 100);
+            world.insert(resources::CurrentStep(step));
+            summary.run_now(&world);
+        }
+        world.write_resource::<SummaryWriter>().finish().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let entries: Vec<SummaryEntry> = serde_yaml::Deserializer::from_str(&contents)
+            .map(|document| serde::Deserialize::deserialize(document).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 10);
+        for entry in &entries {
+            assert!((entry.angular_momentum_about_origin.magnitude() - 1.0).abs() < 1e-9);
+            assert!((entry.angular_momentum_about_center_of_mass.magnitude() - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn clamped_energy_closes_the_energy_budget() {
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.001));
+        world.insert(resources::DynamicsLimits { maximum_velocity: 0.3, ..resources::DynamicsLimits::default() });
+        world.insert(resources::ElectrostaticConstant(1.0));
+        create_body(&mut world, 1.0, Vector(-1.0, 0.0, 0.0), Vector::default());
+        create_body(&mut world, 1.0, Vector(1.0, 0.0, 0.0), Vector::default());
+        simulate(&mut world, 0);
+        let initial = crate::helper::total_energy(&world);
+        simulate(&mut world, 2000);
+        let clamped = world.read_resource::<resources::TotalClampedEnergy>().0;
+        let error = crate::helper::total_energy(&world) + clamped - initial;
+        assert!(clamped > 0.1, "{}", clamped);
+        assert!(error.abs() < 0.01 * clamped, "{} vs {}", error, clamped);
+    }
+
+    /// Returns the speed of a body after each of 100 steps of a constant
+    /// acceleration under the specified velocity limit mode, along with the
+    /// total energy removed by clamping its velocity.
+    fn accelerated_speeds(mode: resources::VelocityLimitMode) -> (Vec<f64>, f64) {
+        let mut world = world();
+        world.insert(resources::DeltaTime(0.1));
+        world.insert(resources::DynamicsLimits { maximum_velocity: 2.0, ..resources::DynamicsLimits::default() });
+        world.insert(mode);
+        let entity = create_body(&mut world, 1.0, Vector::default(), Vector::default());
+        let mut dynamics = HandleDynamics;
+        System::setup(&mut dynamics, &mut world);
+        let speeds = (0..100).map(|_| {
+            world.write_storage::<Dynamics>().get_mut(entity).unwrap().acceleration = Vector(1.0, 0.0, 0.0);
+            dynamics.run_now(&world);
+            world.read_storage::<Dynamics>().get(entity).unwrap().velocity.magnitude()
+        }).collect();
+        let clamped = world.read_resource::<resources::TotalClampedEnergy>().0;
+        (speeds, clamped)
+    }
+
+    #[test]
+    fn soft_drag_approaches_the_speed_cap_without_discarding_energy() {
+        let (hard, hard_clamped) = accelerated_speeds(resources::VelocityLimitMode::HardScale);
+        let (soft, soft_clamped) = accelerated_speeds(resources::VelocityLimitMode::SoftDrag);
+        assert!(hard_clamped > 0.0);
+        assert_eq!(soft_clamped, 0.0);
+        assert!(soft.iter().all(|v| *v < 2.0));
+        assert!(soft[99] > 1.9, "{}", soft[99]);
+        let increments: Vec<f64> = soft.windows(2).map(|w| w[1] - w[0]).collect();
+        assert!(increments.windows(2).all(|w| w[1] <= w[0] && w[1] > 0.0));
+        assert_eq!(hard[99], 2.0);
+    }
+
+    #[test]
+    fn boundary_reflection_conserves_speed() {
+        let mut world = world();
+        world.insert(resources::DynamicsLimits { maximum_position: 10.0, ..resources::DynamicsLimits::default() });
+        let velocity = Vector(1.0, 0.5, 0.0);
+        let entity = create_body(&mut world, 1.0, Vector(9.9, 0.0, 0.0), velocity);
+        simulate(&mut world, 1);
+        let dynamics = world.read_storage::<Dynamics>();
+        let d = dynamics.get(entity).unwrap();
+        assert!((d.position.magnitude() - 10.0).abs() < 1e-12);
+        assert!((d.velocity.magnitude() - velocity.magnitude()).abs() < 1e-12);
+        assert!(d.velocity.dot(d.position) < 0.0);
+    }
+
+
+    #[test]
+    fn merged_bodies_inherit_anchoring_immortality_and_group() {
+        let mut world = world();
+        world.register::<Anchored>();
+        world.register::<Group>();
+        world.register::<Immortal>();
+        let sun = create_body(&mut world, 1.0, Vector(2.0, 0.0, 0.0), Vector::default());
+        let planet = create_body(&mut world, 5.0, Vector::default(), Vector(1.0, 0.0, 0.0));
+        world.write_storage::<Anchored>().insert(sun, Anchored).unwrap();
+        world.write_storage::<Immortal>().insert(sun, Immortal).unwrap();
+        world.write_storage::<Group>().insert(planet, Group("planets".to_string())).unwrap();
+        world.write_storage::<Collisions>().insert(sun, Collisions(vec![planet])).unwrap();
+        world.write_storage::<Collisions>().insert(planet, Collisions(vec![sun])).unwrap();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(HandleCollisions, "handle_collisions", &[])
+            .build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+        world.maintain();
+        let merged = world.entities().join().next().unwrap();
+        assert_eq!(world.entities().join().count(), 1);
+        assert!(merged != sun && merged != planet);
+        assert!(world.read_storage::<Anchored>().contains(merged));
+        assert!(world.read_storage::<Immortal>().contains(merged));
+        assert_eq!(world.read_storage::<Group>().get(merged).unwrap().0, "planets");
+        assert_eq!(world.read_storage::<Dynamics>().get(merged).unwrap().position, Vector(2.0, 0.0, 0.0));
+    }
+
+
+    #[test]
+    fn seeded_stirring_is_reproducible() {
+        let stir = | seed: u64 | {
+            let mut world = world();
+            world.insert(resources::DeltaTime(0.1));
+            world.insert(resources::SharedRng::seeded(seed));
+            world.insert(resources::Stirring { amplitude: 2.0, correlation_time: 10.0 });
+            for _ in 0..10 {
+                create_body(&mut world, 1.0, Vector::default(), Vector::default());
+            }
+            let mut stirring = HandleStirring;
+            System::setup(&mut stirring, &mut world);
+            for _ in 0..5 {
+                stirring.run_now(&world);
+            }
+            let forces = world.read_storage::<Forces>();
+            forces.join().map(|f| f.0[&ForceSource::Stirring]).collect::<Vec<Vector>>()
+        };
+        assert_eq!(stir(1), stir(1));
+        assert_ne!(stir(1), stir(2));
     }
 }
@@ -0,0 +1,155 @@
+//! Contains a uniform spatial hash grid used to find nearby pairs of points
+//! without comparing every pair.
+
+use crate::math::*;
+use std::collections::HashMap;
+
+
+/// Represents a uniform spatial hash grid over a set of points.
+///
+/// Points are binned into cubic cells of a fixed size, so that every point
+/// within one cell size of a given position lies in the cell containing that
+/// position or in one of its 26 neighbors.
+#[derive(Clone, Debug)]
+pub struct SpatialHash {
+    /// The points (by index) contained in each non-empty cell.
+    cells: HashMap<(i64, i64, i64), Vec<usize>>,
+
+    /// The side length of each cell.
+    cell_size: f64
+}
+
+impl SpatialHash {
+    /// Builds a new spatial hash grid with the specified cell size over the
+    /// specified points.
+    pub fn new(cell_size: f64, points: &[Vector]) -> SpatialHash {
+        let mut grid = SpatialHash {
+            cells: HashMap::new(),
+            cell_size
+        };
+        for (i, p) in points.iter().enumerate() {
+            grid.cells.entry(grid.cell(*p)).or_default().push(i);
+        }
+        grid
+    }
+
+    /// Returns the coordinates of the cell containing the specified position.
+    fn cell(&self, position: Vector) -> (i64, i64, i64) {
+        (
+            (position.0 / self.cell_size).floor() as i64,
+            (position.1 / self.cell_size).floor() as i64,
+            (position.2 / self.cell_size).floor() as i64
+        )
+    }
+
+    /// Returns the (sorted) indices of all points in the cell containing the
+    /// specified position or in any neighboring cell.
+    pub fn neighbors(&self, position: Vector) -> Vec<usize> {
+        let (x, y, z) = self.cell(position);
+        let mut indices = Vec::new();
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    if let Some(cell) = self.cells.get(&(x + dx, y + dy, z + dz)) {
+                        indices.extend(cell);
+                    }
+                }
+            }
+        }
+        indices.sort_unstable();
+        indices
+    }
+
+    /// Returns the distance from the point with the specified index to the
+    /// nearest other point, or `None` if there is no other point. The points
+    /// must be those the grid was built over.
+    ///
+    /// Cells are searched in cubic shells of increasing size around the cell
+    /// containing the point, stopping once no unsearched cell can contain a
+    /// nearer point, or searching every cell once that is cheaper.
+    pub fn nearest_distance(&self, points: &[Vector], index: usize) -> Option<f64> {
+        let position = points[index];
+        let (x, y, z) = self.cell(position);
+        let mut nearest = f64::INFINITY;
+        let closest = | cell: &Vec<usize> | -> f64 {
+            cell.iter()
+                .filter(|j| **j != index)
+                .fold(f64::INFINITY, |acc, j| acc.min(position.distance_squared(points[*j])))
+        };
+        let mut shell: i64 = 0;
+        loop {
+            let side = (2 * shell + 1) as usize;
+            if side * side * side > self.cells.len() {
+                nearest = self.cells.values().fold(nearest, |acc, cell| acc.min(closest(cell)));
+                break;
+            }
+            for dx in -shell..=shell {
+                for dy in -shell..=shell {
+                    for dz in -shell..=shell {
+                        if dx.abs().max(dy.abs()).max(dz.abs()) != shell {
+                            continue;
+                        }
+                        if let Some(cell) = self.cells.get(&(x + dx, y + dy, z + dz)) {
+                            nearest = nearest.min(closest(cell));
+                        }
+                    }
+                }
+            }
+            let searched = shell as f64 * self.cell_size;
+            if nearest <= searched * searched {
+                break;
+            }
+            shell += 1;
+        }
+        match nearest.is_finite() {
+            true => Some(nearest.sqrt()),
+            false => None
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the points of a cubic lattice with the specified number of
+    /// points along each side and the specified spacing.
+    fn lattice(side: usize, spacing: f64) -> Vec<Vector> {
+        (0..side * side * side)
+            .map(|i| Vector((i % side) as f64, ((i / side) % side) as f64, (i / (side * side)) as f64) * spacing)
+            .collect()
+    }
+
+    #[test]
+    fn nearest_distance_on_a_lattice_is_the_spacing() {
+        let points = lattice(6, 2.0);
+        for cell_size in [0.7, 2.0, 5.0] {
+            let grid = SpatialHash::new(cell_size, &points);
+            for i in 0..points.len() {
+                assert_eq!(grid.nearest_distance(&points, i), Some(2.0));
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_distance_matches_brute_force() {
+        let points: Vec<Vector> = (0..300)
+            .map(|i| Vector(((i * 37) % 101) as f64, ((i * 53) % 89) as f64, ((i * 71) % 97) as f64) / 8.0)
+            .chain(std::iter::once(Vector(500.0, -500.0, 0.0)))
+            .collect();
+        let grid = SpatialHash::new(1.0, &points);
+        for (i, p) in points.iter().enumerate() {
+            let expected = points.iter().enumerate()
+                .filter(|(j, _)| i != *j)
+                .fold(f64::INFINITY, |acc, (_, q)| acc.min(p.distance(*q)));
+            assert_eq!(grid.nearest_distance(&points, i), Some(expected));
+        }
+    }
+
+    #[test]
+    fn nearest_distance_of_a_lone_point_is_none() {
+        let points = vec![Vector(1.0, 2.0, 3.0)];
+        assert_eq!(SpatialHash::new(1.0, &points).nearest_distance(&points, 0), None);
+    }
+}
@@ -1,35 +1,1148 @@
 //! Helper functions, mainly for debugging.
 
+use crate::config::EntityConfig;
 use crate::ecs::components::*;
+use crate::ecs::resources::*;
 use crate::math::*;
 use specs::prelude::*;
 
 /// Populates the world with the specified set of entities.
+///
+/// Charges, positions and velocities are drawn from the `SharedRng` resource.
 pub fn populate_entities(world: &mut specs::World, num_entities: u32) {
-    //use rand::Rng;
-    //let mut rng = rand::thread_rng();
     for i in 0..num_entities {
+        let id = world.write_resource::<IdCounter>().allocate();
+        let (charge, position, velocity) = {
+            let mut rng = world.write_resource::<SharedRng>();
+            (
+                world.read_resource::<ChargeDistribution>().sample(i, &mut rng.0),
+                Vector::random_from(&mut rng.0, 1.0, 100.0),
+                Vector::random_from(&mut rng.0, 0.0, 10.0)
+            )
+        };
         world.create_entity()
-            .with(Charge(match i % 3 {
-                0 => 0.0,
-                1 => -1.0,
-                _ => 1.0
-            }))
+            .with(Charge(charge))
             .with(Collisions::default())
             .with(
                 Dynamics {
                     acceleration: Vector::default(),
-                    position: Vector::random(1.0, 100.0),
-                    velocity: Vector::random(0.0, 10.0)
+                    position,
+                    velocity
                 }
             )
             .with(Forces::default())
             .with(Lifetime::default())
             .with(Mass(1.0))
+            .with(ParticleId(id))
+            .with(Physicality {
+                collisions_enabled: true,
+                shape: Shape::Sphere(1.0)
+            })
+            .build();
+    }
+}
+
+
+/// Populates the world with the specified number of entities of the specified
+/// species, placed and moving at random as in `populate_entities`.
+///
+/// Panics if the species is not in the `SpeciesRegistry`.
+pub fn populate_species(world: &mut specs::World, species: &str, num_entities: u32) {
+    let bundle = match world.read_resource::<SpeciesRegistry>().0.get(species) {
+        Some(b) => b.clone(),
+        None => panic!("Unknown species \"{}\"", species)
+    };
+    for i in 0..num_entities {
+        let id = world.write_resource::<IdCounter>().allocate();
+        let (charge, mass, position, velocity) = {
+            let mut rng = world.write_resource::<SharedRng>();
+            (
+                match bundle.charged {
+                    true => world.read_resource::<ChargeDistribution>().sample(i, &mut rng.0),
+                    false => 0.0
+                },
+                bundle.sample_mass(&mut rng.0),
+                Vector::random_from(&mut rng.0, 1.0, 100.0),
+                Vector::random_from(&mut rng.0, 0.0, 10.0)
+            )
+        };
+        world.create_entity()
+            .with(Charge(charge))
+            .with(Collisions::default())
+            .with(
+                Dynamics {
+                    acceleration: Vector::default(),
+                    position,
+                    velocity
+                }
+            )
+            .with(Forces::default())
+            .with(Lifetime::default())
+            .with(Mass(mass))
+            .with(ParticleId(id))
+            .with(Physicality {
+                collisions_enabled: bundle.collisions_enabled,
+                shape: bundle.shape
+            })
+            .build();
+    }
+}
+
+
+/// Populates the world with the specified number of entities in a "big-bang"
+/// configuration.
+///
+/// Entities are placed uniformly within `core_radius` of the origin and given
+/// an outward radial velocity proportional to their position (a Hubble-like
+/// flow), such that `velocity = hubble_factor * position`.
+pub fn populate_big_bang(world: &mut specs::World, num_entities: u32, core_radius: f64, hubble_factor: f64) {
+    for i in 0..num_entities {
+        let id = world.write_resource::<IdCounter>().allocate();
+        let (charge, position) = {
+            let mut rng = world.write_resource::<SharedRng>();
+            (
+                world.read_resource::<ChargeDistribution>().sample(i, &mut rng.0),
+                Vector::random_from(&mut rng.0, 0.0, core_radius)
+            )
+        };
+        world.create_entity()
+            .with(Charge(charge))
+            .with(Collisions::default())
+            .with(
+                Dynamics {
+                    acceleration: Vector::default(),
+                    position,
+                    velocity: position * hubble_factor
+                }
+            )
+            .with(Forces::default())
+            .with(Lifetime::default())
+            .with(Mass(1.0))
+            .with(ParticleId(id))
+            .with(Physicality {
+                collisions_enabled: true,
+                shape: Shape::Sphere(1.0)
+            })
+            .build();
+    }
+}
+
+
+/// Represents the floating-point width of the values in a binary initial
+/// conditions file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinaryFormat {
+    /// Each value is a little-endian `f32`.
+    F32,
+
+    /// Each value is a little-endian `f64`.
+    F64
+}
+
+impl BinaryFormat {
+    /// Returns the number of bytes occupied by a single value.
+    pub fn width(&self) -> usize {
+        match self {
+            BinaryFormat::F32 => 4,
+            BinaryFormat::F64 => 8
+        }
+    }
+}
+
+
+/// Populates the world with the entities contained in the specified binary
+/// initial conditions file.
+///
+/// The file is a headerless sequence of records, one per entity, each of which
+/// contains seven little-endian values of the specified format:
+///
+/// ```text
+/// x y z vx vy vz mass
+/// ```
+///
+/// Binary files do not record charges or shapes, so each entity is uncharged
+/// and given a unit sphere.
+pub fn populate_from_binary(world: &mut specs::World, path: &str, format: BinaryFormat) -> std::io::Result<()> {
+    use std::convert::TryInto;
+    let bytes = std::fs::read(path)?;
+    let width = format.width();
+    let record_size = 7 * width;
+    if bytes.len() % record_size != 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("file size is not a multiple of the {}-byte record size", record_size)
+        ));
+    }
+    for record in bytes.chunks(record_size) {
+        let values: Vec<f64> = record.chunks(width)
+            .map(|v| match format {
+                BinaryFormat::F32 => f64::from(f32::from_le_bytes(v.try_into().unwrap())),
+                BinaryFormat::F64 => f64::from_le_bytes(v.try_into().unwrap())
+            })
+            .collect();
+        let id = world.write_resource::<IdCounter>().allocate();
+        world.create_entity()
+            .with(Charge(0.0))
+            .with(Collisions::default())
+            .with(
+                Dynamics {
+                    acceleration: Vector::default(),
+                    position: Vector(values[0], values[1], values[2]),
+                    velocity: Vector(values[3], values[4], values[5])
+                }
+            )
+            .with(Forces::default())
+            .with(Lifetime::default())
+            .with(Mass(values[6]))
+            .with(ParticleId(id))
             .with(Physicality {
                 collisions_enabled: true,
                 shape: Shape::Sphere(1.0)
             })
             .build();
     }
+    Ok(())
+}
+
+
+/// Populates the world with the entities contained in the last entry of the
+/// specified output file, returning the step that entry represents so that the
+/// simulation can continue numbering steps from it.
+///
+/// Accelerations are not restored, since they are recomputed from forces on
+/// the first step. Output files do not record the shape of entities, so each
+/// entity is given a unit sphere.
+pub fn populate_from_output(world: &mut specs::World, path: &str) -> std::io::Result<u128> {
+    let entries = crate::output::read_entries(path)?;
+    let last_entry = match entries.last() {
+        Some(entry) => entry,
+        None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "output file contains no entries"))
+    };
+    for entity in &last_entry.entities {
+        world.create_entity()
+            .with(Charge(entity.charge))
+            .with(Collisions::default())
+            .with(
+                Dynamics {
+                    acceleration: Vector::default(),
+                    position: entity.position.into(),
+                    velocity: entity.velocity.into()
+                }
+            )
+            .with(Forces::default())
+            .with(Lifetime::default())
+            .with(Mass(entity.mass))
+            .with(ParticleId(entity.id))
+            .with(Physicality {
+                collisions_enabled: true,
+                shape: Shape::Sphere(1.0)
+            })
+            .build();
+    }
+    if let Some(max_id) = last_entry.entities.iter().map(|e| e.id).max() {
+        let mut id_counter = world.write_resource::<IdCounter>();
+        id_counter.0 = id_counter.0.max(max_id.saturating_add(1));
+    }
+    Ok(last_entry.step)
+}
+
+
+/// Populates the world with the entities listed in a simulation configuration,
+/// attaching the components corresponding to their flags.
+pub fn populate_from_config(world: &mut specs::World, entities: &[EntityConfig]) {
+    for entity in entities {
+        let id = world.write_resource::<IdCounter>().allocate();
+        let mut builder = world.create_entity()
+            .with(Charge(entity.charge))
+            .with(Collisions::default())
+            .with(
+                Dynamics {
+                    acceleration: Vector::default(),
+                    position: entity.position,
+                    velocity: entity.velocity
+                }
+            )
+            .with(Forces::default())
+            .with(Lifetime::default())
+            .with(Mass(entity.mass))
+            .with(ParticleId(id))
+            .with(Physicality {
+                collisions_enabled: entity.collisions_enabled,
+                shape: Shape::Sphere(entity.radius)
+            });
+        if entity.anchored {
+            builder = builder.with(Anchored);
+        }
+        if let Some(group) = &entity.group {
+            builder = builder.with(Group(group.clone()));
+        }
+        if entity.immortal {
+            builder = builder.with(Immortal);
+        }
+        builder.build();
+    }
+}
+
+
+/// Populates the world with a neutral slab of charges for measuring plasma
+/// oscillations, returning the analytic (angular) plasma frequency.
+///
+/// Heavy positive ions are placed on a cubic lattice with the specified spacing
+/// which is `layers` sites thick along the x axis and `width` sites wide along
+/// the y and z axes. An electron of unit mass and charge is placed at the
+/// center of each lattice cell and then displaced along the x axis by
+/// `displacement`, which sets up an oscillation at (approximately) the plasma
+/// frequency. Entities are not given a lifetime, so they never split.
+pub fn populate_plasma_oscillation(world: &mut specs::World, layers: u32, width: u32, spacing: f64, displacement: f64) -> f64 {
+    let ion_mass = 1000.0;
+    let offset = |n: u32, i: u32| (f64::from(i) - (f64::from(n) - 1.0) / 2.0) * spacing;
+    for x in 0..layers {
+        for y in 0..width {
+            for z in 0..width {
+                let ion_position = Vector(offset(layers, x), offset(width, y), offset(width, z));
+                let electron_position = ion_position + (spacing / 2.0) + Vector(displacement, 0.0, 0.0);
+                for (charge, mass, position) in [(1.0, ion_mass, ion_position), (-1.0, 1.0, electron_position)].iter() {
+                    let id = world.write_resource::<IdCounter>().allocate();
+                    world.create_entity()
+                        .with(Charge(*charge))
+                        .with(Collisions::default())
+                        .with(
+                            Dynamics {
+                                acceleration: Vector::default(),
+                                position: *position,
+                                velocity: Vector::default()
+                            }
+                        )
+                        .with(Forces::default())
+                        .with(Mass(*mass))
+                        .with(ParticleId(id))
+                        .with(Physicality {
+                            collisions_enabled: false,
+                            shape: Shape::Point
+                        })
+                        .build();
+                }
+            }
+        }
+    }
+    let k = world.read_resource::<ElectrostaticConstant>().0;
+    let density = 1.0 / spacing.powi(3);
+    (4.0 * std::f64::consts::PI * k * density).sqrt()
+}
+
+
+/// Populates the world with the specified number of probes at random positions.
+///
+/// Probes have unit mass and no charge, start at rest, and do not collide, so
+/// they trace the gravitational field without perturbing it.
+pub fn populate_probes(world: &mut specs::World, num_probes: usize) {
+    for _ in 0..num_probes {
+        let id = world.write_resource::<IdCounter>().allocate();
+        let position = Vector::random_from(&mut world.write_resource::<SharedRng>().0, 1.0, 100.0);
+        world.create_entity()
+            .with(Charge(0.0))
+            .with(Collisions::default())
+            .with(
+                Dynamics {
+                    acceleration: Vector::default(),
+                    position,
+                    velocity: Vector::default()
+                }
+            )
+            .with(Forces::default())
+            .with(Lifetime::default())
+            .with(Mass(1.0))
+            .with(ParticleId(id))
+            .with(Physicality {
+                collisions_enabled: false,
+                shape: Shape::Point
+            })
+            .with(Probe)
+            .build();
+    }
+}
+
+
+/// Populates the world with a small satellite orbiting a large primary body,
+/// returning the (angular) orbital frequency.
+///
+/// The primary is 1000 times as massive as the satellite, and both move about
+/// their common center of mass (at the origin) on a circular orbit of the
+/// specified separation, so the primary remains nearly fixed. The satellite is
+/// given an orientation (and a spherical shape, so that it has a moment of
+/// inertia on which torques may act) and spins about the orbital axis at
+/// `spin` times the orbital frequency. Entities are not given a lifetime, so
+/// they never split.
+pub fn populate_tidally_locked(world: &mut specs::World, separation: f64, spin: f64) -> f64 {
+    let (primary_mass, satellite_mass) = (1000.0, 1.0);
+    let total_mass = primary_mass + satellite_mass;
+    let g = world.read_resource::<GravitationalConstant>().0;
+    let frequency = (g * total_mass / separation.powi(3)).sqrt();
+    let speed = frequency * separation;
+    let bodies = [
+        (primary_mass, -satellite_mass / total_mass),
+        (satellite_mass, primary_mass / total_mass)
+    ];
+    for (i, (mass, fraction)) in bodies.iter().enumerate() {
+        let id = world.write_resource::<IdCounter>().allocate();
+        let mut builder = world.create_entity()
+            .with(Charge(0.0))
+            .with(Collisions::default())
+            .with(
+                Dynamics {
+                    acceleration: Vector::default(),
+                    position: Vector(separation * fraction, 0.0, 0.0),
+                    velocity: Vector(0.0, speed * fraction, 0.0)
+                }
+            )
+            .with(Forces::default())
+            .with(Mass(*mass))
+            .with(ParticleId(id))
+            .with(Physicality {
+                collisions_enabled: false,
+                shape: if i == 1 { Shape::Sphere(1.0) } else { Shape::Point }
+            });
+        if i == 1 {
+            builder = builder
+                .with(Orientation {
+                    angular_acceleration: Vector::default(),
+                    angular_position: Vector::unit_x(),
+                    angular_velocity: Vector::unit_z() * (spin * frequency)
+                })
+                .with(Torques::default());
+        }
+        builder.build();
+    }
+    frequency
+}
+
+
+/// Marks the first `num_tracers` entities in the world as tracers.
+pub fn mark_tracers(world: &mut specs::World, num_tracers: usize) {
+    let entities: Vec<specs::Entity> = (&world.entities()).join().take(num_tracers).collect();
+    let mut tracers = world.write_storage::<Tracer>();
+    for entity in entities {
+        tracers.insert(entity, Tracer).expect("Unable to mark tracer");
+    }
+}
+
+
+/// Copies every entity in the source world (along with its components) into
+/// the target world, which must have the same components registered.
+///
+/// Collisions refer to entities of the source world, so copies start with
+/// none.
+pub fn copy_entities(source: &specs::World, target: &mut specs::World) {
+    let entities: Vec<specs::Entity> = (&source.entities()).join().collect();
+    for entity in entities {
+        let copy = target.create_entity().build();
+        if source.read_storage::<Collisions>().contains(entity) {
+            target.write_storage::<Collisions>().insert(copy, Collisions::default()).expect("Unable to copy collisions");
+        }
+        copy_component::<Anchored>(source, target, entity, copy);
+        copy_component::<Charge>(source, target, entity, copy);
+        copy_component::<Dynamics>(source, target, entity, copy);
+        copy_component::<Escaped>(source, target, entity, copy);
+        copy_component::<Forces>(source, target, entity, copy);
+        copy_component::<Group>(source, target, entity, copy);
+        copy_component::<Immortal>(source, target, entity, copy);
+        copy_component::<LastMerge>(source, target, entity, copy);
+        copy_component::<Lifetime>(source, target, entity, copy);
+        copy_component::<Mass>(source, target, entity, copy);
+        copy_component::<Orientation>(source, target, entity, copy);
+        copy_component::<Parents>(source, target, entity, copy);
+        copy_component::<ParticleId>(source, target, entity, copy);
+        copy_component::<Physicality>(source, target, entity, copy);
+        copy_component::<PreviousAcceleration>(source, target, entity, copy);
+        copy_component::<Probe>(source, target, entity, copy);
+        copy_component::<ProperTime>(source, target, entity, copy);
+        copy_component::<StirringForce>(source, target, entity, copy);
+        copy_component::<Torques>(source, target, entity, copy);
+        copy_component::<Tracer>(source, target, entity, copy);
+    }
+}
+
+
+/// Forks the entities of the template world into the target world as a member
+/// of an ensemble.
+///
+/// The target's random number generator is seeded with the specified seed, and
+/// each copied entity (other than anchored ones) is displaced by a random
+/// offset whose components have a standard deviation of `jitter`. Offsets are
+/// projected onto the space described by the target's `Dimensionality`.
+pub fn fork_entities(template: &specs::World, target: &mut specs::World, seed: u64, jitter: f64) {
+    copy_entities(template, target);
+    let mut rng = SharedRng::seeded(seed);
+    if jitter > 0.0 {
+        let dimensionality = *target.read_resource::<Dimensionality>();
+        let anchored = target.read_storage::<Anchored>();
+        for (dynamics, ()) in (&mut target.write_storage::<Dynamics>(), !&anchored).join() {
+            dynamics.position += dimensionality.project(Vector::random_normal_from(&mut rng.0) * jitter);
+        }
+    }
+    target.insert(rng);
+}
+
+
+/// Copies the specified component (if present) from an entity of the source
+/// world to an entity of the target world.
+fn copy_component<T>(source: &specs::World, target: &specs::World, from: specs::Entity, to: specs::Entity)
+where T: specs::Component + Clone
+{
+    if let Some(component) = source.read_storage::<T>().get(from) {
+        target.write_storage::<T>().insert(to, component.clone()).expect("Unable to copy component");
+    }
+}
+
+
+/// Projects the positions and velocities of all entities onto the space
+/// described by the `Dimensionality` resource.
+pub fn project_entities(world: &mut specs::World) {
+    let dimensionality = *world.read_resource::<Dimensionality>();
+    for dynamics in (&mut world.write_storage::<Dynamics>()).join() {
+        dynamics.position = dimensionality.project(dynamics.position);
+        dynamics.velocity = dimensionality.project(dynamics.velocity);
+    }
+}
+
+
+/// Rescales the world to Hénon units, in which the gravitational constant and
+/// the total mass are one and the total energy is -1/4, returning whether the
+/// scaling could be applied.
+///
+/// Masses are divided by the total mass, positions are scaled about the center
+/// of mass, and velocities are scaled uniformly, such that the virial ratio
+/// (the kinetic energy over the magnitude of the potential energy) is
+/// preserved. Systems which are not bound (having a virial ratio of at least
+/// one or a non-negative potential energy) are left unscaled, since no such
+/// scaling gives them a negative total energy.
+pub fn apply_henon_units(world: &mut specs::World) -> bool {
+    let (total_mass, center_of_mass) = {
+        let dynamics = world.read_storage::<Dynamics>();
+        let masses = world.read_storage::<Mass>();
+        let probes = world.read_storage::<Probe>();
+        (&dynamics, &masses, !&probes).join().fold((0.0, Vector::default()), |(m, c), (d, mass, _)| (m + mass.0, c + (d.position * mass.0)))
+    };
+    if total_mass <= 0.0 {
+        warn!("Unable to apply Hénon units - total mass is not positive.");
+        return false;
+    }
+    let center_of_mass = center_of_mass / total_mass;
+
+    // The energies are computed as they will be once the gravitational
+    // constant and the total mass are one, so that nothing is modified unless
+    // the system is bound.
+    let kinetic = kinetic_energy(world) / total_mass;
+    let (mass_products, charge_products) = pairwise_products(world);
+    let k = world.read_resource::<ElectrostaticConstant>().0;
+    let potential = (k * charge_products) - (mass_products / (total_mass * total_mass));
+    if potential >= 0.0 || kinetic >= -potential {
+        warn!("Unable to apply Hénon units - the system is not bound.");
+        return false;
+    }
+    let ratio = kinetic / -potential;
+    let target_potential = 1.0 / (4.0 * (1.0 - ratio));
+    let position_scale = -potential / target_potential;
+    let velocity_scale = match kinetic > 0.0 {
+        true => (ratio * target_potential / kinetic).sqrt(),
+        false => 1.0
+    };
+    world.insert(GravitationalConstant(1.0));
+    for m in (&mut world.write_storage::<Mass>()).join() {
+        m.0 /= total_mass;
+    }
+    for d in (&mut world.write_storage::<Dynamics>()).join() {
+        d.position = center_of_mass + ((d.position - center_of_mass) * position_scale);
+        d.velocity *= velocity_scale;
+    }
+    true
+}
+
+
+/// Normalizes a world populated in SI units (along with the time step) to units
+/// in which the gravitational constant, the total mass and the largest
+/// distance from the center of mass are one, returning the size of those units
+/// if the normalization could be applied.
+///
+/// This keeps the magnitudes involved in computing forces near one, and lets
+/// the dynamics limits be given in the same units as for any other
+/// simulation. Charges and the electrostatic constant are left as given.
+pub fn apply_si_units(world: &mut specs::World) -> Option<UnitScale> {
+    let (total_mass, center_of_mass) = {
+        let dynamics = world.read_storage::<Dynamics>();
+        let masses = world.read_storage::<Mass>();
+        let probes = world.read_storage::<Probe>();
+        (&dynamics, &masses, !&probes).join().fold((0.0, Vector::default()), |(m, c), (d, mass, _)| (m + mass.0, c + (d.position * mass.0)))
+    };
+    if total_mass <= 0.0 {
+        warn!("Unable to normalize SI units - total mass is not positive.");
+        return None;
+    }
+    let center_of_mass = center_of_mass / total_mass;
+    let length = {
+        let dynamics = world.read_storage::<Dynamics>();
+        let probes = world.read_storage::<Probe>();
+        (&dynamics, !&probes).join().map(|(d, _)| d.position.distance(center_of_mass)).fold(0.0, f64::max)
+    };
+    if length <= 0.0 {
+        warn!("Unable to normalize SI units - all entities are at the center of mass.");
+        return None;
+    }
+    let g = world.read_resource::<GravitationalConstant>().0;
+    let scale = UnitScale {
+        length,
+        mass: total_mass,
+        time: (length.powi(3) / (g * total_mass)).sqrt()
+    };
+    world.insert(GravitationalConstant(1.0));
+    world.write_resource::<DeltaTime>().0 /= scale.time;
+    for m in (&mut world.write_storage::<Mass>()).join() {
+        m.0 /= scale.mass;
+    }
+    for d in (&mut world.write_storage::<Dynamics>()).join() {
+        d.position /= scale.length;
+        d.velocity *= scale.time / scale.length;
+    }
+    world.insert(scale.clone());
+    Some(scale)
+}
+
+
+/// Returns the total kinetic energy of all entities (excluding probes) in the
+/// world.
+pub fn kinetic_energy(world: &specs::World) -> f64 {
+    let dynamics = world.read_storage::<Dynamics>();
+    let masses = world.read_storage::<Mass>();
+    let probes = world.read_storage::<Probe>();
+    (&dynamics, &masses, !&probes).join()
+        .map(|(d, m, _)| 0.5 * m.0 * d.velocity.dot(d.velocity))
+        .sum()
+}
+
+
+/// Returns the sums, over each pair of entities (excluding probes) in the world,
+/// of the product of their masses and of the product of their charges divided
+/// by their separation.
+///
+/// Coincident pairs of entities are skipped, since their potential energy is
+/// undefined.
+fn pairwise_products(world: &specs::World) -> (f64, f64) {
+    let charges = world.read_storage::<Charge>();
+    let dynamics = world.read_storage::<Dynamics>();
+    let masses = world.read_storage::<Mass>();
+    let probes = world.read_storage::<Probe>();
+    let bodies: Vec<(Vector, f64, f64)> = (&dynamics, &masses, charges.maybe(), !&probes).join()
+        .map(|(d, m, c, _)| (d.position, m.0, c.map_or(0.0, |c| c.0)))
+        .collect();
+    let (mut mass_products, mut charge_products) = (0.0, 0.0);
+    for (i, (i_position, i_mass, i_charge)) in bodies.iter().enumerate() {
+        for (j_position, j_mass, j_charge) in bodies.iter().skip(i + 1) {
+            let dist = i_position.distance(*j_position);
+            if dist > 0.0 {
+                mass_products += i_mass * j_mass / dist;
+                charge_products += i_charge * j_charge / dist;
+            }
+        }
+    }
+    (mass_products, charge_products)
+}
+
+
+/// Returns the total gravitational and electrostatic potential energy of all
+/// entities (excluding probes) in the world.
+///
+/// Coincident pairs of entities are skipped, since their potential energy is
+/// undefined.
+pub fn potential_energy(world: &specs::World) -> f64 {
+    let g = world.read_resource::<GravitationalConstant>().0;
+    let k = world.read_resource::<ElectrostaticConstant>().0;
+    let (mass_products, charge_products) = pairwise_products(world);
+    (k * charge_products) - (g * mass_products)
+}
+
+
+/// Returns the total (kinetic + potential) energy of all entities (excluding
+/// probes) in the world.
+pub fn total_energy(world: &specs::World) -> f64 {
+    kinetic_energy(world) + potential_energy(world)
+}
+
+
+/// Returns the number of steps to simulate, given the requested number of
+/// steps and simulated duration (whichever is reached first) and the time step.
+///
+/// A duration which is not a whole number of steps is rounded up to the next
+/// step. If neither is specified, 1000 steps are simulated.
+pub fn step_count(steps: Option<u128>, duration: Option<f64>, dt: f64) -> u128 {
+    let duration_steps = duration.map(|d| (d / dt).ceil() as u128);
+    match (steps, duration_steps) {
+        (Some(s), Some(d)) => std::cmp::min(s, d),
+        (Some(s), None)    => s,
+        (None, Some(d))    => d,
+        (None, None)       => 1000
+    }
+}
+
+
+/// Returns the range of steps to simulate, given the first step and the
+/// (positive) number of steps.
+///
+/// The step count may be large enough (for example, from a very long duration)
+/// that the last step would overflow, in which case the range ends at the
+/// largest representable step.
+pub fn step_range(first_step: u128, steps: u128) -> std::ops::RangeInclusive<u128> {
+    first_step..=first_step.saturating_add(steps.saturating_sub(1))
+}
+
+
+/// Formats the message displayed alongside the progress bar.
+pub fn progress_message(num_entities: usize, energy: f64) -> String {
+    format!("[Entities: {}] [Energy: {:.4e}]", num_entities, energy)
+}
+
+
+/// Formats the specified number of seconds as `HH:MM:SS`.
+pub fn format_duration(seconds: f64) -> String {
+    let total = seconds.max(0.0).round() as u64;
+    format!("{:02}:{:02}:{:02}", total / 3600, (total / 60) % 60, total % 60)
+}
+
+
+/// Inserts the specified label into the name of the specified path, before its
+/// extension (if any).
+///
+/// For example, labelling `out/summary.yaml` with `G1-S0.5` gives
+/// `out/summary.G1-S0.5.yaml`.
+pub fn label_path(path: &str, label: &str) -> String {
+    let path = std::path::Path::new(path);
+    let name = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => format!("{}.{}.{}", stem.to_string_lossy(), label, extension.to_string_lossy()),
+        (Some(stem), None) => format!("{}.{}", stem.to_string_lossy(), label),
+        _ => label.to_string()
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+
+/// Parses a range of the form `FROM:TO:COUNT` into `COUNT` evenly-spaced
+/// values from `FROM` to `TO` (inclusive).
+///
+/// A range with a count of one contains only `FROM`.
+pub fn parse_range(range: &str) -> Result<Vec<f64>, String> {
+    let parts: Vec<&str> = range.split(':').collect();
+    if parts.len() != 3 {
+        return Err(format!("Range \"{}\" is not of the form FROM:TO:COUNT.", range));
+    }
+    let from = parts[0].parse::<f64>().map_err(|_| format!("Range start \"{}\" is not a number.", parts[0]))?;
+    let to = parts[1].parse::<f64>().map_err(|_| format!("Range end \"{}\" is not a number.", parts[1]))?;
+    let count = match parts[2].parse::<usize>() {
+        Ok(count) if count > 0 => count,
+        _ => return Err(format!("Range count \"{}\" is not a positive integer value.", parts[2]))
+    };
+    if count == 1 {
+        return Ok(vec![from]);
+    }
+    Ok((0..count).map(|i| from + (to - from) * i as f64 / (count - 1) as f64).collect())
+}
+
+
+/// Represents a moving average over a fixed number of the most recent samples.
+///
+/// This is used to smooth the estimated time remaining in the progress bar,
+/// since the wall-clock time of each step varies with the number of
+/// collisions, splits and adaptive substeps.
+#[derive(Clone, Debug)]
+pub struct MovingAverage {
+    /// The most recent samples, oldest first.
+    samples: std::collections::VecDeque<f64>,
+
+    /// The sum of the current samples.
+    sum: f64,
+
+    /// The maximum number of samples to average over.
+    window: usize
+}
+
+impl MovingAverage {
+    /// Creates a new moving average over the specified number of samples.
+    pub fn new(window: usize) -> Self {
+        MovingAverage {
+            samples: std::collections::VecDeque::with_capacity(window),
+            sum: 0.0,
+            window
+        }
+    }
+
+    /// Returns the mean of the current samples, if there are any.
+    pub fn mean(&self) -> Option<f64> {
+        if self.samples.is_empty() {
+            None
+        } else {
+            Some(self.sum / self.samples.len() as f64)
+        }
+    }
+
+    /// Adds a sample, discarding the oldest one if the window is full.
+    pub fn push(&mut self, sample: f64) {
+        if self.window == 0 {
+            return;
+        }
+        if self.samples.len() == self.window {
+            if let Some(oldest) = self.samples.pop_front() {
+                self.sum -= oldest;
+            }
+        }
+        self.samples.push_back(sample);
+        self.sum += sample;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a world with the components and resources used to populate it
+    /// and compute energies registered.
+    fn world() -> specs::World {
+        let mut world = specs::World::new();
+        world.register::<Anchored>();
+        world.register::<Charge>();
+        world.register::<Collisions>();
+        world.register::<Dynamics>();
+        world.register::<Forces>();
+        world.register::<Group>();
+        world.register::<Immortal>();
+        world.register::<Lifetime>();
+        world.register::<Mass>();
+        world.register::<ParticleId>();
+        world.register::<Physicality>();
+        world.register::<Probe>();
+        world.insert(ChargeDistribution::default());
+        world.insert(ElectrostaticConstant(1.0));
+        world.insert(GravitationalConstant(5.0));
+        world.insert(IdCounter::default());
+        world.insert(SharedRng::default());
+        world
+    }
+
+    /// Populates the world with a small cluster of entities on a lattice, with
+    /// the specified speed.
+    fn populate_cluster(world: &mut specs::World, speed: f64) {
+        for i in 0..27 {
+            let position = Vector((i % 3) as f64, ((i / 3) % 3) as f64, (i / 9) as f64);
+            world.create_entity()
+                .with(Charge(0.0))
+                .with(Dynamics {
+                    position,
+                    velocity: Vector(speed, 0.0, 0.0).rotate_around_axis(Vector::unit_z(), i as f64),
+                    ..Dynamics::default()
+                })
+                .with(Mass(2.0 + (i % 4) as f64))
+                .build();
+        }
+    }
+
+    #[test]
+    fn binary_initial_conditions_round_trip() {
+        let bodies = [
+            (Vector(1.0, -2.0, 3.5), Vector(0.25, 0.0, -1.0), 4.0),
+            (Vector(-0.5, 0.0, 8.0), Vector(2.0, 3.0, 0.125), 0.5)
+        ];
+        for format in [BinaryFormat::F32, BinaryFormat::F64] {
+            let path = std::env::temp_dir().join(format!("grav-{}-{:?}.bin", std::process::id(), format));
+            let mut bytes = Vec::new();
+            for (position, velocity, mass) in &bodies {
+                for value in position.to_array().iter().chain(velocity.to_array().iter()).chain(std::iter::once(mass)) {
+                    match format {
+                        BinaryFormat::F32 => bytes.extend_from_slice(&(*value as f32).to_le_bytes()),
+                        BinaryFormat::F64 => bytes.extend_from_slice(&value.to_le_bytes())
+                    }
+                }
+            }
+            std::fs::write(&path, &bytes[1..]).unwrap();
+            assert!(populate_from_binary(&mut world(), &path.to_string_lossy(), format).is_err());
+            std::fs::write(&path, &bytes).unwrap();
+            let mut world = world();
+            populate_from_binary(&mut world, &path.to_string_lossy(), format).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let ids = world.read_storage::<ParticleId>();
+            let dynamics = world.read_storage::<Dynamics>();
+            let masses = world.read_storage::<Mass>();
+            let mut loaded: Vec<(u64, Vector, Vector, f64)> = (&ids, &dynamics, &masses).join()
+                .map(|(id, d, m)| (id.0, d.position, d.velocity, m.0))
+                .collect();
+            loaded.sort_by_key(|(id, _, _, _)| *id);
+            assert_eq!(loaded.len(), bodies.len());
+            for ((_, position, velocity, mass), (p, v, m)) in loaded.iter().zip(bodies.iter()) {
+                assert_eq!((position, velocity, mass), (p, v, m));
+            }
+        }
+    }
+
+    #[test]
+    fn species_populate_entities_with_their_components() {
+        let mut world = world();
+        world.insert(SpeciesRegistry::default());
+        populate_species(&mut world, "star", 3);
+        populate_species(&mut world, "dark", 2);
+        let masses = world.read_storage::<Mass>();
+        let physicality = world.read_storage::<Physicality>();
+        let ids = world.read_storage::<ParticleId>();
+        let mut entities: Vec<(u64, f64, &Physicality)> = (&ids, &masses, &physicality).join().map(|(id, m, p)| (id.0, m.0, p)).collect();
+        entities.sort_by_key(|(id, _, _)| *id);
+        assert_eq!(entities.len(), 5);
+        for (i, (_, mass, p)) in entities.iter().enumerate() {
+            match i < 3 {
+                true => {
+                    assert!((50.0..=200.0).contains(mass), "{}", mass);
+                    assert!(matches!(p.shape, Shape::Sphere(r) if r == 2.0));
+                    assert!(p.collisions_enabled);
+                },
+                false => {
+                    assert!((1.0..=10.0).contains(mass), "{}", mass);
+                    assert!(matches!(p.shape, Shape::Point));
+                    assert!(!p.collisions_enabled);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn uniform_charges_are_uncorrelated_with_position() {
+        let mut world = world();
+        world.insert(ChargeDistribution::Uniform);
+        populate_entities(&mut world, 3000);
+        let samples: Vec<(f64, Vector)> = (&world.read_storage::<Charge>(), &world.read_storage::<Dynamics>()).join()
+            .map(|(c, d)| (c.0, d.position))
+            .collect();
+        let correlation = | value: &dyn Fn(Vector) -> f64 | {
+            let n = samples.len() as f64;
+            let mean_charge = samples.iter().map(|(c, _)| c).sum::<f64>() / n;
+            let mean_value = samples.iter().map(|(_, p)| value(*p)).sum::<f64>() / n;
+            let (mut covariance, mut charge_variance, mut value_variance) = (0.0, 0.0, 0.0);
+            for (c, p) in &samples {
+                let (dc, dv) = (c - mean_charge, value(*p) - mean_value);
+                covariance += dc * dv;
+                charge_variance += dc * dc;
+                value_variance += dv * dv;
+            }
+            covariance / (charge_variance * value_variance).sqrt()
+        };
+        assert!(correlation(&|p| p.0).abs() < 0.1);
+        assert!(correlation(&|p| p.1).abs() < 0.1);
+        assert!(correlation(&|p| p.2).abs() < 0.1);
+        assert!(correlation(&|p| p.magnitude()).abs() < 0.1);
+    }
+
+    #[test]
+    fn label_path_inserts_the_label_before_the_extension() {
+        assert_eq!(label_path("out/summary.yaml", "G1-S0.5"), "out/summary.G1-S0.5.yaml");
+        assert_eq!(label_path("frames", "G1-S0.5"), "frames.G1-S0.5");
+    }
+
+    #[test]
+    fn parse_range_gives_evenly_spaced_values() {
+        assert_eq!(parse_range("1:2:3"), Ok(vec![1.0, 1.5, 2.0]));
+        assert_eq!(parse_range("0.5:9:1"), Ok(vec![0.5]));
+        assert!(parse_range("1:2").is_err());
+        assert!(parse_range("1:2:0").is_err());
+    }
+
+    #[test]
+    fn big_bang_velocities_are_radial_and_proportional_to_distance() {
+        let mut world = world();
+        populate_big_bang(&mut world, 100, 5.0, 0.3);
+        let dynamics = world.read_storage::<Dynamics>();
+        assert_eq!(dynamics.join().count(), 100);
+        for d in dynamics.join() {
+            assert!(d.position.magnitude() <= 5.0);
+            assert!(d.velocity.approx_eq(d.position * 0.3, 1e-12));
+        }
+    }
+
+    #[test]
+    fn configured_entities_are_given_their_flagged_components() {
+        let config: crate::config::SimulationConfig = serde_yaml::from_str("
+entities:
+  - mass: 1000.0
+    position: [0.0, 0.0, 0.0]
+    anchored: true
+    collisions_enabled: false
+    group: sun
+  - mass: 1.0
+    position: [10.0, 0.0, 0.0]
+    velocity: [0.0, 10.0, 0.0]
+").unwrap();
+        let mut world = world();
+        populate_from_config(&mut world, &config.entities.unwrap());
+        let anchored = world.read_storage::<Anchored>();
+        let groups = world.read_storage::<Group>();
+        let immortals = world.read_storage::<Immortal>();
+        let masses = world.read_storage::<Mass>();
+        let physicality = world.read_storage::<Physicality>();
+        let bodies: Vec<_> = (&world.entities(), &masses, &physicality).join().collect();
+        assert_eq!(bodies.len(), 2);
+        for (entity, mass, physicality) in bodies {
+            let sun = mass.0 == 1000.0;
+            assert_eq!(anchored.contains(entity), sun);
+            assert_eq!(physicality.collisions_enabled, !sun);
+            assert_eq!(groups.get(entity).map(|g| g.0.as_str()), if sun { Some("sun") } else { None });
+            assert!(!immortals.contains(entity));
+        }
+    }
+
+    #[test]
+    fn si_earth_sun_orbit_takes_a_year() {
+        use crate::ecs::systems::{ClearForces, HandleDynamics, HandleForces, HandleGravity};
+        let mut world = world();
+        world.insert(DeltaTime(3600.0));
+        world.insert(GravitationalConstant(SI_GRAVITATIONAL_CONSTANT));
+        world.insert(Integrator::VelocityVerlet);
+        let sun = world.create_entity()
+            .with(Dynamics::default())
+            .with(Forces::default())
+            .with(Mass(1.989e30))
+            .build();
+        let earth = world.create_entity()
+            .with(Dynamics {
+                position: Vector(1.496e11, 0.0, 0.0),
+                velocity: Vector(0.0, 2.978e4, 0.0),
+                ..Dynamics::default()
+            })
+            .with(Forces::default())
+            .with(Mass(5.972e24))
+            .build();
+        let scale = apply_si_units(&mut world).unwrap();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(ClearForces, "clear_forces", &[])
+            .with(HandleGravity, "handle_gravity", &["clear_forces"])
+            .with(HandleForces, "handle_forces", &["handle_gravity"])
+            .with(HandleDynamics, "handle_dynamics", &["handle_forces"])
+            .build();
+        dispatcher.setup(&mut world);
+        let separation = |world: &specs::World| {
+            let dynamics = world.read_storage::<Dynamics>();
+            dynamics.get(earth).unwrap().position - dynamics.get(sun).unwrap().position
+        };
+        let (mut angle, mut previous, mut steps) = (0.0, separation(&world), 0);
+        while angle < 2.0 * std::f64::consts::PI && steps < 20000 {
+            dispatcher.dispatch(&world);
+            let current = separation(&world);
+            angle += previous.angle_between(current);
+            previous = current;
+            steps += 1;
+        }
+        let period = f64::from(steps) * world.read_resource::<DeltaTime>().0 * scale.time;
+        let year = 365.25 * 86400.0;
+        assert!((period / year - 1.0).abs() < 0.03, "{} days", period / 86400.0);
+    }
+
+    #[test]
+    fn step_count_stops_at_the_duration_or_the_step_count() {
+        assert_eq!(step_count(None, Some(5.0), 0.5), 10);
+        assert_eq!(step_count(Some(20), Some(5.0), 0.5), 10);
+        assert_eq!(step_count(Some(4), Some(5.0), 0.5), 4);
+        assert_eq!(step_count(None, Some(5.2), 0.5), 11);
+        assert_eq!(step_count(None, None, 0.5), 1000);
+    }
+
+    #[test]
+    fn step_range_saturates_instead_of_overflowing() {
+        assert_eq!(step_range(1, 10), 1..=10);
+        assert_eq!(step_range(11, 5), 11..=15);
+        assert_eq!(step_range(1, u128::MAX), 1..=u128::MAX);
+        assert_eq!(step_range(100, u128::MAX), 100..=u128::MAX);
+        assert_eq!(step_count(Some(u128::MAX), None, 0.1), u128::MAX);
+        assert_eq!(step_count(None, Some(f64::MAX), 1e-300), u128::MAX);
+    }
+
+    #[test]
+    fn moving_average_smooths_noisy_step_times() {
+        let mut average = MovingAverage::new(20);
+        assert_eq!(average.mean(), None);
+        for i in 0..200 {
+            let noise = match i % 4 { 0 => 0.09, 1 => -0.03, 2 => 0.05, _ => -0.11 };
+            average.push(0.1 + noise);
+            if i >= 20 {
+                assert!((average.mean().unwrap() - 0.1).abs() < 0.01, "{:?}", average.mean());
+            }
+        }
+        let mut disabled = MovingAverage::new(0);
+        disabled.push(1.0);
+        assert_eq!(disabled.mean(), None);
+    }
+
+    #[test]
+    fn progress_message_reports_entities_and_energy() {
+        assert_eq!(progress_message(42, -1234.5), "[Entities: 42] [Energy: -1.2345e3]");
+        assert_eq!(progress_message(0, 0.0), "[Entities: 0] [Energy: 0.0000e0]");
+    }
+
+    #[test]
+    fn henon_units_give_unit_mass_and_quarter_binding_energy() {
+        let mut world = world();
+        populate_cluster(&mut world, 0.1);
+        assert!(apply_henon_units(&mut world));
+        let total_mass: f64 = world.read_storage::<Mass>().join().map(|m| m.0).sum();
+        assert!((total_mass - 1.0).abs() < 1e-12);
+        assert_eq!(world.read_resource::<GravitationalConstant>().0, 1.0);
+        assert!((total_energy(&world) + 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn henon_units_leave_unbound_systems_unchanged() {
+        let mut world = world();
+        populate_cluster(&mut world, 100.0);
+        let energy = total_energy(&world);
+        assert!(!apply_henon_units(&mut world));
+        assert_eq!(world.read_resource::<GravitationalConstant>().0, 5.0);
+        assert_eq!(total_energy(&world), energy);
+    }
+
+
+    #[test]
+    fn seeded_uniform_charges_are_reproducible() {
+        let charges = | seed: u64 | {
+            let mut world = world();
+            world.insert(ChargeDistribution::Uniform);
+            world.insert(SharedRng::seeded(seed));
+            populate_entities(&mut world, 100);
+            let charges = world.read_storage::<Charge>();
+            charges.join().map(|c| c.0).collect::<Vec<f64>>()
+        };
+        assert_eq!(charges(1), charges(1));
+        assert_ne!(charges(1), charges(2));
+    }
+
+
+    #[test]
+    fn seeded_initial_positions_and_velocities_are_reproducible() {
+        let dynamics = | seed: u64 | {
+            let mut world = world();
+            world.insert(SharedRng::seeded(seed));
+            populate_entities(&mut world, 10);
+            populate_big_bang(&mut world, 10, 5.0, 0.1);
+            populate_probes(&mut world, 10);
+            let dynamics = world.read_storage::<Dynamics>();
+            dynamics.join().map(|d| (d.position, d.velocity)).collect::<Vec<(Vector, Vector)>>()
+        };
+        assert_eq!(dynamics(1), dynamics(1));
+        assert_ne!(dynamics(1), dynamics(2));
+    }
+
+
+    #[test]
+    fn resuming_from_the_largest_particle_id_does_not_overflow() {
+        let path = std::env::temp_dir().join(format!("grav-{}-largest-id.yaml", std::process::id()));
+        std::fs::write(&path, format!(
+            "step: 3\nentities:\n  - acceleration: [0.0, 0.0, 0.0]\n    charge: 0.0\n    id: {}\n    mass: 1.0\n    position: [1.0, 2.0, 3.0]\n    velocity: [0.0, 0.0, 0.0]\n",
+            u64::MAX
+        )).unwrap();
+        let mut world = world();
+        let step = populate_from_output(&mut world, &path.to_string_lossy());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(step.unwrap(), 3);
+        assert_eq!(world.read_resource::<IdCounter>().0, u64::MAX);
+    }
 }
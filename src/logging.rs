@@ -29,16 +29,10 @@ pub fn setup(
         .chain(std::fs::OpenOptions::new()
                .write(true)
                .create(true)
-               .append(match log_mode {
-                   "append" => true,
-                   _        => false
-               })
-               .truncate(match log_mode {
-                   "append" => false,
-                   _        => true
-               })
+               .append(log_mode == "append")
+               .truncate(log_mode != "append")
                .open(log_file)?
         )
         .apply()?;
-    return Ok(());
+    Ok(())
 }
@@ -1,22 +1,30 @@
 //! grav
 
-#![feature(box_syntax, decl_macro, proc_macro_hygiene)]
-
 #[macro_use] extern crate log;
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate specs_derive;
 
 pub mod cli;
+pub mod config;
 pub mod ecs;
+pub mod grid;
 pub mod helper;
 pub mod logging;
 pub mod math;
+pub mod octree;
 pub mod output;
+pub mod render;
 
 use specs::prelude::*;
 use std::convert::TryInto;
+use crate::config::SimulationConfig;
 use crate::ecs::systems::*;
 use crate::ecs::resources::*;
+use crate::output::{CorrelationWriter, NeighborsWriter, OutputEntry, OutputWriter, ProfileWriter, SummaryWriter};
+
+/// The number of steps per (normalized) unit of time above which SI inputs are
+/// suspected to actually be in normalized units.
+const SI_UNIT_WARNING_STEPS: f64 = 1e6;
 
 /// The entrypoint of the program.
 fn main() {
@@ -33,25 +41,114 @@ fn main() {
         Err(e) => panic!("Unable to initialize logging subsystem - {}", e)
     }
 
+    if let Some(path) = args.value_of("load") {
+        info!("Playing back \"{}\"...", path);
+        if let Err(e) = play_back(path, std::io::stdout().lock()) {
+            panic!("Unable to play back output file - {}", e);
+        }
+        return;
+    }
+
+    let config = match args.value_of("config") {
+        Some(path) => {
+            info!("Loading configuration from \"{}\"...", path);
+            match SimulationConfig::load(path) {
+                Ok(c) => c,
+                Err(e) => panic!("Unable to load configuration file - {}", e)
+            }
+        },
+        None => SimulationConfig::default()
+    };
+
+    if let Some(ensemble_args) = args.subcommand_matches("ensemble") {
+        ensemble(&args, ensemble_args, &config);
+    } else if let Some(sweep_args) = args.subcommand_matches("sweep") {
+        sweep(&args, sweep_args, &config);
+    } else {
+        let mut world = build_world(&args, &config, None);
+        let first_step = populate(&mut world, &args, &config);
+        simulate(world, &args, &config, first_step, true);
+    }
+}
+
+/// Builds a world with every component registered and every resource inserted
+/// according to the specified arguments and configuration.
+///
+/// If a label is specified, it is inserted into the name of each file (or
+/// directory) the simulation writes, so that the runs of a sweep do not
+/// overwrite each other.
+fn build_world(args: &clap::ArgMatches, config: &SimulationConfig, label: Option<&str>) -> specs::World {
+    let labelled = |path: &str| match label {
+        Some(l) => helper::label_path(path, l),
+        None => path.to_string()
+    };
+
     info!("Instantiating world...");
     let mut world = specs::World::new();
 
     info!("Registering components...");
+    world.register::<ecs::components::Anchored>();
     world.register::<ecs::components::Charge>();
     world.register::<ecs::components::Collisions>();
     world.register::<ecs::components::Dynamics>();
+    world.register::<ecs::components::Escaped>();
     world.register::<ecs::components::Forces>();
+    world.register::<ecs::components::Group>();
+    world.register::<ecs::components::Immortal>();
+    world.register::<ecs::components::LastMerge>();
     world.register::<ecs::components::Lifetime>();
     world.register::<ecs::components::Mass>();
+    world.register::<ecs::components::Orientation>();
+    world.register::<ecs::components::Parents>();
+    world.register::<ecs::components::ParticleId>();
     world.register::<ecs::components::Physicality>();
+    world.register::<ecs::components::PreviousAcceleration>();
+    world.register::<ecs::components::Probe>();
+    world.register::<ecs::components::ProperTime>();
+    world.register::<ecs::components::StirringForce>();
+    world.register::<ecs::components::Torques>();
+    world.register::<ecs::components::Tracer>();
 
     info!("Instantiating resources...");
-    world.insert(CollisionLimits {
+    world.insert(match args.value_of("mond_acceleration") {
+        Some(a0) => AccelerationModifier::Mond { a0: a0.parse::<f64>().unwrap() },
+        None => AccelerationModifier::Newtonian
+    });
+    world.insert(BackgroundDensity(args.value_of("background_density").unwrap().parse::<f64>().unwrap()));
+    world.insert(match args.value_of("barnes_hut_theta") {
+        Some(theta) => GravityMethod::BarnesHut { theta: theta.parse::<f64>().unwrap() },
+        None => GravityMethod::BruteForce
+    });
+    world.insert(match args.value_of("charge_distribution").unwrap() {
+        "cyclic" => ChargeDistribution::Cyclic,
+        _ => ChargeDistribution::Uniform
+    });
+    world.insert(ChargeLimits {
+        maximum_charge: args.value_of("maximum_charge").unwrap().parse::<f64>().unwrap(),
+        minimum_charge: args.value_of("minimum_charge").unwrap().parse::<f64>().unwrap()
+    });
+    world.insert(config.collision_limits.clone().unwrap_or(CollisionLimits {
         maximum_detection_theshold: 100.0,
         minimum_detection_theshold: 1.0
+    }));
+    world.insert(CollisionSubsteps(args.value_of("collision_substeps").unwrap().parse::<u32>().unwrap()));
+    if let Some(file) = args.value_of("correlation") {
+        world.insert(CorrelationSettings {
+            bins: args.value_of("correlation_bins").unwrap().parse::<usize>().unwrap(),
+            file: labelled(file),
+            interval: args.value_of("correlation_interval").unwrap().parse::<u128>().unwrap(),
+            maximum_separation: args.value_of("correlation_maximum_separation").unwrap().parse::<f64>().unwrap()
+        });
+    }
+    world.insert(CorrelationWriter::default());
+    world.insert(CurrentStep::default());
+    world.insert(DeltaTime(SimulationConfig::resolve(args, "delta_time", &config.delta_time).unwrap().parse::<f64>().unwrap()));
+    world.insert(match args.is_present("two_dimensional") {
+        true => Dimensionality::Two,
+        false => Dimensionality::Three
     });
-    world.insert(DeltaTime(0.5));
-    world.insert(
+    world.insert(DragCoefficient(args.value_of("drag_coefficient").unwrap().parse::<f64>().unwrap()));
+    world.insert(config.dynamics_limits.clone().unwrap_or(
         DynamicsLimits {
             maximum_acceleration: 5.0,
             maximum_position: 100.0,
@@ -60,39 +157,379 @@ fn main() {
             minimum_position: 0.0,
             minimum_velocity: 0.0
         }
-    );
-    world.insert(ElectrostaticConstant(0.5));
-    world.insert(GravitationalConstant(1.0));
-    world.insert(OutputFile(args.value_of("output").unwrap().to_string()));
+    ));
+    world.insert(ElectrostaticConstant(SimulationConfig::resolve(args, "electrostatic", &config.electrostatic_constant).unwrap().parse::<f64>().unwrap()));
+    world.insert(ElectrostaticSoftening(args.value_of("electrostatic_softening").unwrap().parse::<f64>().unwrap()));
+    if let Some(distance) = args.value_of("escape_distance") {
+        world.insert(EscapeSettings {
+            distance: distance.parse::<f64>().unwrap(),
+            remove: args.is_present("remove_escaped")
+        });
+    }
+    world.insert(match args.is_present("single_precision_forces") {
+        true => ForcePrecision::Single,
+        false => ForcePrecision::Double
+    });
+    world.insert(match args.value_of("force_symmetry").unwrap() {
+        "independent" => ForceSymmetry::Independent,
+        _ => ForceSymmetry::Symmetric
+    });
+    if let Some(directory) = args.value_of("frames") {
+        let directory = labelled(directory);
+        std::fs::create_dir_all(&directory).expect("Unable to create frames directory.");
+        let extent = world.read_resource::<DynamicsLimits>().maximum_position;
+        world.insert(FrameSettings {
+            directory,
+            extent,
+            resolution: args.value_of("frame_resolution").unwrap().parse::<u32>().unwrap()
+        });
+    }
+    world.insert(GravitationalConstant(match SimulationConfig::resolve(args, "gravity", &config.gravitational_constant).unwrap().as_str() {
+        "si" => SI_GRAVITATIONAL_CONSTANT,
+        g => g.parse::<f64>().unwrap()
+    }));
+    world.insert(GravitationalSoftening(args.value_of("gravitational_softening").unwrap().parse::<f64>().unwrap()));
+    world.insert(IdCounter::default());
+    world.insert(match args.value_of("integrator").unwrap() {
+        "velocity-verlet" => Integrator::VelocityVerlet,
+        _ => Integrator::Euler
+    });
+    world.insert(InteractiveForce::default());
+    world.insert(MaintenanceRequired::default());
+    world.insert(MergePreservesIdentity(args.is_present("merge_preserves_identity")));
+    if let Some(file) = args.value_of("neighbors") {
+        world.insert(NeighborsSettings {
+            bins: args.value_of("neighbors_bins").unwrap().parse::<usize>().unwrap(),
+            file: labelled(file),
+            interval: args.value_of("neighbors_interval").unwrap().parse::<u128>().unwrap()
+        });
+    }
+    world.insert(NeighborsWriter::default());
+    let output = labelled(&SimulationConfig::resolve(args, "output", &config.output).unwrap());
+    world.insert(OrientationLimits::default());
+    world.insert(OutputFile(output.clone()));
+    world.insert(OutputReference::World);
+    world.insert(OutputSettings {
+        from_step: args.value_of("output_from").map(|s| s.parse::<u128>().unwrap()),
+        lineage: args.is_present("output_lineage"),
+        to_step: args.value_of("output_to").map(|s| s.parse::<u128>().unwrap()),
+        tracers_only: args.is_present("output_tracers_only")
+    });
+    if args.is_present("output_index") {
+        world.insert(OutputWriter::<OutputEntry>::with_index(&format!("{}.index", output)));
+    } else {
+        world.insert(OutputWriter::<OutputEntry>::default());
+    }
+    world.insert(PlasmaOscillation::default());
+    if let Some(file) = args.value_of("profile") {
+        world.insert(ProfileSettings {
+            bins: args.value_of("profile_bins").unwrap().parse::<usize>().unwrap(),
+            file: labelled(file),
+            interval: args.value_of("profile_interval").unwrap().parse::<u128>().unwrap()
+        });
+    }
+    world.insert(ProfileWriter::default());
+    world.insert(match args.value_of("seed") {
+        Some(seed) => SharedRng::seeded(seed.parse::<u64>().unwrap()),
+        None => SharedRng::default()
+    });
+    world.insert(SpeciesRegistry::default());
     world.insert(SplittingSettings {
+        cooldown: 200,
         maximum_lifetime: 400,
         minimum_lifetime: 100,
         separation_multiplier: 1.0,
         velocity_multiplier: 1.0
     });
+    world.insert(Stirring {
+        amplitude: args.value_of("stirring_amplitude").unwrap().parse::<f64>().unwrap(),
+        correlation_time: args.value_of("stirring_correlation_time").unwrap().parse::<f64>().unwrap()
+    });
+    if let Some(file) = args.value_of("summary") {
+        world.insert(SummarySettings {
+            file: labelled(file),
+            interval: args.value_of("summary_interval").unwrap().parse::<u128>().unwrap()
+        });
+    }
+    world.insert(SummaryWriter::default());
+    world.insert(TidalLocking {
+        strength: args.value_of("tidal_torque").unwrap().parse::<f64>().unwrap(),
+        ..TidalLocking::default()
+    });
+    world.insert(TimeDilation(args.is_present("time_dilation")));
+    world.insert(TotalClampedEnergy::default());
+    world.insert(match args.value_of("velocity_limit_mode").unwrap() {
+        "soft-drag" => VelocityLimitMode::SoftDrag,
+        _ => VelocityLimitMode::HardScale
+    });
+    world.insert(TotalDissipatedEnergy::default());
+    world.insert(UnitScale::default());
 
-    info!("Building dispatcher...");
-    let mut dispatcher = DispatcherBuilder::new()
-        .with(
-            ClearCollisions,
-            "clear_collisions",
-            &[]
-        )
+    world
+}
+
+/// Populates the specified world according to the specified arguments and
+/// configuration, returning the first step to simulate.
+fn populate(world: &mut specs::World, args: &clap::ArgMatches, config: &SimulationConfig) -> u128 {
+    info!("Building entities...");
+    let mut first_step: u128 = 1;
+    if let Some(path) = args.value_of("resume_from_output") {
+        info!("Resuming from the last entry of \"{}\"...", path);
+        match helper::populate_from_output(world, path) {
+            Ok(last_step) => first_step = last_step.saturating_add(1),
+            Err(e) => panic!("Unable to resume from output file - {}", e)
+        }
+    } else if let Some(path) = args.value_of("initial_conditions") {
+        info!("Loading initial conditions from \"{}\"...", path);
+        let format = match args.value_of("initial_conditions_format").unwrap() {
+            "f32" => helper::BinaryFormat::F32,
+            _ => helper::BinaryFormat::F64
+        };
+        if let Err(e) = helper::populate_from_binary(world, path, format) {
+            panic!("Unable to load initial conditions file - {}", e);
+        }
+    } else if let Some(entities) = &config.entities {
+        info!("Adding {} entities from the configuration...", entities.len());
+        helper::populate_from_config(world, entities);
+    } else if let Some(scenario) = args.value_of("scenario") {
+        info!("Setting up the \"{}\" scenario...", scenario);
+        match scenario {
+            "plasma-oscillation" => {
+                let analytic_frequency = helper::populate_plasma_oscillation(world, 4, 12, 2.0, 0.1);
+                info!("Disabling gravity and expecting a plasma frequency of {:.4e}.", analytic_frequency);
+                world.insert(GravitationalConstant(0.0));
+                world.write_resource::<PlasmaOscillation>().analytic_frequency = analytic_frequency;
+            },
+            "tidally-locked" => {
+                let frequency = helper::populate_tidally_locked(world, 50.0, 5.0);
+                info!("Expecting an orbital period of {:.4e}.", 2.0 * std::f64::consts::PI / frequency);
+            },
+            _ => unreachable!()
+        }
+    } else if let Some(species) = args.values_of("species") {
+        for value in species {
+            let mut parts = value.splitn(2, ':');
+            let name = parts.next().unwrap();
+            let count = parts.next().unwrap().parse::<u32>().unwrap();
+            info!("Adding {} entities of species \"{}\"...", count, name);
+            helper::populate_species(world, name, count);
+        }
+    } else {
+        let num_entities = SimulationConfig::resolve(args, "num_entities", &config.num_entities).unwrap().parse::<u32>().unwrap();
+        match SimulationConfig::resolve(args, "distribution", &config.distribution).unwrap().as_str() {
+            "big-bang" => helper::populate_big_bang(
+                world,
+                num_entities,
+                args.value_of("core_radius").unwrap().parse::<f64>().unwrap(),
+                args.value_of("hubble_factor").unwrap().parse::<f64>().unwrap()
+            ),
+            "random" => helper::populate_entities(world, num_entities),
+            _ => unreachable!()
+        }
+    }
+
+    if (&world.entities()).join().next().is_none() {
+        warn!("The initial distribution contains no entities.");
+    }
+
+    if SimulationConfig::resolve(args, "gravity", &config.gravitational_constant).as_deref() == Some("si") {
+        info!("Normalizing SI units...");
+        if let Some(scale) = helper::apply_si_units(world) {
+            info!("Using units of {:.4e} m, {:.4e} kg and {:.4e} s.", scale.length, scale.mass, scale.time);
+            let steps_per_unit = 1.0 / world.read_resource::<DeltaTime>().0;
+            if steps_per_unit > SI_UNIT_WARNING_STEPS {
+                warn!("A single unit of time spans {:.4e} steps - masses and positions may be in normalized rather than SI units.", steps_per_unit);
+            }
+        }
+    }
+
+    if args.is_present("two_dimensional") {
+        info!("Projecting entities onto the plane z = 0...");
+        helper::project_entities(world);
+    }
+
+    if args.is_present("henon_units") {
+        info!("Rescaling to Hénon units...");
+        if helper::apply_henon_units(world) {
+            info!("Total energy after rescaling: {:.4e}", helper::total_energy(world));
+        }
+    }
+
+    let num_probes = args.value_of("probes").unwrap().parse::<usize>().unwrap();
+    if num_probes > 0 {
+        info!("Adding {} probes...", num_probes);
+        helper::populate_probes(world, num_probes);
+    }
+
+    let num_tracers = args.value_of("tracers").unwrap().parse::<usize>().unwrap();
+    if num_tracers > 0 {
+        info!("Marking {} entities as tracers...", num_tracers);
+        helper::mark_tracers(world, num_tracers);
+    }
+
+    if let Some(id) = args.value_of("output_reference") {
+        let id = id.parse::<u64>().unwrap();
+        let exists = world.read_storage::<ecs::components::ParticleId>().join().any(|p| p.0 == id);
+        if exists {
+            info!("Writing output relative to particle {}...", id);
+            world.insert(OutputReference::RelativeTo(id));
+        } else {
+            warn!("Output reference particle {} does not exist - using world frame.", id);
+        }
+    }
+
+    first_step
+}
+
+/// Builds a world for a single labelled run of a sweep or ensemble, copying the
+/// resources set up by `populate` from the specified template (but not its
+/// entities).
+fn build_world_from_template(template: &specs::World, args: &clap::ArgMatches, config: &SimulationConfig, label: &str) -> specs::World {
+    let mut world = build_world(args, config, Some(label));
+    world.insert((*template.read_resource::<DeltaTime>()).clone());
+    world.insert((*template.read_resource::<IdCounter>()).clone());
+    world.insert((*template.read_resource::<OutputReference>()).clone());
+    world.insert((*template.read_resource::<PlasmaOscillation>()).clone());
+    world.insert((*template.read_resource::<UnitScale>()).clone());
+    world
+}
+
+/// Simulates an ensemble of the number of members specified to the `ensemble`
+/// subcommand, each forked from the same initial conditions with its own seed
+/// and a small random displacement of every entity.
+///
+/// Member `i` is seeded with `--seed` (or a random seed) plus `i`, and writes
+/// its output and summary files (among others) with a label like `E3` inserted
+/// into their names. Once every member has finished, the number of entities and
+/// total energy of each are logged, along with the spread of the total energy
+/// across the ensemble.
+fn ensemble(args: &clap::ArgMatches, ensemble_args: &clap::ArgMatches, config: &SimulationConfig) {
+    info!("Building initial conditions...");
+    let mut template = build_world(args, config, None);
+    let first_step = populate(&mut template, args, config);
+
+    let members = ensemble_args.value_of("members").unwrap().parse::<u64>().unwrap();
+    let jitter = ensemble_args.value_of("jitter").unwrap().parse::<f64>().unwrap();
+    let seed = match args.value_of("seed") {
+        Some(seed) => seed.parse::<u64>().unwrap(),
+        None => rand::random()
+    };
+    let parallel = ensemble_args.is_present("parallel");
+    info!("Simulating {} ensemble member(s) from seed {}...", members, seed);
+
+    let run = |i: u64| -> (usize, f64) {
+        let label = format!("E{}", i);
+        info!("Simulating ensemble member \"{}\"...", label);
+        let mut world = build_world_from_template(&template, args, config, &label);
+        helper::fork_entities(&template, &mut world, seed.wrapping_add(i), jitter);
+        let world = simulate(world, args, config, first_step, !parallel);
+        let num_entities = (&world.entities()).join().count();
+        (num_entities, helper::total_energy(&world))
+    };
+    let results: Vec<(usize, f64)> = match parallel {
+        true => std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..members).map(|i| scope.spawn(move || run(i))).collect();
+            handles.into_iter().map(|h| h.join().expect("Ensemble member panicked.")).collect()
+        }),
+        false => (0..members).map(run).collect()
+    };
+    for (i, (num_entities, energy)) in results.iter().enumerate() {
+        info!("Ensemble member \"E{}\" finished with {} entities and a total energy of {:.4e}.", i, num_entities, energy);
+    }
+    if !results.is_empty() {
+        let n = results.len() as f64;
+        let mean = results.iter().map(|(_, e)| e).sum::<f64>() / n;
+        let deviation = (results.iter().map(|(_, e)| (e - mean) * (e - mean)).sum::<f64>() / n).sqrt();
+        info!("Total energy across the ensemble: {:.4e} (standard deviation: {:.4e}).", mean, deviation);
+    }
+}
+
+/// Sweeps the gravitational constant and gravitational softening over the
+/// ranges specified to the `sweep` subcommand, simulating every combination
+/// from the same initial conditions.
+///
+/// Each combination writes its output and summary files (among others) with a
+/// label like `G1-S0.5` inserted into their names.
+fn sweep(args: &clap::ArgMatches, sweep_args: &clap::ArgMatches, config: &SimulationConfig) {
+    info!("Building initial conditions...");
+    let mut template = build_world(args, config, None);
+    let first_step = populate(&mut template, args, config);
+
+    let gravity = match sweep_args.value_of("gravity_range") {
+        Some(range) => helper::parse_range(range).unwrap(),
+        None => vec![template.read_resource::<GravitationalConstant>().0]
+    };
+    let softening = match sweep_args.value_of("softening_range") {
+        Some(range) => helper::parse_range(range).unwrap(),
+        None => vec![template.read_resource::<GravitationalSoftening>().0]
+    };
+    let parallel = sweep_args.is_present("parallel");
+    info!("Sweeping {} combination(s)...", gravity.len() * softening.len());
+
+    let run = |g: f64, s: f64| {
+        let label = format!("G{}-S{}", g, s);
+        info!("Simulating combination \"{}\"...", label);
+        let mut world = build_world_from_template(&template, args, config, &label);
+        helper::copy_entities(&template, &mut world);
+        world.insert(GravitationalConstant(g));
+        world.insert(GravitationalSoftening(s));
+        simulate(world, args, config, first_step, !parallel);
+    };
+    if parallel {
+        std::thread::scope(|scope| {
+            for &g in &gravity {
+                for &s in &softening {
+                    scope.spawn(move || run(g, s));
+                }
+            }
+        });
+    } else {
+        for &g in &gravity {
+            for &s in &softening {
+                run(g, s);
+            }
+        }
+    }
+}
+
+/// Builds the thread pool systems are run on, with the number of threads
+/// specified by `--threads` (or the number of cores).
+fn build_thread_pool(args: &clap::ArgMatches) -> std::sync::Arc<specs::rayon::ThreadPool> {
+    info!("Building thread pool...");
+    let mut pool_builder = specs::rayon::ThreadPoolBuilder::new();
+    if let Some(threads) = args.value_of("threads") {
+        pool_builder = pool_builder.num_threads(threads.parse::<usize>().unwrap());
+    }
+    let pool = std::sync::Arc::new(pool_builder.build().expect("Unable to build thread pool."));
+    info!("Using {} thread(s).", pool.current_num_threads());
+    pool
+}
+
+/// Simulates the specified (populated) world, starting at the specified step,
+/// returning the world once every output file has been flushed.
+///
+/// The progress bar is hidden unless `show_progress` is set.
+fn simulate(mut world: specs::World, args: &clap::ArgMatches, config: &SimulationConfig, first_step: u128, show_progress: bool) -> specs::World {
+    let pool = build_thread_pool(args);
+
+    info!("Building dispatchers...");
+    let mut dispatcher_builder = DispatcherBuilder::new()
+        .with_pool(pool.clone())
         .with(
             ClearForces,
             "clear_forces",
             &[]
         )
-        .with(
-            WriteOutput,
-            "write_output",
-            &[]
-        )
         .with(
             UpdateLifetimes,
             "update_lifetimes",
             &[]
         )
+        .with(
+            HandleDrag,
+            "handle_drag",
+            &["clear_forces"]
+        )
         .with(
             HandleElectrostatics,
             "handle_electrostatics",
@@ -103,15 +540,45 @@ fn main() {
             "handle_gravity",
             &["clear_forces"]
         )
+        .with(
+            HandleBackground,
+            "handle_background",
+            &["clear_forces"]
+        )
+        .with(
+            HandleStirring,
+            "handle_stirring",
+            &["clear_forces"]
+        )
+        .with(
+            HandleInteractiveForce,
+            "handle_interactive_force",
+            &["clear_forces"]
+        )
         .with(
             HandleForces,
             "handle_forces",
-            &["handle_electrostatics", "handle_gravity"]
+            &["handle_background", "handle_drag", "handle_electrostatics", "handle_gravity", "handle_interactive_force", "handle_stirring"]
+        );
+    if args.value_of("scenario") == Some("tidally-locked") {
+        dispatcher_builder.add(HandleTidalTorque, "handle_tidal_torque", &["clear_forces"]);
+        dispatcher_builder.add(HandleTorques, "handle_torques", &["handle_tidal_torque"]);
+    } else {
+        dispatcher_builder.add(HandleTorques, "handle_torques", &["clear_forces"]);
+    }
+    dispatcher_builder.add(HandleOrientation, "handle_orientation", &["handle_torques"]);
+    let mut dispatcher = dispatcher_builder.build();
+    let mut substep_dispatcher = DispatcherBuilder::new()
+        .with_pool(pool.clone())
+        .with(
+            ClearCollisions,
+            "clear_collisions",
+            &[]
         )
         .with(
             HandleDynamics,
             "handle_dynamics",
-            &["handle_forces"]
+            &[]
         )
         .with(
             CollisionDetection,
@@ -123,29 +590,469 @@ fn main() {
             "handle_collisions",
             &["collision_detection"]
         )
+        .build();
+    let mut splitting_dispatcher_builder = DispatcherBuilder::new()
+        .with_pool(pool.clone())
         .with(
             HandleSplitting,
             "handle_splitting",
-            &["handle_collisions", "update_lifetimes"]
-        )
-        .build();
+            &[]
+        );
+    if args.is_present("escape_distance") {
+        splitting_dispatcher_builder.add(HandleEscapes, "handle_escapes", &["handle_splitting"]);
+    }
+    let mut splitting_dispatcher = splitting_dispatcher_builder.build();
+    let mut output_dispatcher_builder = DispatcherBuilder::new()
+        .with_pool(pool)
+        .with(
+            WriteOutput,
+            "write_output",
+            &[]
+        );
+    if args.is_present("correlation") {
+        output_dispatcher_builder.add(WriteCorrelation, "write_correlation", &[]);
+    }
+    if args.is_present("frames") {
+        output_dispatcher_builder.add(WriteFrames::default(), "write_frames", &[]);
+    }
+    if args.is_present("neighbors") {
+        output_dispatcher_builder.add(WriteNeighbors, "write_neighbors", &[]);
+    }
+    if args.value_of("scenario") == Some("plasma-oscillation") {
+        output_dispatcher_builder.add(MeasurePlasmaOscillation, "measure_plasma_oscillation", &[]);
+    }
+    if args.is_present("profile") {
+        output_dispatcher_builder.add(WriteProfile, "write_profile", &[]);
+    }
+    if args.is_present("summary") {
+        output_dispatcher_builder.add(WriteSummary, "write_summary", &[]);
+    }
+    let mut output_dispatcher = output_dispatcher_builder.build();
 
-    info!("Building entities...");
-    helper::populate_entities(&mut world, 1000);
-                              
     info!("Starting simulation...");
-    let steps = args.value_of("steps").unwrap().parse::<u128>().unwrap();
-    let pb = indicatif::ProgressBar::new(steps.try_into().unwrap());
-    pb.set_prefix("Progress");
+    let dt = world.read_resource::<DeltaTime>().0;
+    let duration = args.value_of("duration").map(|d| d.parse::<f64>().unwrap() / world.read_resource::<UnitScale>().time);
+    let steps = helper::step_count(
+        SimulationConfig::resolve(args, "steps", &config.steps).map(|s| s.parse::<u128>().unwrap()),
+        duration,
+        dt
+    );
+    let mut elapsed: f64 = 0.0;
+    let pb = match show_progress {
+        true => indicatif::ProgressBar::new(steps.try_into().unwrap_or(u64::MAX)),
+        false => indicatif::ProgressBar::hidden()
+    };
+    let eta_window = args.value_of("eta_window").unwrap().parse::<usize>().unwrap();
+    let mut step_times = helper::MovingAverage::new(eta_window);
+    if eta_window > 0 {
+        pb.set_prefix("--:--:--");
+        pb.set_style(indicatif::ProgressStyle::default_bar()
+                     .template("Progress: [ETA: {prefix}] [{pos}/{len} ({percent}%)] {msg} {wide_bar}")
+        );
+    } else {
+        pb.set_prefix("Progress");
+        pb.set_style(indicatif::ProgressStyle::default_bar()
+                     .template("{prefix}: [ETA: {eta}] [{pos}/{len} ({percent}%)] {msg} {wide_bar}")
+        );
+    }
+    let substeps = world.read_resource::<CollisionSubsteps>().0;
+    let progress_interval = args.value_of("progress_interval").unwrap().parse::<u128>().unwrap();
+    let steps = helper::step_range(first_step, steps);
+    let last_step = *steps.end();
+    for step in steps {
+        if let Some(d) = duration {
+            if elapsed >= d {
+                info!("Reached simulation duration of {}.", d);
+                break;
+            }
+        }
+        let step_start = std::time::Instant::now();
+        pb.inc(1);
+        world.write_resource::<CurrentStep>().0 = step;
+        info!("Computing step {} of {}...", step, last_step);
+        let num_entities = (&world.entities()).join().count();
+        debug!("Number of entities: {}", num_entities);
+        // Computing the total energy takes time quadratic in the number of
+        // entities, so it is skipped when the progress bar is not drawn.
+        if !pb.is_hidden() && (step == first_step || step % progress_interval == 0) {
+            pb.set_message(&helper::progress_message(num_entities, helper::total_energy(&world)));
+        }
+        dispatcher.dispatch(&world);
+        for _ in 0..substeps {
+            substep_dispatcher.dispatch(&world);
+            maintain_if_required(&mut world);
+        }
+        splitting_dispatcher.dispatch(&world);
+        maintain_if_required(&mut world);
+        output_dispatcher.dispatch(&world);
+        elapsed += dt;
+        step_times.push(step_start.elapsed().as_secs_f64());
+        if let Some(mean) = step_times.mean() {
+            pb.set_prefix(&helper::format_duration(mean * (last_step - step) as f64));
+        }
+    }
+    pb.finish();
+    if args.value_of("scenario") == Some("plasma-oscillation") {
+        let oscillation = world.read_resource::<PlasmaOscillation>();
+        match oscillation.measured_frequency() {
+            Some(f) => info!("Measured plasma frequency of {:.4e} (analytic: {:.4e}).", f, oscillation.analytic_frequency),
+            None => warn!("Unable to measure plasma frequency - no complete half-period was observed.")
+        }
+    }
+    if args.value_of("scenario") == Some("tidally-locked") {
+        let locking = world.read_resource::<TidalLocking>();
+        match (locking.spin_period, locking.orbital_period) {
+            (Some(spin), Some(orbit)) => info!("Final spin period of {:.4e} (orbital period: {:.4e}).", spin, orbit),
+            _ => warn!("Unable to measure spin and orbital periods.")
+        }
+    }
+    info!("Total energy removed by velocity clamping: {:.4e}", world.read_resource::<TotalClampedEnergy>().0);
+    info!("Total energy dissipated by collisions: {:.4e}", world.read_resource::<TotalDissipatedEnergy>().0);
+
+    // Note that `OutputWriter` resources are also finished when `world` is
+    // dropped, so that output is not lost if a system panics above.
+    let flushed = world.write_resource::<OutputWriter<OutputEntry>>().finish();
+    if let Err(e) = flushed {
+        error!("Unable to flush output file - {}", e);
+    }
+    let flushed = world.write_resource::<CorrelationWriter>().finish();
+    if let Err(e) = flushed {
+        error!("Unable to flush two-point correlation file - {}", e);
+    }
+    let flushed = world.write_resource::<NeighborsWriter>().finish();
+    if let Err(e) = flushed {
+        error!("Unable to flush nearest-neighbor distance file - {}", e);
+    }
+    let flushed = world.write_resource::<ProfileWriter>().finish();
+    if let Err(e) = flushed {
+        error!("Unable to flush profile file - {}", e);
+    }
+    let flushed = world.write_resource::<SummaryWriter>().finish();
+    if let Err(e) = flushed {
+        error!("Unable to flush summary file - {}", e);
+    }
+    world
+}
+
+/// Plays back the specified output file, stepping through its entries with a
+/// progress bar and re-emitting each of them (as a YAML document) to the
+/// specified writer.
+fn play_back<W: std::io::Write>(path: &str, mut out: W) -> std::io::Result<()> {
+    let entries = output::read_entries(path)?;
+    info!("Loaded {} entries.", entries.len());
+    let pb = indicatif::ProgressBar::new(entries.len().try_into().unwrap_or(u64::MAX));
+    pb.set_prefix("Playback");
     pb.set_style(indicatif::ProgressStyle::default_bar()
-                 .template("{prefix}: [ETA: {eta}] [{pos}/{len} ({percent}%)] {wide_bar}")
+                 .template("{prefix}: [{pos}/{len} ({percent}%)] {msg} {wide_bar}")
     );
-    for step in 1..(steps + 1) {
+    for entry in entries {
         pb.inc(1);
-        info!("Computing step {} of {}...", step, steps);
-        debug!("Number of entities: {}", (&world.entities()).join().count());
-        dispatcher.dispatch(&mut world);
-        world.maintain();
+        pb.set_message(&format!("[Step: {}] [Entities: {}]", entry.step, entry.entities.len()));
+        let document = serde_yaml::to_string(&entry).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        writeln!(out, "{}", document)?;
     }
     pb.finish();
+    Ok(())
+}
+
+/// Maintains the specified world if any entities have been created or deleted
+/// since it was last maintained.
+fn maintain_if_required(world: &mut specs::World) {
+    if world.read_resource::<MaintenanceRequired>().0 {
+        trace!("Maintaining world...");
+        world.maintain();
+        world.write_resource::<MaintenanceRequired>().0 = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_writes_a_summary_file_for_each_combination() {
+        let directory = std::env::temp_dir().join(format!("grav-{}-sweep", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let output = directory.join("output.yaml");
+        let summary = directory.join("summary.yaml");
+        let args = cli::get_arguments_from(vec![
+            "grav",
+            "--num-entities", "10",
+            "--output", output.to_str().unwrap(),
+            "--steps", "2",
+            "--summary", summary.to_str().unwrap(),
+            "--summary-interval", "1",
+            "sweep",
+            "--gravity-range", "1:2:2",
+            "--softening-range", "0:0.5:2"
+        ]);
+        sweep(&args, args.subcommand_matches("sweep").unwrap(), &SimulationConfig::default());
+        let mut files: Vec<String> = std::fs::read_dir(&directory).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("summary"))
+            .collect();
+        files.sort();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(files, vec![
+            "summary.G1-S0.5.yaml",
+            "summary.G1-S0.yaml",
+            "summary.G2-S0.5.yaml",
+            "summary.G2-S0.yaml"
+        ]);
+    }
+
+    #[test]
+    fn resumed_runs_continue_the_step_numbering() {
+        let directory = std::env::temp_dir().join(format!("grav-{}-resume", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let first = directory.join("first.yaml");
+        let second = directory.join("second.yaml");
+        let run = | arguments: Vec<&str> | {
+            let args = cli::get_arguments_from(arguments);
+            let config = SimulationConfig::default();
+            let mut world = build_world(&args, &config, None);
+            let first_step = populate(&mut world, &args, &config);
+            simulate(world, &args, &config, first_step, false);
+        };
+        run(vec!["grav", "--num-entities", "5", "--output", first.to_str().unwrap(), "--steps", "10"]);
+        run(vec!["grav", "--resume-from-output", first.to_str().unwrap(), "--output", second.to_str().unwrap(), "--steps", "5"]);
+        let steps: Vec<u128> = crate::output::read_entries(second.to_str().unwrap()).unwrap().iter().map(|e| e.step).collect();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(steps, (11..=15).collect::<Vec<u128>>());
+    }
+
+    #[test]
+    fn the_largest_step_count_is_accepted_when_a_duration_ends_the_run() {
+        let output = std::env::temp_dir().join(format!("grav-{}-largest-step-count.yaml", std::process::id()));
+        let steps = u128::MAX.to_string();
+        let args = cli::get_arguments_from(vec![
+            "grav",
+            "--delta-time", "0.1",
+            "--duration", "0.2",
+            "--num-entities", "3",
+            "--output", output.to_str().unwrap(),
+            "--steps", &steps
+        ]);
+        let config = SimulationConfig::default();
+        let mut world = build_world(&args, &config, None);
+        let first_step = populate(&mut world, &args, &config);
+        simulate(world, &args, &config, first_step, false);
+        let entries = crate::output::read_entries(output.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        assert_eq!(entries.len(), 2);
+    }
+
+    #[test]
+    fn empty_simulations_write_empty_output() {
+        let directory = std::env::temp_dir().join(format!("grav-{}-empty", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let output = directory.join("output.yaml");
+        let summary = directory.join("summary.yaml");
+        let args = cli::get_arguments_from(vec![
+            "grav",
+            "--escape-distance", "10",
+            "--num-entities", "0",
+            "--output", output.to_str().unwrap(),
+            "--steps", "5",
+            "--summary", summary.to_str().unwrap(),
+            "--summary-interval", "1"
+        ]);
+        let config = SimulationConfig::default();
+        let mut world = build_world(&args, &config, None);
+        let first_step = populate(&mut world, &args, &config);
+        simulate(world, &args, &config, first_step, false);
+        let entries = crate::output::read_entries(output.to_str().unwrap()).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(entries.iter().map(|e| e.step).collect::<Vec<u128>>(), vec![1, 2, 3, 4, 5]);
+        assert!(entries.iter().all(|e| e.entities.is_empty()));
+    }
+
+    #[test]
+    fn playback_re_emits_every_recorded_step() {
+        let path = std::env::temp_dir().join(format!("grav-{}-playback.yaml", std::process::id()));
+        let args = cli::get_arguments_from(vec!["grav", "--num-entities", "4", "--output", path.to_str().unwrap(), "--steps", "3"]);
+        let config = SimulationConfig::default();
+        let mut world = build_world(&args, &config, None);
+        let first_step = populate(&mut world, &args, &config);
+        simulate(world, &args, &config, first_step, false);
+        let mut played = Vec::new();
+        play_back(path.to_str().unwrap(), &mut played).unwrap();
+        let recorded = output::read_entries(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let played = String::from_utf8(played).unwrap();
+        let replayed: Vec<OutputEntry> = serde_yaml::Deserializer::from_str(&played)
+            .map(|document| serde::Deserialize::deserialize(document).unwrap())
+            .collect();
+        assert_eq!(replayed.len(), 3);
+        assert!(replayed.iter().all(|e| e.entities.len() == 4));
+        assert_eq!(replayed, recorded);
+    }
+
+    #[test]
+    fn a_single_thread_runs_every_system_on_the_same_thread() {
+        /// Records the thread it was run on.
+        struct RecordThread(std::sync::Arc<std::sync::Mutex<Vec<std::thread::ThreadId>>>);
+        impl<'a> specs::System<'a> for RecordThread {
+            type SystemData = ();
+            fn run(&mut self, _: Self::SystemData) {
+                self.0.lock().unwrap().push(std::thread::current().id());
+            }
+        }
+        let pool = build_thread_pool(&cli::get_arguments_from(vec!["grav", "--threads", "1"]));
+        assert_eq!(pool.current_num_threads(), 1);
+        let threads = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut world = specs::World::new();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with_pool(pool)
+            .with(RecordThread(threads.clone()), "a", &[])
+            .with(RecordThread(threads.clone()), "b", &[])
+            .with(RecordThread(threads.clone()), "c", &[])
+            .build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+        let threads = threads.lock().unwrap();
+        assert_eq!(threads.len(), 3);
+        assert!(threads.iter().all(|id| *id == threads[0]));
+    }
+
+    #[test]
+    fn merged_inputs_are_absent_from_the_output_of_their_step() {
+        let output = std::env::temp_dir().join(format!("grav-{}-merge-output.yaml", std::process::id()));
+        let args = cli::get_arguments_from(vec!["grav", "--output", output.to_str().unwrap(), "--steps", "1"]);
+        let config: SimulationConfig = serde_yaml::from_str("
+            entities:
+              - mass: 1.0
+                position: [0.0, 0.0, 0.0]
+              - mass: 2.0
+                position: [0.5, 0.0, 0.0]
+              - mass: 4.0
+                position: [100.0, 0.0, 0.0]
+        ").unwrap();
+        let mut world = build_world(&args, &config, None);
+        let first_step = populate(&mut world, &args, &config);
+        simulate(world, &args, &config, first_step, false);
+        let entries = crate::output::read_entries(output.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        assert_eq!(entries.len(), 1);
+        let mut masses: Vec<f64> = entries[0].entities.iter().map(|e| e.mass).collect();
+        masses.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(masses, vec![3.0, 4.0]);
+    }
+
+    #[test]
+    fn configuration_files_populate_every_resource() {
+        assert!(SimulationConfig::load("examples/config.yaml").is_ok());
+        let path = std::env::temp_dir().join(format!("grav-{}-config.yaml", std::process::id()));
+        std::fs::write(&path, "
+            collision_limits:
+              maximum_detection_theshold: 12.0
+              minimum_detection_theshold: 0.25
+            delta_time: 0.125
+            distribution: big-bang
+            dynamics_limits:
+              maximum_acceleration: 7.0
+              maximum_position: 70.0
+              maximum_velocity: 3.0
+              minimum_acceleration: 0.5
+              minimum_position: 1.5
+              minimum_velocity: 0.75
+            electrostatic_constant: 0.25
+            gravitational_constant: 2.5
+            num_entities: 7
+            output: configured.yaml
+            steps: 42
+        ").unwrap();
+        let config = SimulationConfig::load(&path.to_string_lossy()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let args = cli::get_arguments_from(vec!["grav", "--electrostatic", "0.5"]);
+        let mut world = build_world(&args, &config, None);
+        populate(&mut world, &args, &config);
+        let collision_limits = world.read_resource::<CollisionLimits>();
+        assert_eq!((collision_limits.maximum_detection_theshold, collision_limits.minimum_detection_theshold), (12.0, 0.25));
+        let dynamics_limits = world.read_resource::<DynamicsLimits>();
+        assert_eq!(
+            (dynamics_limits.maximum_acceleration, dynamics_limits.maximum_position, dynamics_limits.maximum_velocity),
+            (7.0, 70.0, 3.0)
+        );
+        assert_eq!(
+            (dynamics_limits.minimum_acceleration, dynamics_limits.minimum_position, dynamics_limits.minimum_velocity),
+            (0.5, 1.5, 0.75)
+        );
+        assert_eq!(world.read_resource::<DeltaTime>().0, 0.125);
+        assert_eq!(world.read_resource::<ElectrostaticConstant>().0, 0.5);
+        assert_eq!(world.read_resource::<GravitationalConstant>().0, 2.5);
+        assert_eq!(world.read_resource::<OutputFile>().0, "configured.yaml");
+        assert_eq!(world.entities().join().count(), 7);
+        assert_eq!(SimulationConfig::resolve(&args, "steps", &config.steps), Some(String::from("42")));
+        assert_eq!(SimulationConfig::resolve(&args, "distribution", &config.distribution), Some(String::from("big-bang")));
+    }
+
+
+    #[test]
+    fn forked_members_with_different_seeds_diverge_from_the_same_initial_state() {
+        let directory = std::env::temp_dir().join(format!("grav-{}-ensemble", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let output = directory.join("output.yaml");
+        let args = cli::get_arguments_from(vec![
+            "grav",
+            "--num-entities", "5",
+            "--output", output.to_str().unwrap(),
+            "--steps", "20",
+            "--stirring-amplitude", "1.0",
+            "ensemble",
+            "--members", "2"
+        ]);
+        let config = SimulationConfig::default();
+        let mut template = build_world(&args, &config, None);
+        let first_step = populate(&mut template, &args, &config);
+        let positions = | world: &specs::World | {
+            let mut positions: Vec<(u64, math::Vector)> = (&world.read_storage::<ecs::components::ParticleId>(), &world.read_storage::<ecs::components::Dynamics>()).join()
+                .map(|(id, d)| (id.0, d.position))
+                .collect();
+            positions.sort_by_key(|(id, _)| *id);
+            positions
+        };
+        let fork = | label: &str, seed: u64 | {
+            let mut world = build_world_from_template(&template, &args, &config, label);
+            helper::fork_entities(&template, &mut world, seed, 0.0);
+            world
+        };
+        let members = vec![fork("E0", 1), fork("E1", 1), fork("E2", 2)];
+        for member in &members {
+            assert_eq!(positions(member), positions(&template));
+        }
+        let finals: Vec<Vec<(u64, math::Vector)>> = members.into_iter()
+            .map(|member| positions(&simulate(member, &args, &config, first_step, false)))
+            .collect();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let separation = | a: &[(u64, math::Vector)], b: &[(u64, math::Vector)] | {
+            a.iter().zip(b).map(|((_, p), (_, q))| p.distance(*q)).fold(0.0, f64::max)
+        };
+        assert!(separation(&finals[0], &finals[1]) < 1e-9);
+        assert!(separation(&finals[0], &finals[2]) > 1e-3);
+    }
+
+    #[test]
+    fn ensembles_write_output_for_each_member() {
+        let directory = std::env::temp_dir().join(format!("grav-{}-ensemble-files", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let output = directory.join("output.yaml");
+        let args = cli::get_arguments_from(vec![
+            "grav",
+            "--num-entities", "5",
+            "--output", output.to_str().unwrap(),
+            "--seed", "7",
+            "--steps", "2",
+            "ensemble",
+            "--jitter", "0.1",
+            "--members", "3"
+        ]);
+        ensemble(&args, args.subcommand_matches("ensemble").unwrap(), &SimulationConfig::default());
+        let mut files: Vec<String> = std::fs::read_dir(&directory).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(files, vec!["output.E0.yaml", "output.E1.yaml", "output.E2.yaml"]);
+    }
 }
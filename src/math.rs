@@ -1,5 +1,50 @@
 //! Contains definitions of various mathematical constructs.
 
+/// Represents a 3×3 matrix in row-major order.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Matrix(pub [[f64; 3]; 3]);
+
+impl Matrix {
+    /// Returns the eigenvalues of this matrix in descending order, assuming
+    /// that it is symmetric.
+    ///
+    /// The eigenvalues are computed via cyclic Jacobi rotations, which converge
+    /// quickly for matrices of this size.
+    pub fn symmetric_eigenvalues(&self) -> [f64; 3] {
+        let mut a = self.0;
+        for _ in 0..50 {
+            let off_diagonal = (a[0][1] * a[0][1]) + (a[0][2] * a[0][2]) + (a[1][2] * a[1][2]);
+            if off_diagonal < 1.0e-24 {
+                break;
+            }
+            for &(p, q) in &[(0, 1), (0, 2), (1, 2)] {
+                if a[p][q] == 0.0 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + ((theta * theta) + 1.0).sqrt());
+                let c = 1.0 / ((t * t) + 1.0).sqrt();
+                let s = t * c;
+                for row in a.iter_mut() {
+                    let akp = row[p];
+                    let akq = row[q];
+                    row[p] = (c * akp) - (s * akq);
+                    row[q] = (s * akp) + (c * akq);
+                }
+                let (row_p, row_q) = (a[p], a[q]);
+                for (k, (apk, aqk)) in row_p.iter().zip(row_q.iter()).enumerate() {
+                    a[p][k] = (c * apk) - (s * aqk);
+                    a[q][k] = (s * apk) + (c * aqk);
+                }
+            }
+        }
+        let mut eigenvalues = [a[0][0], a[1][1], a[2][2]];
+        eigenvalues.sort_by(|x, y| y.partial_cmp(x).unwrap_or(std::cmp::Ordering::Equal));
+        eigenvalues
+    }
+}
+
+
 /// Represents the various shapes that an object may have.
 #[derive(Clone, Copy, Debug)]
 pub enum Shape {
@@ -14,6 +59,42 @@ pub enum Shape {
     Sphere(f64),
 }
 
+impl Shape {
+    /// Returns the radius of the smallest sphere (sharing the same center)
+    /// which encloses this shape.
+    pub fn bounding_radius(&self) -> f64 {
+        match *self {
+            Shape::Cuboid(x, y, z) => ((x * x) + (y * y) + (z * z)).sqrt(),
+            Shape::Point => 0.0,
+            Shape::Sphere(r) => r
+        }
+    }
+
+    /// Returns the moment of inertia of this shape (assumed to be of uniform
+    /// density) with the specified mass about an axis through its center.
+    ///
+    /// Cuboids are not rotationally symmetric, so the mean of their three
+    /// principal moments is used.
+    pub fn moment_of_inertia(&self, mass: f64) -> f64 {
+        match *self {
+            Shape::Cuboid(x, y, z) => 2.0 * mass * ((x * x) + (y * y) + (z * z)) / 9.0,
+            Shape::Point => 0.0,
+            Shape::Sphere(r) => 0.4 * mass * r * r
+        }
+    }
+
+    /// Returns the area of this shape projected onto the plane perpendicular to
+    /// the specified direction.
+    pub fn cross_sectional_area(&self, direction: Vector) -> f64 {
+        let d = direction.direction();
+        match *self {
+            Shape::Cuboid(x, y, z) => 4.0 * ((y * z * d.0.abs()) + (x * z * d.1.abs()) + (x * y * d.2.abs())),
+            Shape::Point => 0.0,
+            Shape::Sphere(r) => std::f64::consts::PI * r * r
+        }
+    }
+}
+
 /// Implements `std::default::Default` for `Shape`.
 impl std::default::Default for Shape {
     fn default() -> Self { Shape::Point }
@@ -21,10 +102,66 @@ impl std::default::Default for Shape {
 
 
 /// Represents a 3D mathematical vector.
-#[derive(Clone, Copy, Debug, Serialize)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vector(pub f64, pub f64, pub f64);
 
 impl Vector {
+    /// Returns the angle (in radians) between this vector and another one, or
+    /// zero if either vector has no magnitude.
+    ///
+    /// The cosine is clamped to `[-1, 1]` so that floating-point error cannot
+    /// produce `NaN` for (anti)parallel vectors.
+    pub fn angle_between(&self, other: Vector) -> f64 {
+        let mags = self.magnitude() * other.magnitude();
+        if mags == 0.0 {
+            0.0
+        } else {
+            (self.dot(other) / mags).clamp(-1.0, 1.0).acos()
+        }
+    }
+
+    /// Returns whether each component of this vector is within `epsilon` (in
+    /// absolute value) of the corresponding component of another one.
+    pub fn approx_eq(&self, other: Vector, epsilon: f64) -> bool {
+        (self.0 - other.0).abs() <= epsilon && (self.1 - other.1).abs() <= epsilon && (self.2 - other.2).abs() <= epsilon
+    }
+
+    /// Returns this vector with each component clamped between the
+    /// corresponding components of `min` and `max`.
+    pub fn clamp(&self, min: Vector, max: Vector) -> Vector {
+        self.component_max(min).component_min(max)
+    }
+
+    /// Returns this vector rescaled (if necessary) so that its magnitude lies
+    /// within the specified limits.
+    ///
+    /// The zero vector has no direction to rescale along, so it is returned
+    /// unchanged.
+    pub fn clamp_magnitude(&self, min: f64, max: f64) -> Vector {
+        let mag = self.magnitude();
+        if mag == 0.0 {
+            *self
+        } else if mag < min {
+            *self * (min / mag)
+        } else if mag > max {
+            *self * (max / mag)
+        } else {
+            *self
+        }
+    }
+
+    /// Returns the vector made of the larger of each pair of components of this
+    /// vector and another one.
+    pub fn component_max(&self, other: Vector) -> Vector {
+        Vector(self.0.max(other.0), self.1.max(other.1), self.2.max(other.2))
+    }
+
+    /// Returns the vector made of the smaller of each pair of components of
+    /// this vector and another one.
+    pub fn component_min(&self, other: Vector) -> Vector {
+        Vector(self.0.min(other.0), self.1.min(other.1), self.2.min(other.2))
+    }
+
     /// Returns the cross product between this vector and another one.
     pub fn cross(&self, other: Vector) -> Vector {
         Vector(
@@ -34,14 +171,22 @@ impl Vector {
         )
     }
     
-    /// Returns the direction of this vector (as a unit vector).
+    /// Returns the direction of this vector (as a unit vector), or the zero
+    /// vector if this vector has no magnitude.
     pub fn direction(&self) -> Vector {
-        let mag = self.magnitude();
-        if mag != 0.0 {
-            *self / mag
-        } else {
-            Vector::default()
-        }
+        self.try_direction().unwrap_or_default()
+    }
+
+    /// Returns the distance between the point represented by this vector and
+    /// the point represented by another one.
+    pub fn distance(&self, other: Vector) -> f64 {
+        self.distance_squared(other).sqrt()
+    }
+
+    /// Returns the squared distance between the point represented by this
+    /// vector and the point represented by another one.
+    pub fn distance_squared(&self, other: Vector) -> f64 {
+        (other - *self).magnitude_squared()
     }
 
     /// Returns the dot product between this vector and another one.
@@ -49,22 +194,170 @@ impl Vector {
         (self.0 * other.0) + (self.1 * other.1) + (self.2 * other.2)
     }
 
+    /// Returns the linear interpolation between this vector (at `t = 0`) and
+    /// another one (at `t = 1`).
+    ///
+    /// Values of `t` outside of `[0, 1]` are not clamped, so they extrapolate
+    /// along the line through both vectors.
+    pub fn lerp(&self, other: Vector, t: f64) -> Vector {
+        (*self * (1.0 - t)) + (other * t)
+    }
+
     /// Returns the magnitude of this vector.
     pub fn magnitude(&self) -> f64 {
         ((self.0 * self.0) + (self.1 * self.1) + (self.2 * self.2)).sqrt()
     }
 
+    /// Returns the squared magnitude of this vector, which avoids computing a
+    /// square root when only comparing magnitudes.
+    pub fn magnitude_squared(&self) -> f64 {
+        (self.0 * self.0) + (self.1 * self.1) + (self.2 * self.2)
+    }
+
+    /// Sets this vector to its direction (as a unit vector), leaving the zero
+    /// vector unchanged.
+    pub fn normalize(&mut self) {
+        if let Some(direction) = self.try_direction() {
+            *self = direction;
+        }
+    }
+
+    /// Returns the projection of this vector onto another one, or the zero
+    /// vector if the other vector has no magnitude.
+    pub fn project(&self, onto: Vector) -> Vector {
+        let onto_mag_squared = onto.magnitude_squared();
+        if onto_mag_squared == 0.0 {
+            Vector::default()
+        } else {
+            onto * (self.dot(onto) / onto_mag_squared)
+        }
+    }
+
     /// Returns a new random vector with the specified length restrictions.
+    ///
+    /// The direction is uniformly distributed over the unit sphere (by
+    /// normalizing a vector of independent standard normal components), and the
+    /// length is uniformly distributed between `min` and `max`.
     pub fn random(min: f64, max: f64) -> Vector {
-        use rand::Rng;
-        let mut rng = rand::thread_rng();
-        Vector(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0)).direction() * rng.gen_range(min, max)
+        Vector::random_from(&mut rand::thread_rng(), min, max)
+    }
+
+    /// Returns a new random vector with the specified length restrictions,
+    /// distributed as in `random`, using the specified generator.
+    pub fn random_from<R: rand::Rng>(rng: &mut R, min: f64, max: f64) -> Vector {
+        let direction = loop {
+            if let Some(direction) = Vector::random_normal_from(rng).try_direction() {
+                break direction;
+            }
+        };
+        direction * rng.gen_range(min, max)
+    }
+
+    /// Returns a new random vector whose components are independently drawn
+    /// from the standard normal distribution.
+    pub fn random_normal() -> Vector {
+        Vector::random_normal_from(&mut rand::thread_rng())
+    }
+
+    /// Returns a new random vector whose components are independently drawn
+    /// from the standard normal distribution, using the specified generator.
+    pub fn random_normal_from<R: rand::Rng>(rng: &mut R) -> Vector {
+        let mut sample = || {
+            let u1: f64 = 1.0 - rng.gen::<f64>();
+            let u2: f64 = rng.gen::<f64>();
+            (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+        };
+        Vector(sample(), sample(), sample())
+    }
+
+    /// Returns the reflection of this vector off a surface with the specified
+    /// (unit) normal.
+    pub fn reflect(&self, normal: Vector) -> Vector {
+        *self - (normal * (2.0 * self.dot(normal)))
+    }
+
+    /// Returns the rejection of this vector from another one (the component of
+    /// this vector perpendicular to the other), or the zero vector if the other
+    /// vector has no magnitude.
+    pub fn reject(&self, onto: Vector) -> Vector {
+        if onto.magnitude_squared() == 0.0 {
+            Vector::default()
+        } else {
+            *self - self.project(onto)
+        }
+    }
+
+    /// Returns this vector rotated by the specified angle (in radians) around
+    /// the specified axis, following the right-hand rule.
+    ///
+    /// The rotation is computed with Rodrigues' rotation formula after
+    /// normalizing the axis. If the axis has no magnitude, this vector is
+    /// returned unchanged.
+    pub fn rotate_around_axis(&self, axis: Vector, angle_rad: f64) -> Vector {
+        let k = match axis.try_direction() {
+            Some(k) => k,
+            None => return *self
+        };
+        let (sin, cos) = angle_rad.sin_cos();
+        (*self * cos) + (k.cross(*self) * sin) + (k * (k.dot(*self) * (1.0 - cos)))
+    }
+
+    /// Returns a new vector with all three components set to the specified
+    /// value.
+    pub fn splat(v: f64) -> Vector {
+        Vector(v, v, v)
+    }
+
+    /// Returns the direction of this vector (as a unit vector), or `None` if
+    /// this vector has no magnitude.
+    pub fn try_direction(&self) -> Option<Vector> {
+        let mag = self.magnitude();
+        if mag != 0.0 {
+            Some(*self / mag)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the components of this vector as an array.
+    pub fn to_array(&self) -> [f64; 3] {
+        [self.0, self.1, self.2]
+    }
+
+    /// Returns the unit vector along the x axis.
+    pub fn unit_x() -> Vector {
+        Vector(1.0, 0.0, 0.0)
+    }
+
+    /// Returns the unit vector along the y axis.
+    pub fn unit_y() -> Vector {
+        Vector(0.0, 1.0, 0.0)
+    }
+
+    /// Returns the unit vector along the z axis.
+    pub fn unit_z() -> Vector {
+        Vector(0.0, 0.0, 1.0)
+    }
+
+    /// Returns the zero vector.
+    pub fn zero() -> Vector {
+        Vector(0.0, 0.0, 0.0)
     }
 }
 
 /// Implements `std::default::Default` for `Vector`.
 impl std::default::Default for Vector {
-    fn default() -> Self { Vector(0.0, 0.0, 0.0) }
+    fn default() -> Self { Vector::zero() }
+}
+
+/// Implements `std::convert::From<[f64; 3]>` for `Vector`.
+impl std::convert::From<[f64; 3]> for Vector {
+    fn from(array: [f64; 3]) -> Self { Vector(array[0], array[1], array[2]) }
+}
+
+/// Implements `std::convert::From<Vector>` for `[f64; 3]`.
+impl std::convert::From<Vector> for [f64; 3] {
+    fn from(vector: Vector) -> Self { vector.to_array() }
 }
 
 // ----- Operator Implementations -----
@@ -153,6 +446,33 @@ impl std::ops::DivAssign<f64> for Vector {
     }
 }
 
+/// Implements `std::ops::Index` for `Vector`, mapping `0`, `1` and `2` to the
+/// x, y and z components.
+impl std::ops::Index<usize> for Vector {
+    type Output = f64;
+    fn index(&self, index: usize) -> &f64 {
+        match index {
+            0 => &self.0,
+            1 => &self.1,
+            2 => &self.2,
+            _ => panic!("index out of bounds: a vector has 3 components but the index is {}", index)
+        }
+    }
+}
+
+/// Implements `std::ops::IndexMut` for `Vector`, mapping `0`, `1` and `2` to
+/// the x, y and z components.
+impl std::ops::IndexMut<usize> for Vector {
+    fn index_mut(&mut self, index: usize) -> &mut f64 {
+        match index {
+            0 => &mut self.0,
+            1 => &mut self.1,
+            2 => &mut self.2,
+            _ => panic!("index out of bounds: a vector has 3 components but the index is {}", index)
+        }
+    }
+}
+
 /// Implements `std::ops::Mul` between `Vector` and `Vector`.
 impl std::ops::Mul<Vector> for Vector {
     type Output = Vector;
@@ -186,6 +506,14 @@ impl std::ops::Mul<f64> for Vector {
     }
 }
 
+/// Implements `std::ops::Mul` between `f64` and `Vector`.
+impl std::ops::Mul<Vector> for f64 {
+    type Output = Vector;
+    fn mul(self, other: Vector) -> Vector {
+        other * self
+    }
+}
+
 /// Implements `std::ops::MulAssign` between `Vector` and `f64`.
 impl std::ops::MulAssign<f64> for Vector {
     fn mul_assign(&mut self, rhs: f64) {
@@ -259,13 +587,207 @@ impl<'a> std::iter::Sum<&'a Self> for Vector {
         for i in iter {
             result += *i;
         }
-        return result;
+        result
+    }
+}
+
+/// Implements `serde::Serialize` for `Vector`.
+///
+/// Vectors are written as a flat `[x, y, z]` sequence.
+impl serde::Serialize for Vector {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
+        serde::Serialize::serialize(&self.to_array(), serializer)
     }
 }
 
-///// Implements `Serialize` for `Vector`.
-//impl serde::Serialize for Vector {
-//    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
-//        serializer.serialize_str(&format!("[{}, {}, {}]", self.0, self.1, self.2))
-//    }
-//}
+/// Implements `serde::Deserialize` for `Vector`.
+///
+/// Vectors are read from a flat `[x, y, z]` sequence.
+impl<'de> serde::Deserialize<'de> for Vector {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
+        <[f64; 3] as serde::Deserialize>::deserialize(deserializer).map(Vector::from)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reflect_off_the_x_axis() {
+        assert_eq!(Vector(1.0, -2.0, 3.0).reflect(Vector::unit_y()), Vector(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn reflect_off_a_diagonal() {
+        let normal = Vector(1.0, 1.0, 0.0).direction();
+        assert!(Vector(1.0, 0.0, 0.0).reflect(normal).approx_eq(Vector(0.0, -1.0, 0.0), 1e-12));
+    }
+
+    #[test]
+    fn magnitude_squared_is_the_square_of_the_magnitude() {
+        let v = Vector(1.0, -2.0, 2.0);
+        assert_eq!(v.magnitude_squared(), 9.0);
+        assert_eq!(v.magnitude_squared(), v.magnitude() * v.magnitude());
+        assert_eq!(Vector::zero().magnitude_squared(), 0.0);
+    }
+
+    #[test]
+    fn distance_between_points() {
+        let p = Vector(1.0, 2.0, 3.0);
+        assert_eq!(p.distance(p), 0.0);
+        assert_eq!(p.distance(Vector(1.0, 2.0, -2.0)), 5.0);
+        assert_eq!(p.distance(Vector(4.0, 6.0, 3.0)), 5.0);
+        assert_eq!(p.distance_squared(Vector(4.0, 6.0, 3.0)), 25.0);
+    }
+
+    #[test]
+    fn vectors_serialize_as_flat_arrays() {
+        let yaml = serde_yaml::to_string(&Vector(1.0, -2.5, 3.0)).unwrap();
+        assert_eq!(yaml.trim_start_matches("---").trim(), "- 1.0\n- -2.5\n- 3.0");
+        assert_eq!(serde_yaml::from_str::<Vector>("[1.0, -2.5, 3.0]").unwrap(), Vector(1.0, -2.5, 3.0));
+        assert!(serde_yaml::from_str::<Vector>("[1.0, 2.0]").is_err());
+    }
+
+    #[test]
+    fn arrays_round_trip_through_vectors() {
+        assert_eq!(Vector::from([1.0, 2.0, 3.0]), Vector(1.0, 2.0, 3.0));
+        assert_eq!(Vector::from([1.0, 2.0, 3.0]).to_array(), [1.0, 2.0, 3.0]);
+        assert_eq!(<[f64; 3]>::from(Vector(-1.0, 0.5, 4.0)), [-1.0, 0.5, 4.0]);
+    }
+
+    #[test]
+    fn components_are_indexed_in_order() {
+        let mut v = Vector(1.0, 2.0, 3.0);
+        assert_eq!([v[0], v[1], v[2]], [1.0, 2.0, 3.0]);
+        v[0] = -1.0;
+        v[1] *= 2.0;
+        v[2] += 1.0;
+        assert_eq!(v, Vector(-1.0, 4.0, 4.0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn indexing_past_the_z_component_panics() {
+        let _ = Vector(1.0, 2.0, 3.0)[3];
+    }
+
+    #[test]
+    fn lerp_interpolates_and_extrapolates() {
+        let a = Vector(0.0, 2.0, -4.0);
+        let b = Vector(2.0, 4.0, 0.0);
+        assert_eq!(a.lerp(b, 0.0), a);
+        assert_eq!(a.lerp(b, 1.0), b);
+        assert_eq!(a.lerp(b, 0.5), Vector(1.0, 3.0, -2.0));
+        assert_eq!(a.lerp(b, 2.0), Vector(4.0, 6.0, 4.0));
+    }
+
+    #[test]
+    fn try_direction_of_zero_and_nonzero_vectors() {
+        assert_eq!(Vector::zero().try_direction(), None);
+        assert_eq!(Vector(0.0, -3.0, 4.0).try_direction(), Some(Vector(0.0, -0.6, 0.8)));
+        assert_eq!(Vector::zero().direction(), Vector::zero());
+    }
+
+    #[test]
+    fn clamp_magnitude_rescales_only_outside_the_limits() {
+        assert_eq!(Vector::zero().clamp_magnitude(1.0, 2.0), Vector::zero());
+        assert_eq!(Vector(0.0, 0.5, 0.0).clamp_magnitude(1.0, 2.0), Vector(0.0, 1.0, 0.0));
+        assert_eq!(Vector(0.0, 0.0, 1.5).clamp_magnitude(1.0, 2.0), Vector(0.0, 0.0, 1.5));
+        assert!(Vector(3.0, 4.0, 0.0).clamp_magnitude(1.0, 2.0).approx_eq(Vector(1.2, 1.6, 0.0), 1e-12));
+    }
+
+    #[test]
+    fn project_and_reject_decompose_a_vector() {
+        let v = Vector(3.0, 4.0, 0.0);
+        assert_eq!(v.project(Vector(2.0, 0.0, 0.0)), Vector(3.0, 0.0, 0.0));
+        assert_eq!(v.reject(Vector(2.0, 0.0, 0.0)), Vector(0.0, 4.0, 0.0));
+        assert_eq!(v.project(Vector::unit_z()), Vector::zero());
+        assert_eq!(v.reject(Vector::unit_z()), v);
+        assert_eq!(v.project(v * -2.0), v);
+        assert_eq!(v.reject(v * -2.0), Vector::zero());
+        assert_eq!(v.project(Vector::zero()), Vector::zero());
+        assert_eq!(v.reject(Vector::zero()), Vector::zero());
+    }
+
+    #[test]
+    fn angle_between_orthogonal_parallel_and_antiparallel_vectors() {
+        let v = Vector(1.0, 1.0, 0.0);
+        assert!((v.angle_between(Vector(1.0, -1.0, 0.0)) - std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+        assert!(v.angle_between(v * 3.0).abs() < 1e-6);
+        assert!((v.angle_between(v * -0.1) - std::f64::consts::PI).abs() < 1e-6);
+        assert_eq!(v.angle_between(Vector::zero()), 0.0);
+    }
+
+    #[test]
+    fn rotating_x_a_quarter_turn_around_z_gives_y() {
+        let rotated = Vector::unit_x().rotate_around_axis(Vector(0.0, 0.0, 3.0), std::f64::consts::FRAC_PI_2);
+        assert!(rotated.approx_eq(Vector::unit_y(), 1e-12), "{:?}", rotated);
+        assert_eq!(Vector::unit_z().rotate_around_axis(Vector::unit_z(), 1.0), Vector::unit_z());
+        assert_eq!(Vector::unit_x().rotate_around_axis(Vector::zero(), 1.0), Vector::unit_x());
+    }
+
+    #[test]
+    fn random_directions_are_uniform_on_the_sphere() {
+        let samples = 60_000;
+        let mut bins = [0usize; 6];
+        for _ in 0..samples {
+            let v = Vector::random(1.0, 2.0);
+            let magnitude = v.magnitude();
+            assert!((1.0..=2.0).contains(&magnitude), "{}", magnitude);
+            let cos_theta = v.2 / magnitude;
+            let hemisphere = if v.0 >= 0.0 { 0 } else { 3 };
+            bins[hemisphere + (((cos_theta + 1.0) / 2.0 * 3.0) as usize).min(2)] += 1;
+        }
+        let expected = samples as f64 / 6.0;
+        for count in &bins {
+            assert!((*count as f64 / expected - 1.0).abs() < 0.05, "{:?}", bins);
+        }
+    }
+
+    #[test]
+    fn scalar_multiplication_commutes() {
+        let v = Vector(1.5, -2.0, 0.25);
+        assert_eq!(2.0 * v, v * 2.0);
+        assert_eq!(-0.5 * v, Vector(-0.75, 1.0, -0.125));
+    }
+
+    #[test]
+    fn component_min_max_and_clamp_with_mixed_signs() {
+        let a = Vector(-1.0, 2.0, -3.0);
+        let b = Vector(1.0, -2.0, -4.0);
+        assert_eq!(a.component_min(b), Vector(-1.0, -2.0, -4.0));
+        assert_eq!(a.component_max(b), Vector(1.0, 2.0, -3.0));
+        assert_eq!(Vector(-5.0, 0.5, 5.0).clamp(Vector::splat(-1.0), Vector::splat(1.0)), Vector(-1.0, 0.5, 1.0));
+    }
+
+    #[test]
+    fn approx_eq_includes_the_epsilon_boundary() {
+        let v = Vector(1.0, 2.0, 3.0);
+        assert!(v.approx_eq(Vector(1.5, 1.5, 3.0), 0.5));
+        assert!(!v.approx_eq(Vector(1.5, 1.5, 3.0), 0.49));
+        assert!(!v.approx_eq(Vector(1.0, 2.0, 3.75), 0.5));
+        assert!(v.approx_eq(v, 0.0));
+    }
+
+    #[test]
+    fn named_constructors_have_the_expected_components() {
+        assert_eq!(Vector::zero(), Vector(0.0, 0.0, 0.0));
+        assert_eq!(Vector::zero(), Vector::default());
+        assert_eq!(Vector::unit_x(), Vector(1.0, 0.0, 0.0));
+        assert_eq!(Vector::unit_y(), Vector(0.0, 1.0, 0.0));
+        assert_eq!(Vector::unit_z(), Vector(0.0, 0.0, 1.0));
+        assert_eq!(Vector::splat(-2.5), Vector(-2.5, -2.5, -2.5));
+    }
+
+    #[test]
+    fn normalize_in_place() {
+        let mut zero = Vector::zero();
+        zero.normalize();
+        assert_eq!(zero, Vector::zero());
+        let mut v = Vector(0.0, 3.0, -4.0);
+        v.normalize();
+        assert_eq!(v, Vector(0.0, 0.6, -0.8));
+    }
+}
@@ -0,0 +1,205 @@
+//! Contains an octree used to approximate long-range interactions with the
+//! Barnes-Hut algorithm.
+
+use crate::math::*;
+
+/// The maximum depth of the octree. Bodies which are still not separated at
+/// this depth (such as coincident bodies) share a single leaf.
+pub const OCTREE_MAXIMUM_DEPTH: u32 = 32;
+
+
+/// Represents a single (cubic) node of an octree.
+#[derive(Clone, Debug)]
+struct Node {
+    /// The indices of the bodies contained in this node, if it is a leaf.
+    bodies: Vec<usize>,
+
+    /// The center of the cube spanned by this node.
+    center: Vector,
+
+    /// The center of mass of the bodies contained in this node.
+    center_of_mass: Vector,
+
+    /// The indices of the non-empty children of this node.
+    children: Vec<usize>,
+
+    /// Half of the side length of the cube spanned by this node.
+    half_size: f64,
+
+    /// The total mass of the bodies contained in this node.
+    mass: f64
+}
+
+impl Node {
+    /// Returns whether the specified position lies within the cube spanned by
+    /// this node.
+    fn contains(&self, position: Vector) -> bool {
+        let d = position - self.center;
+        d.0.abs() <= self.half_size && d.1.abs() <= self.half_size && d.2.abs() <= self.half_size
+    }
+}
+
+
+/// Represents an octree over a set of point masses.
+///
+/// Each node records the total mass and center of mass of the bodies within
+/// it, so that distant groups of bodies may be treated as a single body.
+#[derive(Clone, Debug)]
+pub struct Octree {
+    /// The positions and masses of the bodies in the tree.
+    bodies: Vec<(Vector, f64)>,
+
+    /// The nodes of the tree. The root node (if any) is the first node.
+    nodes: Vec<Node>,
+
+    /// Whether nodes are only subdivided along the x and y axes, making the
+    /// tree a quadtree.
+    planar: bool
+}
+
+impl Octree {
+    /// Builds a new octree over the specified bodies, given as pairs of
+    /// position and mass.
+    pub fn new(bodies: Vec<(Vector, f64)>) -> Octree {
+        Octree::with_bodies(bodies, false)
+    }
+
+    /// Builds a new quadtree over the specified bodies, given as pairs of
+    /// position and mass, ignoring the z component of their positions.
+    ///
+    /// Each node has at most four children, which makes the tree cheaper to
+    /// build and traverse than an octree over the same (planar) bodies.
+    pub fn new_quadtree(bodies: Vec<(Vector, f64)>) -> Octree {
+        Octree::with_bodies(bodies.into_iter().map(|(p, m)| (Vector(p.0, p.1, 0.0), m)).collect(), true)
+    }
+
+    /// Builds a new tree over the specified bodies, subdividing nodes along
+    /// the x and y axes only if `planar` is set.
+    fn with_bodies(bodies: Vec<(Vector, f64)>, planar: bool) -> Octree {
+        let mut tree = Octree {
+            bodies,
+            nodes: Vec::new(),
+            planar
+        };
+        if tree.bodies.is_empty() {
+            return tree;
+        }
+        let min = tree.bodies.iter().fold(tree.bodies[0].0, |acc, (p, _)| acc.component_min(*p));
+        let max = tree.bodies.iter().fold(tree.bodies[0].0, |acc, (p, _)| acc.component_max(*p));
+        let extent = max - min;
+        let half_size = (extent.0.max(extent.1).max(extent.2) / 2.0).max(f64::MIN_POSITIVE);
+        let indices = (0..tree.bodies.len()).collect();
+        tree.build((min + max) / 2.0, half_size, indices, 0);
+        tree
+    }
+
+    /// Builds the node spanning the specified cube over the specified bodies,
+    /// returning its index.
+    fn build(&mut self, center: Vector, half_size: f64, indices: Vec<usize>, depth: u32) -> usize {
+        let mass: f64 = indices.iter().map(|i| self.bodies[*i].1).sum();
+        let center_of_mass = match mass != 0.0 {
+            true => indices.iter().map(|i| self.bodies[*i].0 * self.bodies[*i].1).fold(Vector::default(), |acc, p| acc + p) / mass,
+            false => indices.iter().map(|i| self.bodies[*i].0).fold(Vector::default(), |acc, p| acc + p) / indices.len() as f64
+        };
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bodies: Vec::new(),
+            center,
+            center_of_mass,
+            children: Vec::new(),
+            half_size,
+            mass
+        });
+        if indices.len() == 1 || depth >= OCTREE_MAXIMUM_DEPTH {
+            self.nodes[index].bodies = indices;
+            return index;
+        }
+        let mut octants: Vec<Vec<usize>> = vec![Vec::new(); if self.planar { 4 } else { 8 }];
+        for i in indices {
+            let p = self.bodies[i].0;
+            let octant = (p.0 > center.0) as usize | ((p.1 > center.1) as usize) << 1 | ((!self.planar && p.2 > center.2) as usize) << 2;
+            octants[octant].push(i);
+        }
+        let quarter_size = half_size / 2.0;
+        for (octant, octant_indices) in octants.into_iter().enumerate() {
+            if octant_indices.is_empty() {
+                continue;
+            }
+            let offset = Vector(
+                if octant & 1 != 0 { quarter_size } else { -quarter_size },
+                if octant & 2 != 0 { quarter_size } else { -quarter_size },
+                match (self.planar, octant & 4 != 0) {
+                    (true, _) => 0.0,
+                    (false, true) => quarter_size,
+                    (false, false) => -quarter_size
+                }
+            );
+            let child = self.build(center + offset, quarter_size, octant_indices, depth + 1);
+            self.nodes[index].children.push(child);
+        }
+        index
+    }
+
+    /// Calls the specified function with the position and mass of each source
+    /// acting on a body at the specified position.
+    ///
+    /// A node is treated as a single source located at its center of mass when
+    /// the ratio of its side length to its distance from the position is less
+    /// than `theta` and the position lies outside of it. Otherwise its children
+    /// (or, for a leaf, its individual bodies) are visited instead. The body
+    /// with the specified index (if any) is skipped, so that a body does not
+    /// act on itself. The z component of the position is ignored by a
+    /// quadtree.
+    pub fn visit_sources<F: FnMut(Vector, f64)>(&self, position: Vector, exclude: Option<usize>, theta: f64, mut f: F) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let position = match self.planar {
+            true => Vector(position.0, position.1, 0.0),
+            false => position
+        };
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.children.is_empty() {
+                for i in &node.bodies {
+                    if Some(*i) != exclude {
+                        f(self.bodies[*i].0, self.bodies[*i].1);
+                    }
+                }
+                continue;
+            }
+            let distance = position.distance(node.center_of_mass);
+            if !node.contains(position) && distance > 0.0 && (2.0 * node.half_size) / distance < theta {
+                f(node.center_of_mass, node.mass);
+            } else {
+                stack.extend(&node.children);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quadtree_sources_exclude_the_body_itself_at_large_opening_angles() {
+        let bodies: Vec<(Vector, f64)> = (0..64)
+            .map(|i| {
+                let jitter = Vector(((i * 37) % 17) as f64, ((i * 53) % 19) as f64, 0.0) / 40.0;
+                (Vector((i % 8) as f64, (i / 8) as f64, 0.0) + jitter, 1.0 + (i % 3) as f64)
+            })
+            .collect();
+        let total: f64 = bodies.iter().map(|(_, m)| m).sum();
+        let tree = Octree::new_quadtree(bodies.clone());
+        for theta in &[1.0, 2.0, 10.0] {
+            for (i, (position, mass)) in bodies.iter().enumerate() {
+                let mut visited = 0.0;
+                tree.visit_sources(*position, Some(i), *theta, |_, m| visited += m);
+                assert!((visited - (total - mass)).abs() < 1e-9, "body {} feels {} of {} at theta {}", i, visited, total - mass, theta);
+            }
+        }
+    }
+}
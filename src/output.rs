@@ -1,9 +1,12 @@
 //! Defines structs used in specifying output files.
 
+use crate::ecs::resources::Dimensionality;
 use crate::math::*;
+use std::io::Write;
 
 /// Represents a specific entry in the output file.
-#[derive(Serialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct OutputEntry {
     /// The time step this entry represents.
     pub step: u128,
@@ -13,20 +16,584 @@ pub struct OutputEntry {
 }
 
 /// Represents an entity, as defined in the output file.
-#[derive(Serialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct OutputEntity {
     /// The current acceleration of this entity.
-    pub acceleration: Vector,
+    pub acceleration: OutputVector,
 
     /// The charge of the entity.
     pub charge: f64,
 
+    /// The unique particle ID of the entity.
+    pub id: u64,
+
+    /// The number of steps the entity has existed, if lineage output is
+    /// enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lifetime: Option<u128>,
+
     /// The mass of the entity.
     pub mass: f64,
 
+    /// The particle IDs of the particles this entity was created from, if
+    /// lineage output is enabled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parents: Vec<u64>,
+
     /// The current position of this entity.
-    pub position: Vector,
+    pub position: OutputVector,
 
     /// The current velocity of this entity.
-    pub velocity: Vector
+    pub velocity: OutputVector
+}
+
+/// Represents a vector, as defined in the output file.
+///
+/// In two dimensions the (always zero) z component is omitted, so vectors are
+/// written as `[x, y]` rather than `[x, y, z]`. Either form may be read back.
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum OutputVector {
+    /// A vector in the plane `z = 0`, written without its z component.
+    Planar(f64, f64),
+
+    /// A vector written with all three of its components.
+    Spatial(Vector)
+}
+
+impl OutputVector {
+    /// Creates a new output vector from the specified vector, omitting its z
+    /// component in two dimensions.
+    pub fn new(v: Vector, dimensionality: Dimensionality) -> Self {
+        match dimensionality {
+            Dimensionality::Two => OutputVector::Planar(v.0, v.1),
+            Dimensionality::Three => OutputVector::Spatial(v)
+        }
+    }
+}
+
+/// Implements `std::convert::From<Vector>` for `OutputVector`.
+impl std::convert::From<Vector> for OutputVector {
+    fn from(v: Vector) -> Self { OutputVector::Spatial(v) }
+}
+
+/// Implements `std::convert::From<OutputVector>` for `Vector`.
+impl std::convert::From<OutputVector> for Vector {
+    fn from(v: OutputVector) -> Self {
+        match v {
+            OutputVector::Planar(x, y) => Vector(x, y, 0.0),
+            OutputVector::Spatial(v) => v
+        }
+    }
+}
+
+
+/// Represents a specific entry in the two-point correlation file.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CorrelationEntry {
+    /// The bins of the correlation function, ordered from the smallest
+    /// separation upward.
+    pub bins: Vec<CorrelationBin>,
+
+    /// The time step this entry represents.
+    pub step: u128
+}
+
+/// Represents a single separation bin of a two-point correlation function.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CorrelationBin {
+    /// The estimated correlation (excess probability of finding a pair over a
+    /// uniform distribution) within the bin.
+    pub correlation: f64,
+
+    /// The number of pairs whose separation falls within the bin.
+    pub pairs: usize,
+
+    /// The upper edge of the bin.
+    pub separation: f64
+}
+
+
+/// Represents a specific entry in the nearest-neighbor distance file.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NeighborsEntry {
+    /// The bins of the histogram, ordered from the smallest distance upward.
+    pub bins: Vec<NeighborsBin>,
+
+    /// The time step this entry represents.
+    pub step: u128
+}
+
+/// Represents a single bin of a nearest-neighbor distance histogram.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NeighborsBin {
+    /// The number of entities whose nearest-neighbor distance falls within the
+    /// bin.
+    pub count: usize,
+
+    /// The upper edge of the bin.
+    pub distance: f64
+}
+
+
+/// Represents a specific entry in the radial density profile file.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileEntry {
+    /// The radial shells of the profile, ordered from the center outward.
+    pub shells: Vec<ProfileShell>,
+
+    /// The time step this entry represents.
+    pub step: u128
+}
+
+/// Represents a single radial shell of a density profile.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileShell {
+    /// The mean density within the shell.
+    pub density: f64,
+
+    /// The total mass within the outer radius of the shell.
+    pub enclosed_mass: f64,
+
+    /// The outer radius of the shell, measured from the center of mass.
+    pub radius: f64
+}
+
+
+/// Represents a specific entry in the summary file.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SummaryEntry {
+    /// The total angular momentum about the center of mass, measured in the
+    /// center of mass frame.
+    pub angular_momentum_about_center_of_mass: Vector,
+
+    /// The total angular momentum about the origin.
+    pub angular_momentum_about_origin: Vector,
+
+    /// The ratio of the intermediate to the major axis of the mass
+    /// distribution.
+    pub intermediate_axis_ratio: f64,
+
+    /// The ratio of the minor to the major axis of the mass distribution.
+    pub minor_axis_ratio: f64,
+
+    /// The principal moments of inertia about the center of mass, in
+    /// ascending order.
+    pub principal_moments: [f64; 3],
+
+    /// The time step this entry represents.
+    pub step: u128
+}
+
+
+/// Represents an entry which is associated with a particular time step.
+pub trait Stepped {
+    /// Returns the time step this entry represents.
+    fn step(&self) -> u128;
+}
+
+/// Implements `Stepped` for `CorrelationEntry`.
+impl Stepped for CorrelationEntry {
+    fn step(&self) -> u128 { self.step }
+}
+
+/// Implements `Stepped` for `NeighborsEntry`.
+impl Stepped for NeighborsEntry {
+    fn step(&self) -> u128 { self.step }
+}
+
+/// Implements `Stepped` for `OutputEntry`.
+impl Stepped for OutputEntry {
+    fn step(&self) -> u128 { self.step }
+}
+
+/// Implements `Stepped` for `ProfileEntry`.
+impl Stepped for ProfileEntry {
+    fn step(&self) -> u128 { self.step }
+}
+
+/// Implements `Stepped` for `SummaryEntry`.
+impl Stepped for SummaryEntry {
+    fn step(&self) -> u128 { self.step }
+}
+
+
+/// The number of bytes occupied by each record of an index file.
+pub const INDEX_RECORD_SIZE: usize = 16;
+
+
+/// Reads the entry representing the specified step from the specified output
+/// file, using the index file written alongside it to seek directly to the
+/// entry. Returns `None` if the index contains no such step.
+///
+/// An index file is a sequence of records, one per entry, each of which
+/// contains the step and the byte offset of the entry within the output file
+/// as little-endian `u64` values. Since entries are usually written for every
+/// step, the record for a step is first looked for at its expected position
+/// before falling back to a binary search.
+pub fn seek_to_step(path: &str, index_path: &str, step: u128) -> std::io::Result<Option<OutputEntry>> {
+    use std::convert::TryInto;
+    use std::io::{Read, Seek};
+    let index = std::fs::read(index_path)?;
+    let records: Vec<(u128, u64)> = index.chunks_exact(INDEX_RECORD_SIZE)
+        .map(|r| (
+            u128::from(u64::from_le_bytes(r[0..8].try_into().unwrap())),
+            u64::from_le_bytes(r[8..16].try_into().unwrap())
+        ))
+        .collect();
+    let first_step = match records.first() {
+        Some((s, _)) => *s,
+        None => return Ok(None)
+    };
+    let position = match step.checked_sub(first_step).map(|i| i as usize) {
+        Some(i) if i < records.len() && records[i].0 == step => i,
+        _ => match records.binary_search_by_key(&step, |(s, _)| *s) {
+            Ok(i) => i,
+            Err(_) => return Ok(None)
+        }
+    };
+    let mut file = std::fs::File::open(path)?;
+    let start = records[position].1;
+    let end = match records.get(position + 1) {
+        Some((_, offset)) => *offset,
+        None => file.metadata()?.len()
+    };
+    file.seek(std::io::SeekFrom::Start(start))?;
+    let mut contents = String::new();
+    file.take(end - start).read_to_string(&mut contents)?;
+    serde_yaml::from_str(&contents).map(Some).map_err(|e| std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Invalid entry for step {} in \"{}\" - {}", step, path, e)
+    ))
+}
+
+
+/// Reads all of the entries contained within the specified output file.
+///
+/// Parsing is strict: an entry containing an unknown or mistyped field results
+/// in an error naming the offending entry and field.
+pub fn read_entries(path: &str) -> std::io::Result<Vec<OutputEntry>> {
+    use serde::Deserialize;
+    let contents = std::fs::read_to_string(path)?;
+    serde_yaml::Deserializer::from_str(&contents)
+        .enumerate()
+        .map(|(i, document)| {
+            OutputEntry::deserialize(document).map_err(|e| std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid entry {} in \"{}\" - {}", i, path, e)
+            ))
+        })
+        .collect()
+}
+
+
+/// The maximum number of entries which may be queued for writing before
+/// writers block the simulation.
+pub const OUTPUT_QUEUE_CAPACITY: usize = 16;
+
+
+/// Represents a writer which serializes entries of type `T` to a file on a
+/// dedicated background thread.
+///
+/// The file is opened and the thread started lazily on the first write. Entries
+/// are handed to the thread over a bounded channel, so that the simulation only
+/// blocks once `OUTPUT_QUEUE_CAPACITY` entries are waiting to be written. All
+/// queued entries are written when the writer is finished or dropped, which
+/// includes the case where the stack is unwound by a panic part-way through a
+/// simulation.
+///
+/// If an index path is specified, the step and byte offset of each entry are
+/// also appended to that file (see `seek_to_step`).
+#[derive(Debug)]
+pub struct OutputWriter<T> {
+    /// The path to the index file, if any.
+    index_path: Option<String>,
+
+    /// The sending half of the channel to the background thread.
+    sender: Option<std::sync::mpsc::SyncSender<T>>,
+
+    /// The handle of the background thread.
+    thread: Option<std::thread::JoinHandle<std::io::Result<()>>>
+}
+
+impl<T: serde::Serialize + Send + Stepped + 'static> OutputWriter<T> {
+    /// Creates a new writer which also writes an index of its entries to the
+    /// specified path.
+    pub fn with_index(index_path: &str) -> Self {
+        OutputWriter {
+            index_path: Some(index_path.to_string()),
+            sender: None,
+            thread: None
+        }
+    }
+
+    /// Waits for all queued entries to be written and flushed to disk,
+    /// stopping the background thread.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        self.sender = None;
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or_else(|_| {
+                Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "output thread panicked"))
+            }),
+            None => Ok(())
+        }
+    }
+
+    /// Queues the specified entry to be serialized and appended to the file at
+    /// the specified path, opening the file if necessary.
+    pub fn write_entry(&mut self, path: &str, entry: T) -> std::io::Result<()> {
+        if self.sender.is_none() {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            let mut offset = file.metadata()?.len();
+            let mut index_writer = match &self.index_path {
+                Some(index_path) => Some(std::io::BufWriter::new(
+                    std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(index_path)?
+                )),
+                None => None
+            };
+            let (sender, receiver) = std::sync::mpsc::sync_channel::<T>(OUTPUT_QUEUE_CAPACITY);
+            let path = path.to_string();
+            self.thread = Some(std::thread::spawn(move || {
+                let mut writer = std::io::BufWriter::new(file);
+                for entry in receiver {
+                    let yaml_string = format!(
+                        "{}\n",
+                        serde_yaml::to_string(&entry).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+                    );
+                    if let Err(e) = writer.write_all(yaml_string.as_bytes()) {
+                        error!("Unable to write to \"{}\" - {}", path, e);
+                        return Err(e);
+                    }
+                    if let Some(index_writer) = &mut index_writer {
+                        let mut record = (entry.step() as u64).to_le_bytes().to_vec();
+                        record.extend_from_slice(&offset.to_le_bytes());
+                        if let Err(e) = index_writer.write_all(&record) {
+                            error!("Unable to write to the index of \"{}\" - {}", path, e);
+                            return Err(e);
+                        }
+                    }
+                    offset += yaml_string.len() as u64;
+                }
+                writer.flush()?;
+                match &mut index_writer {
+                    Some(index_writer) => index_writer.flush(),
+                    None => Ok(())
+                }
+            }));
+            self.sender = Some(sender);
+        }
+        match &self.sender {
+            Some(sender) => sender.send(entry).map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::BrokenPipe, "output thread has stopped")
+            }),
+            None => Ok(())
+        }
+    }
+}
+
+/// Implements `std::default::Default` for `OutputWriter`.
+impl<T> std::default::Default for OutputWriter<T> {
+    fn default() -> Self {
+        OutputWriter {
+            index_path: None,
+            sender: None,
+            thread: None
+        }
+    }
+}
+
+/// Implements `std::ops::Drop` for `OutputWriter`.
+impl<T> std::ops::Drop for OutputWriter<T> {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            match thread.join() {
+                Ok(Err(e)) => error!("Unable to flush output file - {}", e),
+                Err(_) => error!("Unable to flush output file - output thread panicked"),
+                _ => {}
+            }
+        }
+    }
+}
+
+
+/// Represents a background writer over the two-point correlation file.
+pub type CorrelationWriter = OutputWriter<CorrelationEntry>;
+
+
+/// Represents a background writer over the nearest-neighbor distance file.
+pub type NeighborsWriter = OutputWriter<NeighborsEntry>;
+
+
+/// Represents a background writer over the radial density profile file.
+pub type ProfileWriter = OutputWriter<ProfileEntry>;
+
+
+/// Represents a background writer over the simulation summary file.
+pub type SummaryWriter = OutputWriter<SummaryEntry>;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a path in the temporary directory unique to this process and the
+    /// specified name, removing any file already there.
+    fn temporary_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("grav-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn queued_entries_are_written_when_a_panic_unwinds() {
+        let path = temporary_path("panic.yaml");
+        let result = std::panic::catch_unwind(|| {
+            let mut writer = OutputWriter::<OutputEntry>::default();
+            for step in 1..=5 {
+                writer.write_entry(&path, OutputEntry { step, entities: Vec::new() }).unwrap();
+            }
+            panic!("a system failed part-way through the simulation");
+        });
+        assert!(result.is_err());
+        let steps: Vec<u128> = read_entries(&path).unwrap().iter().map(|e| e.step).collect();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(steps, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn misspelled_output_fields_are_rejected_by_name() {
+        let path = temporary_path("typo.yaml");
+        std::fs::write(&path, "step: 1\nentities: []\n---\nstep: 2\nentites: []\n").unwrap();
+        let result = read_entries(&path);
+        std::fs::remove_file(&path).unwrap();
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("entry 1") && message.contains("entites"), "{}", message);
+    }
+
+    #[test]
+    fn output_entries_round_trip_through_yaml() {
+        let entry = OutputEntry {
+            entities: vec![
+                OutputEntity {
+                    acceleration: Vector(0.5, -0.25, 0.0).into(),
+                    charge: -1.0,
+                    id: 7,
+                    lifetime: Some(12),
+                    mass: 3.5,
+                    parents: vec![2, 5],
+                    position: Vector(1.0, 2.0, 3.0).into(),
+                    velocity: Vector(-4.0, 0.125, 6.0).into()
+                }
+            ],
+            step: 42
+        };
+        let yaml = serde_yaml::to_string(&entry).unwrap();
+        assert_eq!(serde_yaml::from_str::<OutputEntry>(&yaml).unwrap(), entry);
+    }
+
+    #[test]
+    fn two_dimensional_output_omits_z_and_round_trips() {
+        let path = temporary_path("planar.yaml");
+        let index_path = temporary_path("planar.idx");
+        let entity = | id: u64, dimensionality | OutputEntity {
+            acceleration: OutputVector::new(Vector(0.5, -0.25, 0.0), dimensionality),
+            charge: 0.0,
+            id,
+            lifetime: None,
+            mass: 1.0,
+            parents: Vec::new(),
+            position: OutputVector::new(Vector(1.0, 2.0, 0.0), dimensionality),
+            velocity: OutputVector::new(Vector(-4.0, 0.125, 0.0), dimensionality)
+        };
+        let planar = OutputEntry { entities: vec![entity(1, Dimensionality::Two)], step: 1 };
+        let spatial = OutputEntry { entities: vec![entity(2, Dimensionality::Three)], step: 2 };
+        let mut writer = OutputWriter::<OutputEntry>::with_index(&index_path);
+        writer.write_entry(&path, planar.clone()).unwrap();
+        writer.write_entry(&path, spatial.clone()).unwrap();
+        writer.finish().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let entries = read_entries(&path).unwrap();
+        let seeked = seek_to_step(&path, &index_path, 1).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&index_path).unwrap();
+        let written: serde_yaml::Value = serde_yaml::from_str(contents.split("\n---").next().unwrap()).unwrap();
+        for field in &["acceleration", "position", "velocity"] {
+            assert_eq!(written["entities"][0][*field].as_sequence().unwrap().len(), 2, "{}", contents);
+        }
+        assert_eq!(entries, vec![planar, spatial]);
+        let position: Vector = seeked.unwrap().entities[0].position.into();
+        assert_eq!(position, Vector(1.0, 2.0, 0.0));
+        let velocity: Vector = entries[1].entities[0].velocity.into();
+        assert_eq!(velocity, Vector(-4.0, 0.125, 0.0));
+    }
+
+    #[test]
+    fn seeking_to_a_middle_step_returns_exactly_that_entry() {
+        let path = temporary_path("seek.yaml");
+        let index_path = temporary_path("seek.idx");
+        let entry = | step: u128 | OutputEntry {
+            entities: (0..step as u64 % 4).map(|id| OutputEntity {
+                acceleration: Vector::zero().into(),
+                charge: 0.0,
+                id,
+                lifetime: None,
+                mass: step as f64,
+                parents: Vec::new(),
+                position: Vector::splat(id as f64).into(),
+                velocity: Vector::zero().into()
+            }).collect(),
+            step
+        };
+        let mut writer = OutputWriter::<OutputEntry>::with_index(&index_path);
+        for step in 1..=20 {
+            writer.write_entry(&path, entry(step)).unwrap();
+        }
+        writer.finish().unwrap();
+        let middle = seek_to_step(&path, &index_path, 11).unwrap();
+        let missing = seek_to_step(&path, &index_path, 21).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&index_path).unwrap();
+        assert_eq!(middle, Some(entry(11)));
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn every_entry_reaches_disk_when_the_queue_fills() {
+        let path = temporary_path("backpressure.yaml");
+        let entities = | step: u128 | (0..100).map(|id| OutputEntity {
+            acceleration: Vector::zero().into(),
+            charge: 0.0,
+            id,
+            lifetime: None,
+            mass: step as f64,
+            parents: Vec::new(),
+            position: Vector::splat(id as f64).into(),
+            velocity: Vector::zero().into()
+        }).collect();
+        let mut writer = OutputWriter::<OutputEntry>::default();
+        let steps = (4 * OUTPUT_QUEUE_CAPACITY) as u128;
+        for step in 1..=steps {
+            writer.write_entry(&path, OutputEntry { entities: entities(step), step }).unwrap();
+        }
+        writer.finish().unwrap();
+        let entries = read_entries(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.iter().map(|e| e.step).collect::<Vec<u128>>(), (1..=steps).collect::<Vec<u128>>());
+        assert!(entries.iter().all(|e| e.entities.len() == 100 && e.entities[0].mass == e.step as f64));
+    }
 }
@@ -0,0 +1,178 @@
+//! Contains functions pertaining to rendering simulation frames as images.
+
+use crate::math::*;
+
+/// Represents a single rendered RGB frame.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// The height of the frame, in pixels.
+    pub height: u32,
+
+    /// The raw RGB pixel data of the frame, in row-major order.
+    pub pixels: Vec<u8>,
+
+    /// The width of the frame, in pixels.
+    pub width: u32
+}
+
+impl Frame {
+    /// Creates a new black frame of the specified dimensions.
+    pub fn new(width: u32, height: u32) -> Frame {
+        Frame {
+            height,
+            pixels: vec![0; (width * height * 3) as usize],
+            width
+        }
+    }
+
+    /// Sets the color of the pixel at the specified coordinates, ignoring
+    /// coordinates outside of the frame.
+    pub fn set(&mut self, x: i64, y: i64, color: [u8; 3]) {
+        if x < 0 || y < 0 || x >= i64::from(self.width) || y >= i64::from(self.height) {
+            return;
+        }
+        let i = ((y as usize * self.width as usize) + x as usize) * 3;
+        self.pixels[i..(i + 3)].copy_from_slice(&color);
+    }
+}
+
+
+/// Returns the color an entity with the specified charge is drawn with.
+///
+/// Positively-charged entities are drawn red, negatively-charged entities are
+/// drawn blue, and neutral entities are drawn white.
+pub fn charge_color(charge: f64) -> [u8; 3] {
+    if charge > 0.0 {
+        [255, 64, 64]
+    } else if charge < 0.0 {
+        [64, 64, 255]
+    } else {
+        [255, 255, 255]
+    }
+}
+
+
+/// Projects the specified position onto pixel coordinates of a frame of the
+/// specified resolution.
+///
+/// The projection is orthographic, looking down the z-axis at the square
+/// region spanning `[-extent, extent]` along the x and y axes.
+pub fn project(position: Vector, extent: f64, resolution: u32) -> (i64, i64) {
+    let scale = f64::from(resolution) / (2.0 * extent);
+    (
+        ((position.0 + extent) * scale).floor() as i64,
+        ((extent - position.1) * scale).floor() as i64
+    )
+}
+
+
+/// Rasterizes the specified set of `(position, radius, color)` particles onto a
+/// new square frame of the specified resolution.
+///
+/// Each particle is drawn as a filled dot whose radius is scaled from its
+/// physical radius, with a minimum of a single pixel.
+pub fn rasterize(particles: &[(Vector, f64, [u8; 3])], extent: f64, resolution: u32) -> Frame {
+    let mut frame = Frame::new(resolution, resolution);
+    let scale = f64::from(resolution) / (2.0 * extent);
+    for (position, radius, color) in particles {
+        let (cx, cy) = project(*position, extent, resolution);
+        let r = (radius * scale).floor() as i64;
+        for y in (cy - r)..=(cy + r) {
+            for x in (cx - r)..=(cx + r) {
+                if (x - cx) * (x - cx) + (y - cy) * (y - cy) <= r * r {
+                    frame.set(x, y, *color);
+                }
+            }
+        }
+    }
+    frame
+}
+
+
+/// Encodes the specified frame as a PNG image.
+///
+/// Image data is written using uncompressed ("stored") deflate blocks, which
+/// keeps the encoder dependency-free at the cost of larger files.
+pub fn encode_png(frame: &Frame) -> Vec<u8> {
+    let mut raw: Vec<u8> = Vec::with_capacity(((frame.width * 3 + 1) * frame.height) as usize);
+    for row in frame.pixels.chunks((frame.width * 3) as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    let mut zlib: Vec<u8> = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        zlib.push(if blocks.peek().is_none() { 1 } else { 0 });
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+    let mut ihdr: Vec<u8> = Vec::new();
+    ihdr.extend_from_slice(&frame.width.to_be_bytes());
+    ihdr.extend_from_slice(&frame.height.to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+    let mut png: Vec<u8> = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+    write_png_chunk(&mut png, b"IHDR", &ihdr);
+    write_png_chunk(&mut png, b"IDAT", &zlib);
+    write_png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+
+/// Computes the Adler-32 checksum of the specified data.
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+
+/// Computes the CRC-32 checksum of the specified data.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffff_ffff;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+
+/// Appends a PNG chunk of the specified type and data to the specified buffer.
+fn write_png_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let mut body: Vec<u8> = chunk_type.to_vec();
+    body.extend_from_slice(data);
+    png.extend_from_slice(&body);
+    png.extend_from_slice(&crc32(&body).to_be_bytes());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_single_particle_is_plotted_at_its_projected_pixel() {
+        let color = charge_color(1.0);
+        let frame = rasterize(&[(Vector(5.0, 5.0, 3.0), 0.0, color)], 10.0, 100);
+        let lit: Vec<usize> = frame.pixels.chunks(3)
+            .enumerate()
+            .filter(|(_, pixel)| pixel.iter().any(|c| *c != 0))
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(lit, vec![(25 * 100) + 75]);
+        assert_eq!(frame.pixels[lit[0] * 3..(lit[0] * 3) + 3], color);
+        assert!(encode_png(&frame).starts_with(&[0x89, b'P', b'N', b'G']));
+    }
+}